@@ -0,0 +1,203 @@
+use console::style;
+use peeko::manifest::{ImageConfig, ImageManifest, Platform, PlatformParam};
+use peeko::reader::load_image_config;
+use peeko::registry::{RegistryError, RemoteImage};
+use tabled::{Table, Tabled};
+
+use crate::commands;
+use crate::config;
+use crate::error::{PeekoCliError, Result};
+use crate::utils;
+
+/// Longest `created_by` command shown in the layers table.
+const MAX_COMMAND_LEN: usize = 80;
+
+#[derive(Tabled)]
+struct LayerInfo {
+    #[tabled(rename = "#")]
+    index: usize,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Created By")]
+    created_by: String,
+}
+
+pub async fn execute(image: &str, platform: Option<&str>, remote: bool) -> Result<()> {
+    if remote {
+        inspect_remote(image, platform).await
+    } else {
+        inspect_local(image, platform).await
+    }
+}
+
+/// Fetches the manifest and config of `image` from its registry, leaving the
+/// layers where they are.
+async fn inspect_remote(image: &str, platform: Option<&str>) -> Result<()> {
+    let reference = commands::parse_reference(image)?;
+    let registry_url = reference.registry_url();
+    let image_ref = commands::display_reference(&reference.repository, reference.reference());
+    let platform = platform.map(commands::parse_platform).transpose()?;
+    utils::print_header(&format!("Inspecting {image_ref} on {registry_url}"));
+
+    let client = commands::registry_client(&registry_url);
+    match client.inspect_image(&reference, platform.as_ref()).await {
+        Ok(RemoteImage {
+            digest,
+            platforms,
+            platform,
+            manifest,
+            config,
+        }) => {
+            print_image(Some(&digest), &platform, &platforms, &manifest, &config);
+            Ok(())
+        }
+        Err(RegistryError::ManifestNotFound) => {
+            utils::print_error(&format!("Image not found for {image_ref}"));
+            Err(PeekoCliError::RuntimeError("".to_string()))
+        }
+        Err(RegistryError::PlatformNotFound(platform)) => {
+            utils::print_error(&format!("{image_ref} is not available for {platform}"));
+            utils::print_info("Use '--platform' to pick another platform.");
+            Err(PeekoCliError::RuntimeError("".to_string()))
+        }
+        Err(err) => {
+            utils::print_error(&format!("Failed to inspect {image_ref}"));
+            Err(err.into())
+        }
+    }
+}
+
+/// Reads the manifest and config of a downloaded image.
+async fn inspect_local(image: &str, platform: Option<&str>) -> Result<()> {
+    let image = commands::find_local_image(image, platform)?;
+    utils::print_header(&format!("Inspecting {image}"));
+
+    let platform_param = match &image.platform {
+        Some(platform) => platform.into(),
+        None => PlatformParam::default(),
+    };
+    let (manifest, image_config) = load_image_config(&image.path, &platform_param).await?;
+    // platforms pulled with --all-platforms share the image directory
+    let platforms: Vec<Platform> = peeko::fs::collect_stored_images(config::get_peeko_dir())?
+        .into_iter()
+        .filter(|stored| stored.path == image.path)
+        .filter_map(|stored| stored.platform)
+        .collect();
+    let platform = image.platform.clone().unwrap_or_else(|| Platform {
+        architecture: image_config.architecture.clone(),
+        os: image_config.os.clone(),
+        os_version: None,
+        os_features: None,
+        variant: None,
+    });
+    let platforms = if platforms.len() > 1 {
+        platforms
+    } else {
+        Vec::new()
+    };
+    print_image(
+        image.digest.as_deref(),
+        &platform,
+        &platforms,
+        &manifest,
+        &image_config,
+    );
+    Ok(())
+}
+
+fn print_image(
+    digest: Option<&str>,
+    platform: &Platform,
+    platforms: &[Platform],
+    manifest: &ImageManifest,
+    config: &ImageConfig,
+) {
+    let print_field = |name: &str, value: &str| {
+        if !value.is_empty() {
+            println!("{:<12} {}", style(format!("{name}:")).bold(), value);
+        }
+    };
+    let join = |values: &Option<Vec<String>>| values.as_deref().unwrap_or_default().join(" ");
+
+    print_field("Digest", digest.unwrap_or_default());
+    print_field("Platform", &platform.to_string());
+    let platforms: Vec<String> = platforms.iter().map(|p| p.to_string()).collect();
+    print_field("Platforms", &platforms.join(", "));
+    print_field("Created", &config.created);
+    print_field(
+        "Layers",
+        &format!(
+            "{} ({} compressed)",
+            manifest.layers.len(),
+            utils::format_size(manifest.layers_size())
+        ),
+    );
+
+    let runtime = &config.config;
+    print_field("User", runtime.user.as_deref().unwrap_or_default());
+    print_field("Entrypoint", &join(&runtime.entrypoint));
+    print_field("Cmd", &join(&runtime.cmd));
+    print_field(
+        "WorkingDir",
+        runtime.working_dir.as_deref().unwrap_or_default(),
+    );
+    if let Some(ports) = &runtime.exposed_ports {
+        let mut ports: Vec<&str> = ports.keys().map(String::as_str).collect();
+        ports.sort();
+        print_field("Ports", &ports.join(", "));
+    }
+    if let Some(env) = &runtime.env
+        && !env.is_empty()
+    {
+        println!("{}", style("Env:").bold());
+        for var in env {
+            println!("  {var}");
+        }
+    }
+    if let Some(labels) = &runtime.labels
+        && !labels.is_empty()
+    {
+        let mut labels: Vec<_> = labels.iter().collect();
+        labels.sort();
+        println!("{}", style("Labels:").bold());
+        for (key, value) in labels {
+            println!("  {key}={value}");
+        }
+    }
+
+    println!();
+    println!("{}", Table::new(layer_infos(manifest, config)));
+}
+
+/// Pairs each layer with the history entry that created it. Empty-layer
+/// entries have no layer, and images whose history does not line up with
+/// their layers show no commands.
+fn layer_infos(manifest: &ImageManifest, config: &ImageConfig) -> Vec<LayerInfo> {
+    let history: Vec<&str> = config
+        .history
+        .iter()
+        .filter(|entry| !entry.empty_layer)
+        .map(|entry| entry.created_by.as_str())
+        .collect();
+    let aligned = history.len() == manifest.layers.len();
+    manifest
+        .layers
+        .iter()
+        .enumerate()
+        .map(|(index, layer)| {
+            let created_by = if aligned { history[index] } else { "" };
+            LayerInfo {
+                index: index + 1,
+                size: utils::format_size(layer.size),
+                created_by: truncate(created_by.trim(), MAX_COMMAND_LEN),
+            }
+        })
+        .collect()
+}
+
+fn truncate(text: &str, max_len: usize) -> String {
+    match text.char_indices().nth(max_len) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
@@ -1,4 +1,5 @@
 use std::fs;
+use std::path::PathBuf;
 use tabled::{Table, Tabled};
 
 use crate::config;
@@ -11,35 +12,40 @@ struct ImageInfo {
     name: String,
     #[tabled(rename = "Tag")]
     tag: String,
+    #[tabled(rename = "Platform")]
+    platform: String,
+    #[tabled(rename = "Digest")]
+    digest: String,
     #[tabled(rename = "Size")]
     size: String,
 }
 
 pub async fn execute() -> Result<()> {
     utils::print_header("Downloaded Images");
-    let peeko_dir = config::get_peeko_dir();
-    let image_directories = peeko::fs::collect_image_directories(&peeko_dir)?;
+    let stored_images = peeko::fs::collect_stored_images(config::get_peeko_dir())?;
 
-    let images: Vec<ImageInfo> = image_directories
-        .iter()
-        .filter_map(|dir| {
-            let size = calculate_directory_size(dir).unwrap_or(0);
-            let relative_path = dir
-                .strip_prefix(&peeko_dir)
-                .expect("Must be a subdirectory of the peeko directory")
-                .to_string_lossy()
-                .to_string();
-            if let Some((image, tag)) = relative_path.rsplit_once('/') {
-                Some(ImageInfo {
-                    name: image.to_owned(),
-                    tag: tag.to_owned(),
-                    size: utils::format_size(size),
-                })
-            } else {
-                None
-            }
-        })
-        .collect();
+    // platforms of a multi-platform layout share one directory and one row
+    let mut images: Vec<(ImageInfo, PathBuf)> = Vec::new();
+    for image in stored_images {
+        let platform = image
+            .platform
+            .map(|platform| platform.to_string())
+            .unwrap_or_default();
+        match images.iter_mut().find(|(_, path)| *path == image.path) {
+            Some((info, _)) => info.platform = format!("{}, {platform}", info.platform),
+            None => images.push((
+                ImageInfo {
+                    name: image.name,
+                    tag: image.tag,
+                    platform,
+                    digest: image.digest.unwrap_or_default(),
+                    size: utils::format_size(calculate_directory_size(&image.path).unwrap_or(0)),
+                },
+                image.path,
+            )),
+        }
+    }
+    let images: Vec<ImageInfo> = images.into_iter().map(|(info, _)| info).collect();
 
     if images.is_empty() {
         utils::print_info("No downloaded images found.");
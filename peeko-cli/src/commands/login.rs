@@ -0,0 +1,51 @@
+use std::io::{self, Read};
+
+use inquire::{Password, PasswordDisplayMode, Text};
+use peeko::registry::{Credentials, RegistryClient, RegistryError};
+
+use crate::commands;
+use crate::error::{PeekoCliError, Result};
+use crate::utils;
+
+pub async fn execute(registry: &str, username: Option<String>, password_stdin: bool) -> Result<()> {
+    let username = match username {
+        Some(username) => username,
+        None => Text::new("Username:").prompt()?,
+    };
+    let password = if password_stdin {
+        let mut password = String::new();
+        io::stdin().read_to_string(&mut password)?;
+        password.trim_end_matches(['\r', '\n']).to_string()
+    } else {
+        Password::new("Password or token:")
+            .with_display_mode(PasswordDisplayMode::Hidden)
+            .without_confirmation()
+            .prompt()?
+    };
+    if username.is_empty() || password.is_empty() {
+        return Err(PeekoCliError::Input(
+            "Username and password are required".to_string(),
+        ));
+    }
+
+    let registry_url = commands::registry_url(registry);
+    let client = RegistryClient::with_credentials(&registry_url, &username, &password);
+    match client.login().await {
+        Ok(_) => {}
+        Err(RegistryError::TokenFetchFailed(401) | RegistryError::Unauthorized(_)) => {
+            return Err(PeekoCliError::RuntimeError(format!(
+                "Login to {registry} failed: invalid username or password"
+            )));
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    let store = commands::credential_store();
+    store
+        .store(&registry_url, &Credentials { username, password })
+        .map_err(|err| PeekoCliError::RuntimeError(err.to_string()))?;
+
+    utils::print_success(&format!("Login succeeded for {registry}"));
+    utils::print_info(&format!("Credentials saved to {}", store.path().display()));
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use crate::commands;
+use crate::error::{PeekoCliError, Result};
+use crate::utils;
+
+pub async fn execute(registry: &str) -> Result<()> {
+    let registry_url = commands::registry_url(registry);
+    let removed = commands::credential_store()
+        .remove(&registry_url)
+        .map_err(|err| PeekoCliError::RuntimeError(err.to_string()))?;
+
+    if removed {
+        utils::print_success(&format!("Removed credentials for {registry}"));
+    } else {
+        utils::print_warning(&format!("Not logged in to {registry}"));
+    }
+    Ok(())
+}
@@ -1,10 +1,10 @@
 use std::time::Duration;
 
 use indicatif::{ProgressBar, ProgressStyle};
-use peeko::reader::{build_image_reader, vfs::FileEntry};
+use peeko::reader::vfs::{FileEntry, FileMetadata};
 use tabled::{Table, Tabled, settings::Style};
 
-use crate::config;
+use crate::commands;
 use crate::error::{PeekoCliError, Result};
 use crate::utils;
 
@@ -18,80 +18,151 @@ struct FileInfo {
     name: String,
 }
 
-pub async fn execute(image_with_tag: &str, path: &str) -> Result<()> {
-    match image_with_tag.rsplit_once(':') {
-        Some((image, tag)) => {
-            let image_path = config::get_peeko_dir().join(format!("{image}/{tag}"));
-            // Check if image exists
-            if !std::path::Path::new(&image_path).exists() {
-                utils::print_warning(&format!("Image {image}:{tag} not found locally"));
-                utils::print_info("Use 'peeko pull' to download the image first.");
-                return Err(PeekoCliError::RuntimeError("".to_string()));
+/// Row of the `-l` listing.
+#[derive(Tabled)]
+struct LongFileInfo {
+    #[tabled(rename = "Mode")]
+    mode: String,
+    #[tabled(rename = "Owner")]
+    owner: String,
+    #[tabled(rename = "Group")]
+    group: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Modified")]
+    modified: String,
+    #[tabled(rename = "File")]
+    name: String,
+    #[tabled(rename = "Xattrs")]
+    xattrs: String,
+}
+
+impl LongFileInfo {
+    fn new(name: &str, entry: &FileEntry) -> Self {
+        let metadata = entry.metadata();
+        let (kind, size, name) = match entry {
+            FileEntry::File { size, .. } => ('-', size.to_string(), name.to_string()),
+            FileEntry::Directory { .. } => ('d', String::new(), name.to_string()),
+            FileEntry::Symlink { target, .. } => {
+                ('l', String::new(), format!("{name} -> {target}"))
+            }
+            FileEntry::Hardlink { target, size, .. } => {
+                ('-', size.to_string(), format!("{name} => /{target}"))
+            }
+            FileEntry::CharDevice { major, minor, .. } => {
+                ('c', format!("{major}, {minor}"), name.to_string())
+            }
+            FileEntry::BlockDevice { major, minor, .. } => {
+                ('b', format!("{major}, {minor}"), name.to_string())
             }
+            FileEntry::Fifo { .. } => ('p', String::new(), name.to_string()),
+            FileEntry::Sparse { size, .. } => ('-', size.to_string(), name.to_string()),
+        };
+        let owner = |name: &Option<String>, id: u64| name.clone().unwrap_or_else(|| id.to_string());
+        Self {
+            mode: format!("{kind}{}", metadata.permissions()),
+            owner: owner(&metadata.user, metadata.uid),
+            group: owner(&metadata.group, metadata.gid),
+            size,
+            modified: utils::format_timestamp(metadata.mtime),
+            name,
+            xattrs: xattr_names(metadata),
+        }
+    }
+}
 
-            // 创建一个无限 spinner
-            let pb = ProgressBar::new_spinner();
-            pb.set_style(
-                ProgressStyle::default_spinner()
-                    .template("{spinner:.green} {msg}")
-                    .unwrap()
-                    .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ "), // 这些字符会循环形成动画
-            );
-            pb.set_message("Loading image...");
-            pb.enable_steady_tick(Duration::from_millis(100));
+fn xattr_names(metadata: &FileMetadata) -> String {
+    metadata
+        .xattrs
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-            let reader = build_image_reader(&image_path).await?;
+pub async fn execute(
+    image_with_tag: &str,
+    platform: Option<&str>,
+    path: &str,
+    long: bool,
+    remote: bool,
+) -> Result<()> {
+    // 创建一个无限 spinner
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} {msg}")
+            .unwrap()
+            .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ "), // 这些字符会循环形成动画
+    );
+    pb.set_message("Loading image...");
+    pb.enable_steady_tick(Duration::from_millis(100));
 
-            let dir_tree = reader.get_dir_tree()?;
-            let target_node = dir_tree.find(path);
-            match target_node {
-                Some(node) => {
-                    let mut files: Vec<FileInfo> = Vec::new();
-                    for child in node.children.borrow().values() {
-                        let full_path_str = child.pwd(false);
-                        let entry = reader.get_file_metadata(&full_path_str);
-                        if let Some(entry) = entry {
-                            let file_info = match entry {
-                                FileEntry::File { size, .. } => FileInfo {
-                                    name: child.name.clone(),
-                                    size: utils::format_size(*size),
-                                    file_type: "file".to_string(),
-                                },
-                                FileEntry::Directory { .. } => FileInfo {
-                                    name: child.name.clone(),
-                                    size: "".to_string(),
-                                    file_type: "dir".to_string(),
-                                },
-                                FileEntry::Symlink { .. } => FileInfo {
-                                    name: child.name.clone(),
-                                    size: "".to_string(),
-                                    file_type: "symlink".to_string(),
-                                },
-                            };
-                            files.push(file_info);
-                        }
-                    }
+    let reader = if remote {
+        commands::open_remote_image(image_with_tag, platform).await?
+    } else {
+        let image = commands::find_local_image(image_with_tag, platform)?;
+        commands::open_image(&image).await?
+    };
 
-                    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let dir_tree = reader.get_dir_tree()?;
+    let target_node = dir_tree.find(path);
+    match target_node {
+        Some(node) => {
+            let mut entries: Vec<(String, &FileEntry)> = node
+                .children
+                .borrow()
+                .values()
+                .filter_map(|child| {
+                    let entry = reader.get_symlink_metadata(&child.pwd(false))?;
+                    Some((child.name.clone(), entry))
+                })
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
 
-                    let len = files.len();
-                    let mut table = Table::new(files);
-                    table.with(Style::blank());
+            let len = entries.len();
+            let mut table = if long {
+                Table::new(
+                    entries
+                        .iter()
+                        .map(|(name, entry)| LongFileInfo::new(name, entry)),
+                )
+            } else {
+                Table::new(entries.iter().map(|(name, entry)| file_info(name, entry)))
+            };
+            table.with(Style::blank());
 
-                    pb.finish_and_clear();
-                    println!("{table}");
-                    println!();
-                    utils::print_info(&format!("Showing {len} files"));
-                    Ok(())
-                }
-                None => {
-                    pb.finish_and_clear();
-                    Err(PeekoCliError::RuntimeError(format!(
-                        "Path {path} not found"
-                    )))
-                }
-            }
+            pb.finish_and_clear();
+            println!("{table}");
+            println!();
+            utils::print_info(&format!("Showing {len} files"));
+            Ok(())
+        }
+        None => {
+            pb.finish_and_clear();
+            Err(PeekoCliError::RuntimeError(format!(
+                "Path {path} not found"
+            )))
+        }
+    }
+}
+
+fn file_info(name: &str, entry: &FileEntry) -> FileInfo {
+    let (file_type, size) = match entry {
+        FileEntry::File { size, .. } => ("file", utils::format_size(*size)),
+        FileEntry::Directory { .. } => ("dir", String::new()),
+        FileEntry::Symlink { .. } => ("symlink", String::new()),
+        FileEntry::Hardlink { size, .. } => ("hardlink", utils::format_size(*size)),
+        FileEntry::CharDevice { major, minor, .. } => ("char device", format!("{major}, {minor}")),
+        FileEntry::BlockDevice { major, minor, .. } => {
+            ("block device", format!("{major}, {minor}"))
         }
-        None => Err(PeekoCliError::Input("Image tag is required".to_string())),
+        FileEntry::Fifo { .. } => ("fifo", String::new()),
+        FileEntry::Sparse { size, .. } => ("sparse", utils::format_size(*size)),
+    };
+    FileInfo {
+        file_type: file_type.to_string(),
+        size,
+        name: name.to_string(),
     }
 }
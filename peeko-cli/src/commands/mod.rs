@@ -1,6 +1,142 @@
+use peeko::fs::StoredImage;
+use peeko::manifest::{InvalidPlatform, PlatformParam};
+use peeko::reader::{
+    ImageReader, build_image_reader, build_platform_image_reader, build_remote_image_reader,
+};
+use peeko::reference::ImageReference;
+use peeko::registry::{CredentialResolver, CredentialStore, RegistryClient, RetryPolicy};
+
+use crate::config;
+use crate::error::{PeekoCliError, Result};
+use crate::utils;
+
 pub mod cat;
+pub mod inspect;
 pub mod list;
+pub mod login;
+pub mod logout;
 pub mod ls;
+pub mod prune;
 pub mod pull;
 pub mod remove;
+pub mod tags;
 pub mod tree;
+
+/// Returns the credential store backing `peeko login`.
+pub fn credential_store() -> CredentialStore {
+    CredentialStore::new(config::get_credentials_path())
+}
+
+/// Creates a client for `registry_url`, authenticated with credentials from
+/// `peeko login` or, failing that, the Docker/Podman config files.
+pub fn registry_client(registry_url: &str) -> RegistryClient {
+    let mut resolver = CredentialResolver::from_env();
+    resolver.prepend_file(config::get_credentials_path());
+    match resolver.resolve(registry_url) {
+        Ok(Some(credentials)) => RegistryClient::with_credentials(
+            registry_url,
+            &credentials.username,
+            &credentials.password,
+        ),
+        Ok(None) => RegistryClient::new(registry_url),
+        Err(err) => {
+            utils::print_warning(&format!("Ignoring registry credentials: {err}"));
+            RegistryClient::new(registry_url)
+        }
+    }
+}
+
+/// Turns a registry host given on the command line (`ghcr.io`, `docker.io`)
+/// into the URL of its API endpoint.
+pub fn registry_url(registry: &str) -> String {
+    peeko::reference::registry_url(registry)
+}
+
+/// Parses an image reference such as `nginx`, `ghcr.io/owner/app:tag` or
+/// `localhost:5000/app@sha256:<hex>`.
+pub fn parse_reference(image: &str) -> Result<ImageReference> {
+    image
+        .parse()
+        .map_err(|err: peeko::reference::ReferenceError| PeekoCliError::Input(err.to_string()))
+}
+
+/// Parses a `--platform` value such as `linux/arm64/v8`.
+pub fn parse_platform(platform: &str) -> Result<PlatformParam> {
+    platform
+        .parse()
+        .map_err(|err: InvalidPlatform| PeekoCliError::Input(err.to_string()))
+}
+
+/// Formats an image and its tag or digest as `image:tag` or `image@digest`.
+pub fn display_reference(image: &str, reference: &str) -> String {
+    if reference.contains(':') {
+        format!("{image}@{reference}")
+    } else {
+        format!("{image}:{reference}")
+    }
+}
+
+/// Finds the downloaded image named by a reference such as `nginx:latest` or
+/// `nginx@sha256:<hex>`, preferring the host platform when no platform is
+/// given.
+pub fn find_local_image(image_with_tag: &str, platform: Option<&str>) -> Result<StoredImage> {
+    let reference = parse_reference(image_with_tag)?;
+    let platform = platform.map(parse_platform).transpose()?;
+
+    let find = |repository: &str| {
+        peeko::fs::find_image(
+            config::get_peeko_dir(),
+            repository,
+            reference.reference(),
+            platform.as_ref(),
+        )
+    };
+    let mut found = find(&reference.repository)?;
+    // single-component names of other registries are stored without the
+    // `library/` namespace the reference gives them
+    if found.is_none()
+        && let Some(repository) = reference.repository.strip_prefix("library/")
+    {
+        found = find(repository)?;
+    }
+
+    match found {
+        Some(image) => Ok(image),
+        None => {
+            match &platform {
+                Some(platform) => utils::print_error(&format!(
+                    "Image {image_with_tag} for {platform} not found locally"
+                )),
+                None => utils::print_error(&format!("Image {image_with_tag} not found locally")),
+            }
+            utils::print_info("Use 'peeko pull' to download the image first.");
+            Err(PeekoCliError::RuntimeError("".to_string()))
+        }
+    }
+}
+
+/// Opens a reader for the platform of `image` returned by [`find_local_image`].
+pub async fn open_image(image: &StoredImage) -> Result<ImageReader> {
+    let reader = match &image.platform {
+        Some(platform) => build_platform_image_reader(&image.path, &platform.into()).await?,
+        None => build_image_reader(&image.path).await?,
+    };
+    Ok(reader)
+}
+
+/// Opens a reader for an image left in its registry, fetching files on
+/// demand instead of pulling the image first. The host platform is picked
+/// from multi-platform images unless `platform` is given.
+pub async fn open_remote_image(image: &str, platform: Option<&str>) -> Result<ImageReader> {
+    let reference = parse_reference(image)?;
+    let platform = platform.map(parse_platform).transpose()?;
+    let mut client = registry_client(&reference.registry_url());
+    // shares the layer indexes of pulled images
+    client.set_downloads_dir(config::get_peeko_dir());
+    client.set_concurrent_downloads(config::get_concurrent_downloads());
+    client.set_retry_policy(RetryPolicy {
+        max_attempts: config::get_max_retries() + 1,
+        ..Default::default()
+    });
+    Ok(build_remote_image_reader(client, &reference, platform.as_ref()).await?)
+}
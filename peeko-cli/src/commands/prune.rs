@@ -0,0 +1,62 @@
+use peeko::fs::{PruneFilter, PruneOptions, PruneReport};
+
+use crate::config;
+use crate::error::Result;
+use crate::utils;
+
+pub async fn execute(filter: PruneFilter, dry_run: bool) -> Result<()> {
+    let report = peeko::fs::prune(config::get_peeko_dir(), &PruneOptions { filter, dry_run })?;
+    print_report(&report, dry_run);
+    Ok(())
+}
+
+/// Lists what pruning with `filter` would remove, returning whether there is
+/// anything to remove.
+pub fn preview(filter: PruneFilter) -> Result<bool> {
+    let options = PruneOptions {
+        filter,
+        dry_run: true,
+    };
+    let report = peeko::fs::prune(config::get_peeko_dir(), &options)?;
+    print_report(&report, true);
+    Ok(!is_empty(&report))
+}
+
+fn is_empty(report: &PruneReport) -> bool {
+    report.images.is_empty()
+        && report.orphaned_blobs.is_empty()
+        && report.orphaned_indexes.is_empty()
+}
+
+fn print_report(report: &PruneReport, dry_run: bool) {
+    let (removed, reclaimed) = if dry_run {
+        ("Would remove", "Would reclaim")
+    } else {
+        ("Removed", "Reclaimed")
+    };
+    for image in &report.images {
+        utils::print_info(&format!("{removed} {image}"));
+    }
+    if !report.orphaned_blobs.is_empty() {
+        utils::print_info(&format!(
+            "{removed} {} unreferenced blob(s)",
+            report.orphaned_blobs.len()
+        ));
+    }
+
+    if !report.orphaned_indexes.is_empty() {
+        utils::print_info(&format!(
+            "{removed} {} unused layer index(es)",
+            report.orphaned_indexes.len()
+        ));
+    }
+
+    if is_empty(report) {
+        utils::print_info("Nothing to clean up.");
+    } else {
+        utils::print_success(&format!(
+            "{reclaimed} {}",
+            utils::format_size(report.reclaimed_bytes)
+        ));
+    }
+}
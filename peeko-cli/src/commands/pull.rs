@@ -1,103 +1,65 @@
 use console::style;
-use peeko::registry::client::{PlatformParam, RegistryClient, RegistryError};
+use peeko::manifest::PlatformParam;
+use peeko::registry::RetryPolicy;
+use peeko::registry::client::RegistryError;
 
+use crate::commands;
 use crate::config;
 use crate::error::{PeekoCliError, Result};
 use crate::utils;
 
-const DEFAULT_REGISTRY: &str = "https://registry-1.docker.io";
-
-pub async fn execute(image_url: &str) -> Result<()> {
-    let (registry_url, image, tag) = parse_image_url(image_url)?;
-    utils::print_header(&format!("Pulling {image}:{tag} from {registry_url}"));
+pub async fn execute(image_url: &str, platform: Option<&str>, all_platforms: bool) -> Result<()> {
+    let reference = commands::parse_reference(image_url)?;
+    let registry_url = reference.registry_url();
+    let image_ref = commands::display_reference(&reference.repository, reference.reference());
+    let platform = platform.map(commands::parse_platform).transpose()?;
+    let platform_name = match &platform {
+        _ if all_platforms => "all platforms".to_string(),
+        Some(platform) => platform.to_string(),
+        None => PlatformParam::host().to_string(),
+    };
+    utils::print_header(&format!(
+        "Pulling {image_ref} ({platform_name}) from {registry_url}"
+    ));
 
-    let mut client = RegistryClient::new(&registry_url).enable_progress();
+    let mut client = commands::registry_client(&registry_url).enable_progress();
     client.set_concurrent_downloads(config::get_concurrent_downloads());
     client.set_downloads_dir(config::get_peeko_dir());
+    client.set_retry_policy(RetryPolicy {
+        max_attempts: config::get_max_retries() + 1,
+        ..Default::default()
+    });
 
-    let platform = PlatformParam {
-        architecture: None,
-        os: None,
-        variant: None,
+    let result = if all_platforms {
+        client.download_all_platforms(&reference).await
+    } else {
+        client.download_image(&reference, platform.as_ref()).await
     };
+    match result {
+        Ok(image_path) => {
+            utils::print_success(&format!("Successfully pulled {image_ref}"));
+            if let Some(digest) = peeko::fs::read_resolved_digest(&image_path) {
+                utils::print_info(&format!("Digest: {}", style(digest).cyan()));
+            }
 
-    match client.download_image(&image, &tag, platform).await {
-        Ok(_) => {
-            utils::print_success(&format!("Successfully pulled {image}:{tag}"));
-
-            let image_path = format!("{image}/{tag}");
-            utils::print_info(&format!("Image saved to: {}", style(&image_path).cyan()));
+            utils::print_info(&format!(
+                "Image saved to: {}",
+                style(image_path.display()).cyan()
+            ));
             Ok(())
         }
         Err(RegistryError::ManifestNotFound) => {
-            utils::print_error(&format!("Image not found for {image}:{tag}"));
+            utils::print_error(&format!("Image not found for {image_ref}"));
+            Err(PeekoCliError::RuntimeError("".to_string()))
+        }
+        Err(RegistryError::PlatformNotFound(platform)) => {
+            utils::print_error(&format!("{image_ref} is not available for {platform}"));
+            utils::print_info("Use '--platform' to pick another platform.");
             Err(PeekoCliError::RuntimeError("".to_string()))
         }
         Err(err) => {
-            utils::print_error(&format!("Failed to pull {image}:{tag}"));
+            utils::print_error(&format!("Failed to pull {image_ref}"));
             Err(err.into())
         }
     }
 }
-
-fn parse_image_url(image_url: &str) -> Result<(String, String, String)> {
-    let (image_url, tag) = image_url
-        .rsplit_once(':')
-        .ok_or_else(|| PeekoCliError::Input("Image tag is required".to_string()))?;
-
-    match image_url.find('/') {
-        Some(index) => {
-            let registry = &image_url[..index];
-            match registry.find('.') {
-                Some(_) => {
-                    let image = &image_url[index + 1..];
-                    Ok((
-                        format!("https://{registry}"),
-                        image.to_string(),
-                        tag.to_string(),
-                    ))
-                }
-                None => Ok((
-                    DEFAULT_REGISTRY.to_string(),
-                    image_url.to_string(),
-                    tag.to_string(),
-                )),
-            }
-        }
-        None => {
-            let image = format!("library/{image_url}");
-            Ok((DEFAULT_REGISTRY.to_string(), image, tag.to_string()))
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_parse_image_url() {
-        // full image url
-        let (registry_url, image, tag) =
-            parse_image_url("registry-1.docker.io/library/nginx:latest").unwrap();
-        assert_eq!(registry_url, "https://registry-1.docker.io");
-        assert_eq!(image, "library/nginx");
-        assert_eq!(tag, "latest");
-
-        // official image
-        let (registry_url, image, tag) = parse_image_url("nginx:latest").unwrap();
-        assert_eq!(registry_url, DEFAULT_REGISTRY);
-        assert_eq!(image, "library/nginx");
-        assert_eq!(tag, "latest");
-
-        let (registry_url, image, tag) = parse_image_url("mcp/slack:latest").unwrap();
-        assert_eq!(registry_url, DEFAULT_REGISTRY);
-        assert_eq!(image, "mcp/slack");
-        assert_eq!(tag, "latest");
-
-        let (registry_url, image, tag) = parse_image_url("ghcr.io/owner/image:tag").unwrap();
-        assert_eq!(registry_url, "https://ghcr.io");
-        assert_eq!(image, "owner/image");
-        assert_eq!(tag, "tag");
-    }
-}
@@ -1,14 +1,52 @@
+use peeko::fs::StoredImage;
+
+use crate::commands;
 use crate::config;
 use crate::error::{PeekoCliError, Result};
 use crate::utils;
 
 pub async fn execute(image_with_tag: &str) -> Result<()> {
-    match image_with_tag.rsplit_once(':') {
-        Some((image, tag)) => {
-            peeko::fs::delete_image(config::get_peeko_dir(), image, tag)?;
-            utils::print_success(&format!("Successfully removed {image_with_tag}"));
-            Ok(())
-        }
-        None => Err(PeekoCliError::Input("Image tag is required".to_string())),
+    let image = commands::find_local_image(image_with_tag, None)?;
+    check_pulled_by(image_with_tag, &image)?;
+    peeko::fs::delete_image(config::get_peeko_dir(), &image.name, &image.tag)?;
+    utils::print_success(&format!("Successfully removed {image_with_tag}"));
+    Ok(())
+}
+
+/// Refuses to remove an image found through the digest a tag resolved to:
+/// removing it would delete the tag the user did not name.
+fn check_pulled_by(image_with_tag: &str, image: &StoredImage) -> Result<()> {
+    let reference = commands::parse_reference(image_with_tag)?;
+    match reference.digest {
+        Some(digest) if digest != image.tag => Err(PeekoCliError::Input(format!(
+            "{image_with_tag} was pulled as {}; remove it by that name",
+            image.reference()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_check_pulled_by() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let image = |tag: &str| StoredImage {
+            name: "library/nginx".to_string(),
+            tag: tag.to_string(),
+            digest: Some(digest.clone()),
+            platform: None,
+            path: PathBuf::from("library/nginx").join(tag),
+        };
+
+        let by_digest = format!("nginx@{digest}");
+        assert!(check_pulled_by(&by_digest, &image(&digest)).is_ok());
+        assert!(check_pulled_by("nginx:latest", &image("latest")).is_ok());
+        let err = check_pulled_by(&by_digest, &image("latest")).unwrap_err();
+        assert!(err.to_string().contains("library/nginx:latest"));
     }
 }
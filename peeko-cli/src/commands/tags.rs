@@ -0,0 +1,169 @@
+use console::style;
+use glob::Pattern;
+use peeko::reference::ImageReference;
+use peeko::registry::RegistryError;
+use semver::{Version, VersionReq};
+
+use crate::commands;
+use crate::error::{PeekoCliError, Result};
+use crate::utils;
+
+/// Selects which tags of a repository are shown.
+pub enum TagFilter {
+    /// Every tag, in the order the registry returns them.
+    None,
+    /// Tags matching a glob such as `1.2*` or `*-alpine`.
+    Glob(Pattern),
+    /// Tags that are versions matching a requirement such as `>=1.25, <2`,
+    /// sorted by version.
+    Semver(VersionReq),
+}
+
+impl TagFilter {
+    /// Builds the filter from the `--filter` and `--semver` options.
+    pub fn new(glob: Option<&str>, semver: Option<&str>) -> Result<Self> {
+        match (glob, semver) {
+            (Some(glob), _) => Pattern::new(glob)
+                .map(Self::Glob)
+                .map_err(|err| PeekoCliError::Input(format!("Invalid glob {glob}: {err}"))),
+            (None, Some(req)) => VersionReq::parse(req)
+                .map(Self::Semver)
+                .map_err(|err| PeekoCliError::Input(format!("Invalid version {req}: {err}"))),
+            (None, None) => Ok(Self::None),
+        }
+    }
+
+    /// Returns the tags passing the filter.
+    pub fn apply(&self, tags: Vec<String>) -> Vec<String> {
+        match self {
+            Self::None => tags,
+            Self::Glob(pattern) => tags.into_iter().filter(|t| pattern.matches(t)).collect(),
+            Self::Semver(req) => {
+                let mut versions: Vec<(Version, String)> = tags
+                    .into_iter()
+                    .filter_map(|tag| parse_version(&tag).map(|version| (version, tag)))
+                    .filter(|(version, _)| req.matches(version))
+                    .collect();
+                versions.sort();
+                versions.into_iter().map(|(_, tag)| tag).collect()
+            }
+        }
+    }
+}
+
+pub async fn execute(repository: &str, glob: Option<&str>, semver: Option<&str>) -> Result<()> {
+    let filter = TagFilter::new(glob, semver)?;
+    let reference = parse_repository(repository)?;
+    let name = &reference.repository;
+    utils::print_header(&format!("Tags of {name} on {}", reference.registry));
+
+    let client = commands::registry_client(&reference.registry_url());
+    let tags = match client.list_tags(name).await {
+        Ok(tags) => filter.apply(tags),
+        Err(RegistryError::RepositoryNotFound(_)) => {
+            utils::print_error(&format!("Repository {name} not found"));
+            return Err(PeekoCliError::RuntimeError("".to_string()));
+        }
+        Err(err) => {
+            utils::print_error(&format!("Failed to list tags of {name}"));
+            return Err(err.into());
+        }
+    };
+
+    if tags.is_empty() {
+        utils::print_info("No matching tags found.");
+    } else {
+        for tag in &tags {
+            println!("{}", style(tag).cyan());
+        }
+        println!();
+        utils::print_info(&format!("Found {} tag(s)", tags.len()));
+    }
+    Ok(())
+}
+
+/// Parses a repository name such as `nginx` or `ghcr.io/owner/app`,
+/// refusing references that name a tag or a digest.
+fn parse_repository(repository: &str) -> Result<ImageReference> {
+    let reference = commands::parse_reference(repository)?;
+    // a colon after the last slash starts a tag, as when parsing references
+    let last = repository.rsplit('/').next().unwrap_or(repository);
+    if reference.digest.is_some() || last.contains(':') {
+        return Err(PeekoCliError::Input(format!(
+            "{repository} names an image, expected a repository without tag or digest"
+        )));
+    }
+    Ok(reference)
+}
+
+/// Reads a tag as a version, accepting a `v` prefix and missing minor or
+/// patch numbers (`v1.25` is `1.25.0`). Suffixes such as `-alpine` are
+/// pre-release identifiers, which requirements only match when they name
+/// one themselves.
+fn parse_version(tag: &str) -> Option<Version> {
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    let split = tag.find(['-', '+']).unwrap_or(tag.len());
+    let (core, suffix) = tag.split_at(split);
+    let mut parts: Vec<&str> = core.split('.').collect();
+    if parts.len() > 3 || parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+    parts.resize(3, "0");
+    Version::parse(&format!("{}{suffix}", parts.join("."))).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let all = tags(&[
+            "latest",
+            "1.25-alpine",
+            "1.26.1",
+            "v1.25",
+            "1",
+            "1.24.0",
+            "stable",
+        ]);
+
+        let filter = TagFilter::new(None, None).unwrap();
+        assert_eq!(filter.apply(all.clone()), all);
+
+        let filter = TagFilter::new(Some("1.25*"), None).unwrap();
+        assert_eq!(filter.apply(all.clone()), tags(&["1.25-alpine"]));
+
+        let filter = TagFilter::new(None, Some(">=1.25, <2")).unwrap();
+        assert_eq!(filter.apply(all.clone()), tags(&["v1.25", "1.26.1"]));
+
+        assert!(TagFilter::new(Some("[1"), None).is_err());
+        assert!(TagFilter::new(None, Some("not a version")).is_err());
+    }
+
+    #[test]
+    fn test_parse_repository() {
+        assert_eq!(
+            parse_repository("nginx").unwrap().repository,
+            "library/nginx"
+        );
+        let reference = parse_repository("localhost:5000/team/app").unwrap();
+        assert_eq!(reference.repository, "team/app");
+
+        let digest = format!("sha256:{}", "a".repeat(64));
+        for image in [
+            "nginx:latest",
+            "localhost:5000/app:v1",
+            &format!("nginx@{digest}"),
+        ] {
+            assert!(
+                matches!(parse_repository(image), Err(PeekoCliError::Input(_))),
+                "{image}"
+            );
+        }
+    }
+}
@@ -1,30 +1,23 @@
-use peeko::reader::build_image_reader;
-
-use crate::config;
-use crate::error::{PeekoCliError, Result};
+use crate::commands;
+use crate::error::Result;
 use crate::utils;
 
-pub async fn execute(image_with_tag: &str, depth: usize, path: Option<String>) -> Result<()> {
-    match image_with_tag.rsplit_once(':') {
-        Some((image, tag)) => {
-            utils::print_header(&format!("Filesystem Tree for {image}:{tag}"));
-
-            let image_path = config::get_peeko_dir().join(format!("{image}/{tag}"));
-
-            // Check if image exists
-            if !std::path::Path::new(&image_path).exists() {
-                utils::print_error(&format!("Image {image}:{tag} not found locally"));
-                utils::print_info("Use 'peeko pull' to download the image first.");
-                return Err(PeekoCliError::RuntimeError("".to_string()));
-            }
-
-            let reader = build_image_reader(&image_path).await?;
-            reader.print_dir_tree(depth, path)?;
+pub async fn execute(
+    image_with_tag: &str,
+    platform: Option<&str>,
+    depth: usize,
+    path: Option<String>,
+    remote: bool,
+) -> Result<()> {
+    let reader = if remote {
+        utils::print_header(&format!("Filesystem Tree for {image_with_tag}"));
+        commands::open_remote_image(image_with_tag, platform).await?
+    } else {
+        let image = commands::find_local_image(image_with_tag, platform)?;
+        utils::print_header(&format!("Filesystem Tree for {image}"));
+        commands::open_image(&image).await?
+    };
+    reader.print_dir_tree(depth, path)?;
 
-            Ok(())
-        }
-        None => Err(PeekoCliError::Input(
-            "Image with tag is required".to_string(),
-        )),
-    }
+    Ok(())
 }
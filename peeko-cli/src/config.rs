@@ -3,6 +3,7 @@ use std::path::PathBuf;
 
 const DEFAULT_PEEKO_DIR: &str = "~/.peeko";
 const DEFAULT_CONCURRENT_DOWNLOADS: &str = "4";
+const DEFAULT_MAX_RETRIES: &str = "4";
 
 pub fn get_peeko_dir() -> PathBuf {
     let peeko_dir = env::var("PEEKO_DIR").unwrap_or(DEFAULT_PEEKO_DIR.to_string());
@@ -16,8 +17,17 @@ pub fn get_peeko_dir() -> PathBuf {
     peeko_dir.into()
 }
 
+pub fn get_credentials_path() -> PathBuf {
+    get_peeko_dir().join("auth.json")
+}
+
 pub fn get_concurrent_downloads() -> usize {
     let concurrent_downloads =
         env::var("CONCURRENT_DOWNLOADS").unwrap_or(DEFAULT_CONCURRENT_DOWNLOADS.to_string());
     concurrent_downloads.parse().unwrap_or(3)
 }
+
+pub fn get_max_retries() -> u32 {
+    let max_retries = env::var("MAX_RETRIES").unwrap_or(DEFAULT_MAX_RETRIES.to_string());
+    max_retries.parse().unwrap_or(4)
+}
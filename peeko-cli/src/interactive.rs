@@ -1,8 +1,11 @@
+use std::time::Duration;
+
 use console::style;
 use inquire::{Confirm, Select, Text};
+use peeko::fs::PruneFilter;
 
 use crate::commands;
-use crate::error::Result;
+use crate::error::{PeekoCliError, Result};
 use crate::utils;
 
 const MENU_OPTIONS: &[&str] = &[
@@ -14,6 +17,13 @@ const MENU_OPTIONS: &[&str] = &[
     "❌ Exit",
 ];
 
+const CLEAN_OPTIONS: &[&str] = &[
+    "All downloaded images",
+    "Images older than N days",
+    "Images not used in N days",
+    "Only unreferenced blobs",
+];
+
 pub async fn run() -> Result<()> {
     utils::print_welcome();
 
@@ -56,12 +66,12 @@ pub async fn run() -> Result<()> {
 async fn handle_pull_image() -> Result<()> {
     println!("\n{}", style("📥 Pull Container Image").bold().cyan());
 
-    let image = Text::new("Image name with tag (e.g., library/node:18-alpine, nginx:latest):")
+    let image = Text::new("Image reference (e.g., library/node:18-alpine, ghcr.io/owner/app:tag):")
         .with_help_message("Enter the image name with tag")
         .prompt()?;
 
     println!("\n{}", style("Starting download...").dim());
-    commands::pull::execute(&image).await?;
+    commands::pull::execute(&image, None, false).await?;
 
     Ok(())
 }
@@ -80,7 +90,7 @@ async fn handle_browse_filesystem() -> Result<()> {
 
     let depth: usize = depth.parse().unwrap_or(3);
 
-    commands::tree::execute(&image, depth, None).await?;
+    commands::tree::execute(&image, None, depth, None, false).await?;
 
     Ok(())
 }
@@ -88,17 +98,37 @@ async fn handle_browse_filesystem() -> Result<()> {
 async fn handle_clean_images() -> Result<()> {
     println!("\n{}", style("🧹 Clean Downloaded Images").bold().red());
 
-    let confirm = Confirm::new("Are you sure you want to delete all downloaded images?")
+    let scope = Select::new("What would you like to clean?", CLEAN_OPTIONS.to_vec()).prompt()?;
+    let filter = match scope {
+        "All downloaded images" => PruneFilter::All,
+        "Images older than N days" => PruneFilter::OlderThan(prompt_days()?),
+        "Images not used in N days" => PruneFilter::UnusedFor(prompt_days()?),
+        _ => PruneFilter::None,
+    };
+
+    if !commands::prune::preview(filter)? {
+        return Ok(());
+    }
+    let confirm = Confirm::new("Are you sure you want to delete the items listed above?")
         .with_default(false)
         .with_help_message("This action cannot be undone")
         .prompt()?;
 
     if confirm {
-        // TODO: Implement clean functionality
-        utils::print_success("All downloaded images have been cleaned!");
+        commands::prune::execute(filter, false).await?;
     } else {
         utils::print_info("Clean operation cancelled.");
     }
 
     Ok(())
 }
+
+fn prompt_days() -> Result<Duration> {
+    let days = Text::new("Number of days:")
+        .with_default("30")
+        .prompt()?
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| PeekoCliError::Input("Number of days must be a whole number".to_string()))?;
+    Ok(Duration::from_secs(days.saturating_mul(24 * 60 * 60)))
+}
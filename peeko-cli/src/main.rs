@@ -1,4 +1,7 @@
+use std::time::Duration;
+
 use clap::{Parser, Subcommand};
+use peeko::fs::PruneFilter;
 
 use crate::{
     error::PeekoCliError,
@@ -24,21 +27,102 @@ struct Cli {
 enum Commands {
     /// Pull a container image from registry
     Pull {
-        /// Image name (e.g., library/node, nginx)
+        /// Image reference (e.g., nginx, ghcr.io/owner/app:tag, localhost:5000/app@sha256:...)
         image: String,
+
+        /// Platform to pull as os/arch[/variant] (defaults to the host platform)
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Pull every platform of a multi-platform image
+        #[arg(long, conflicts_with = "platform")]
+        all_platforms: bool,
     },
     /// List downloaded images
     List,
+    /// Show the platforms, layers and config of an image
+    Inspect {
+        /// Image reference (e.g., nginx, ghcr.io/owner/app:tag, localhost:5000/app@sha256:...)
+        image: String,
+
+        /// Fetch the manifest and config from the registry instead of a downloaded image
+        #[arg(long)]
+        remote: bool,
+
+        /// Platform to inspect as os/arch[/variant] (defaults to the host platform)
+        #[arg(long)]
+        platform: Option<String>,
+    },
+    /// List the tags of a repository in its registry
+    Tags {
+        /// Repository (e.g., nginx, ghcr.io/owner/app)
+        repository: String,
+
+        /// Only show tags matching a glob (e.g., "1.2*", "*-alpine")
+        #[arg(long, conflicts_with = "semver")]
+        filter: Option<String>,
+
+        /// Only show version tags matching a requirement (e.g., ">=1.25, <2"), sorted by version
+        #[arg(long)]
+        semver: Option<String>,
+    },
+    /// Log in to a container registry
+    Login {
+        /// Registry host (e.g., ghcr.io, docker.io)
+        #[arg(default_value = "docker.io")]
+        registry: String,
+
+        /// Username to log in with
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Read the password or token from stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Log out from a container registry
+    Logout {
+        /// Registry host (e.g., ghcr.io, docker.io)
+        #[arg(default_value = "docker.io")]
+        registry: String,
+    },
     /// Remove a downloaded image
     Remove {
-        /// Image name with tag (e.g., library/node:18-alpine, nginx:latest)
+        /// Image name with tag or digest (e.g., library/node:18-alpine, nginx@sha256:...)
         image: String,
     },
+    /// Remove downloaded images and blobs no image uses anymore
+    #[command(alias = "clean")]
+    Prune {
+        /// Remove all downloaded images
+        #[arg(long, conflicts_with_all = ["older_than", "unused_for"])]
+        all: bool,
+
+        /// Remove images downloaded more than DAYS days ago
+        #[arg(long, value_name = "DAYS", conflicts_with = "unused_for")]
+        older_than: Option<u64>,
+
+        /// Remove images not pulled or read in the last DAYS days
+        #[arg(long, value_name = "DAYS")]
+        unused_for: Option<u64>,
+
+        /// Show what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Show image filesystem tree
     Tree {
-        /// Image name with tag (e.g., library/node:18-alpine, nginx:latest)
+        /// Image name with tag or digest (e.g., library/node:18-alpine, nginx@sha256:...)
         image: String,
 
+        /// Platform to use when several were pulled (defaults to the host platform)
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Read the image from its registry on demand instead of a downloaded copy
+        #[arg(long)]
+        remote: bool,
+
         /// Path to start the tree from
         #[arg(short, long)]
         path: Option<String>,
@@ -49,21 +133,45 @@ enum Commands {
     },
     /// List files in an image
     Ls {
-        /// Image name with tag (e.g., library/node:18-alpine, nginx:latest)
+        /// Image name with tag or digest (e.g., library/node:18-alpine, nginx@sha256:...)
         image: String,
 
+        /// Platform to use when several were pulled (defaults to the host platform)
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Read the image from its registry on demand instead of a downloaded copy
+        #[arg(long)]
+        remote: bool,
+
         /// Path to start the ls from
         #[arg(short, long)]
         path: String,
+
+        /// Show permissions, owner, group, modification time and extended attributes
+        #[arg(short, long)]
+        long: bool,
     },
     /// Cat a file in an image
     Cat {
-        /// Image name with tag (e.g., library/node:18-alpine, nginx:latest)
+        /// Image name with tag or digest (e.g., library/node:18-alpine, nginx@sha256:...)
         image: String,
 
+        /// Platform to use when several were pulled (defaults to the host platform)
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Read the image from its registry on demand instead of a downloaded copy
+        #[arg(long)]
+        remote: bool,
+
         /// Path to the file to cat
         #[arg(short, long)]
         path: String,
+
+        /// Fail on a symbolic link instead of printing the file it points to
+        #[arg(long)]
+        no_follow: bool,
     },
     /// Start interactive mode
     Interactive,
@@ -74,14 +182,67 @@ async fn main() -> Result<(), error::PeekoCliError> {
     let cli = Cli::parse();
 
     let result = match cli.command {
-        Some(Commands::Pull { image }) => commands::pull::execute(&image).await,
+        Some(Commands::Pull {
+            image,
+            platform,
+            all_platforms,
+        }) => commands::pull::execute(&image, platform.as_deref(), all_platforms).await,
         Some(Commands::List) => commands::list::execute().await,
+        Some(Commands::Inspect {
+            image,
+            remote,
+            platform,
+        }) => commands::inspect::execute(&image, platform.as_deref(), remote).await,
+        Some(Commands::Tags {
+            repository,
+            filter,
+            semver,
+        }) => commands::tags::execute(&repository, filter.as_deref(), semver.as_deref()).await,
+        Some(Commands::Login {
+            registry,
+            username,
+            password_stdin,
+        }) => commands::login::execute(&registry, username, password_stdin).await,
+        Some(Commands::Logout { registry }) => commands::logout::execute(&registry).await,
         Some(Commands::Remove { image }) => commands::remove::execute(&image).await,
-        Some(Commands::Tree { image, depth, path }) => {
-            commands::tree::execute(&image, depth, path).await
+        Some(Commands::Prune {
+            all,
+            older_than,
+            unused_for,
+            dry_run,
+        }) => {
+            let filter = if all {
+                PruneFilter::All
+            } else if let Some(days) = older_than {
+                PruneFilter::OlderThan(days_to_duration(days))
+            } else if let Some(days) = unused_for {
+                PruneFilter::UnusedFor(days_to_duration(days))
+            } else {
+                PruneFilter::None
+            };
+            commands::prune::execute(filter, dry_run).await
         }
-        Some(Commands::Ls { image, path }) => commands::ls::execute(&image, &path).await,
-        Some(Commands::Cat { image, path }) => commands::cat::execute(&image, &path).await,
+        Some(Commands::Tree {
+            image,
+            platform,
+            remote,
+            depth,
+            path,
+        }) => commands::tree::execute(&image, platform.as_deref(), depth, path, remote).await,
+        Some(Commands::Ls {
+            image,
+            platform,
+            remote,
+            path,
+            long,
+        }) => commands::ls::execute(&image, platform.as_deref(), &path, long, remote).await,
+        Some(Commands::Cat {
+            image,
+            platform,
+            remote,
+            path,
+            no_follow,
+        }) => commands::cat::execute(&image, platform.as_deref(), &path, !no_follow, remote).await,
         Some(Commands::Interactive) | None => interactive::run().await,
     };
     match result {
@@ -99,3 +260,7 @@ async fn main() -> Result<(), error::PeekoCliError> {
 
     Ok(())
 }
+
+fn days_to_duration(days: u64) -> Duration {
+    Duration::from_secs(days.saturating_mul(24 * 60 * 60))
+}
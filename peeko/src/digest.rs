@@ -0,0 +1,63 @@
+//! Helpers for computing and comparing OCI content digests.
+
+use sha2::{Digest, Sha256, Sha512};
+
+/// Incremental hasher for an OCI digest string such as `sha256:<hex>`.
+pub enum Digester {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Digester {
+    /// Creates a hasher matching the algorithm of `digest`.
+    ///
+    /// Returns `None` when the algorithm is not supported.
+    pub fn for_digest(digest: &str) -> Option<Self> {
+        match digest.split_once(':') {
+            Some(("sha256", _)) => Some(Self::Sha256(Sha256::new())),
+            Some(("sha512", _)) => Some(Self::Sha512(Sha512::new())),
+            _ => None,
+        }
+    }
+
+    /// Feeds more bytes into the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Consumes the hasher and returns the digest in `<algorithm>:<hex>` form.
+    pub fn finalize(self) -> String {
+        match self {
+            Self::Sha256(hasher) => format!("sha256:{}", to_hex(&hasher.finalize())),
+            Self::Sha512(hasher) => format!("sha512:{}", to_hex(&hasher.finalize())),
+        }
+    }
+}
+
+/// Computes the `sha256:<hex>` digest of `data`.
+pub fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{}", to_hex(&Sha256::digest(data)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digester() {
+        let expected = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let mut digester = Digester::for_digest(expected).unwrap();
+        digester.update(b"hel");
+        digester.update(b"lo");
+        assert_eq!(digester.finalize(), expected);
+        assert_eq!(sha256_digest(b"hello"), expected);
+        assert!(Digester::for_digest("md5:abc").is_none());
+    }
+}
@@ -0,0 +1,132 @@
+//! On-disk formats used for stored images.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{Platform, get_file_type};
+
+/// Marker file identifying an OCI image layout directory.
+pub const OCI_LAYOUT_FILE: &str = "oci-layout";
+/// Version written to the `oci-layout` marker file.
+pub const OCI_LAYOUT_VERSION: &str = "1.0.0";
+/// Entry point of an OCI image layout.
+pub const INDEX_FILE: &str = "index.json";
+/// Manifest file of the legacy peeko layout.
+pub const LEGACY_MANIFEST_FILE: &str = "manifest.json";
+/// Directory of a tag holding all of its platforms, next to the
+/// `<os>-<arch>[-<variant>]` directories of single-platform pulls.
+pub const ALL_PLATFORMS_DIR: &str = "all";
+/// Annotation holding the tag of a manifest referenced from `index.json`.
+pub const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+/// Annotation of `index.json` holding the digest of the manifest the pulled
+/// tag or digest resolved to.
+pub const RESOLVED_DIGEST_ANNOTATION: &str = "io.peeko.image.digest";
+
+/// Contents of the `oci-layout` marker file.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OciLayout {
+    #[serde(rename = "imageLayoutVersion")]
+    /// Version of the image layout specification.
+    pub image_layout_version: String,
+}
+
+impl Default for OciLayout {
+    fn default() -> Self {
+        Self {
+            image_layout_version: OCI_LAYOUT_VERSION.to_string(),
+        }
+    }
+}
+
+/// Format of a stored image directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageLayout {
+    /// OCI image layout: `oci-layout`, `index.json` and `blobs/<alg>/<hex>`.
+    Oci,
+    /// Layout written by earlier peeko versions: `manifest.json` next to
+    /// `<digest>.<ext>` blobs.
+    Legacy,
+}
+
+impl ImageLayout {
+    /// Detects the layout of `image_dir`, returning `None` when the directory
+    /// does not hold a complete image.
+    pub fn detect<P: AsRef<Path>>(image_dir: P) -> Option<Self> {
+        let image_dir = image_dir.as_ref();
+        if image_dir.join(OCI_LAYOUT_FILE).is_file() && image_dir.join(INDEX_FILE).is_file() {
+            Some(Self::Oci)
+        } else if image_dir.join(LEGACY_MANIFEST_FILE).is_file() {
+            Some(Self::Legacy)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the file that references the rest of the image, which is
+    /// written last when the image is stored.
+    pub fn entry_file(self) -> &'static str {
+        match self {
+            Self::Oci => INDEX_FILE,
+            Self::Legacy => LEGACY_MANIFEST_FILE,
+        }
+    }
+
+    /// Returns where the blob with `digest` and `media_type` is stored.
+    pub fn blob_path<P: AsRef<Path>>(
+        self,
+        image_dir: P,
+        digest: &str,
+        media_type: &str,
+    ) -> PathBuf {
+        match self {
+            Self::Oci => oci_blob_path(image_dir, digest),
+            Self::Legacy => image_dir
+                .as_ref()
+                .join(format!("{digest}.{}", get_file_type(media_type))),
+        }
+    }
+}
+
+/// Returns `blobs/<algorithm>/<hex>` inside an OCI image layout.
+pub fn oci_blob_path<P: AsRef<Path>>(layout_dir: P, digest: &str) -> PathBuf {
+    layout_dir.as_ref().join("blobs").join(digest_path(digest))
+}
+
+/// Returns the name of the directory holding the `platform` build of a tag,
+/// `<os>-<architecture>[-<variant>]`.
+pub fn platform_dir(platform: &Platform) -> String {
+    platform.to_string().replace('/', "-")
+}
+
+/// Returns the name of the directory holding the images pulled by
+/// `reference`: the tag itself, or `@<algorithm>-<hex>` for a digest, which
+/// no tag can be confused with.
+pub fn reference_dir(reference: &str) -> String {
+    match reference.split_once(':') {
+        Some((algorithm, hex)) => format!("@{algorithm}-{hex}"),
+        None => reference.to_string(),
+    }
+}
+
+/// Returns the tag or digest stored in a directory named by [`reference_dir`].
+pub fn reference_from_dir(dir_name: &str) -> String {
+    match dir_name
+        .strip_prefix('@')
+        .and_then(|digest| digest.split_once('-'))
+    {
+        Some((algorithm, hex)) => format!("{algorithm}:{hex}"),
+        None => dir_name.to_string(),
+    }
+}
+
+/// Returns whether `reference` is a digest such as `sha256:<hex>` rather than a tag.
+pub fn is_digest(reference: &str) -> bool {
+    reference.contains(':')
+}
+
+/// Returns the `<algorithm>/<hex>` path of a blob relative to a blobs directory.
+pub(crate) fn digest_path(digest: &str) -> PathBuf {
+    let (algorithm, hex) = digest.split_once(':').unwrap_or(("sha256", digest));
+    Path::new(algorithm).join(hex)
+}
@@ -1,34 +1,212 @@
+use std::collections::VecDeque;
+use std::fmt;
 use std::fs;
-use std::io::Result;
+use std::io::{ErrorKind, Result};
 use std::path::{Path, PathBuf};
 
-/// Returns a list of image name/tag pairs found under the given OCI directory.
+use crate::manifest::{INDEX_MEDIA_TYPES, ManifestList, Platform, PlatformParam};
+use layout::{
+    ALL_PLATFORMS_DIR, INDEX_FILE, RESOLVED_DIGEST_ANNOTATION, is_digest, oci_blob_path,
+    platform_dir, reference_dir, reference_from_dir,
+};
+
+pub mod layout;
+pub mod prune;
+pub mod store;
+
+pub use layout::ImageLayout;
+pub use prune::{PruneFilter, PruneOptions, PruneReport, mark_image_used, prune};
+pub use store::BlobStore;
+
+/// Image stored in a downloads directory, one per stored platform.
+#[derive(Debug, Clone)]
+pub struct StoredImage {
+    /// Repository name, for example `library/nginx`.
+    pub name: String,
+    /// Tag or digest the image was pulled with.
+    pub tag: String,
+    /// Digest of the manifest the tag resolved to when it was pulled, when
+    /// recorded.
+    pub digest: Option<String>,
+    /// Platform the image was built for, when recorded.
+    pub platform: Option<Platform>,
+    /// Directory holding the image.
+    pub path: PathBuf,
+}
+
+impl StoredImage {
+    /// Returns the reference the image was pulled with, `name:tag` or
+    /// `name@digest`.
+    pub fn reference(&self) -> String {
+        let separator = if is_digest(&self.tag) { '@' } else { ':' };
+        format!("{}{separator}{}", self.name, self.tag)
+    }
+}
+
+impl fmt::Display for StoredImage {
+    /// Formats the image as its reference, followed by the platform if known.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reference())?;
+        if let Some(platform) = &self.platform {
+            write!(f, " ({platform})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns a list of image references (`name:tag` or `name@digest`) found
+/// under the given OCI directory.
 ///
-/// The directory is expected to follow the layout created by `docker pull` or
-/// this crate's [`RegistryClient`](crate::registry::RegistryClient), where
-/// images are stored under `<name>/<tag>`.
+/// The directory is expected to follow the layout created by this crate's
+/// [`RegistryClient`](crate::registry::RegistryClient). Each tag is listed
+/// once, however many of its platforms are stored.
 pub fn collect_images<P: AsRef<Path>>(oci_dir: P) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for image in collect_stored_images(oci_dir)? {
+        let name = image.reference();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Returns every image stored under the given OCI directory.
+///
+/// Images are stored as OCI image layouts under
+/// `<name>/<tag>/<os>-<arch>[-<variant>]` or, when all platforms were pulled,
+/// `<name>/<tag>/all`, which yields one entry per platform sharing the same
+/// path. Images pulled by digest use `@<algorithm>-<hex>` in place of the
+/// tag. Earlier versions stored images directly under `<name>/<tag>`, either
+/// as an OCI image layout or in the legacy `manifest.json` format.
+pub fn collect_stored_images<P: AsRef<Path>>(oci_dir: P) -> Result<Vec<StoredImage>> {
     let base_dir = oci_dir.as_ref();
-    collect_image_directories(base_dir).map(|dirs| {
-        dirs.into_iter()
-            .map(|dir| {
-                let mut relative_path = dir
-                    .strip_prefix(base_dir)
-                    .expect("Must be a subdirectory of the peeko directory")
-                    .to_string_lossy()
-                    .to_string();
-                if let Some(pos) = relative_path.rfind('/') {
-                    relative_path.replace_range(pos..pos + 1, ":")
-                }
-
-                relative_path
-            })
-            .collect()
+    let dirs = collect_image_directories(base_dir)?;
+    Ok(dirs
+        .into_iter()
+        .flat_map(|dir| stored_images(base_dir, dir))
+        .collect())
+}
+
+/// Returns the stored image matching `name`, `reference` and, when given,
+/// `platform`. Without a platform the host's build is preferred, followed by
+/// the first one found.
+///
+/// `reference` is a tag or a digest; a digest also matches images pulled by
+/// a tag that resolved to it.
+pub fn find_image<P: AsRef<Path>>(
+    oci_dir: P,
+    name: &str,
+    reference: &str,
+    platform: Option<&PlatformParam>,
+) -> Result<Option<StoredImage>> {
+    let mut candidates: Vec<StoredImage> = collect_stored_images(oci_dir)?
+        .into_iter()
+        .filter(|image| {
+            image.name == name
+                && (image.tag == reference || image.digest.as_deref() == Some(reference))
+        })
+        .collect();
+    // prefer the image pulled by the reference itself
+    candidates.sort_by_key(|image| image.tag != reference);
+    let matching = |platform: &PlatformParam| {
+        candidates
+            .iter()
+            .find(|image| image.platform.as_ref().is_some_and(|p| platform.matches(p)))
+            .cloned()
+    };
+    Ok(match platform {
+        Some(platform) => matching(platform),
+        None => matching(&PlatformParam::host()).or_else(|| candidates.first().cloned()),
     })
 }
 
-/// Recursively walks the given path and collects directories that contain a
-/// `manifest.json`, returning their absolute paths.
+/// Returns one entry per platform stored in `image_dir`, or a single entry
+/// without platform when none is recorded.
+fn stored_images(base_dir: &Path, image_dir: PathBuf) -> Vec<StoredImage> {
+    let Some(layout) = ImageLayout::detect(&image_dir) else {
+        return Vec::new();
+    };
+    let platforms = match layout {
+        ImageLayout::Oci => stored_platforms(&image_dir),
+        ImageLayout::Legacy => Vec::new(),
+    };
+    let dir_name = image_dir.file_name().unwrap_or_default();
+    let in_tag_dir = layout == ImageLayout::Oci
+        && (dir_name == ALL_PLATFORMS_DIR
+            || platforms
+                .first()
+                .is_some_and(|platform| dir_name == platform_dir(platform).as_str()));
+
+    let Ok(relative_path) = image_dir.strip_prefix(base_dir) else {
+        return Vec::new();
+    };
+    let tag_path = if in_tag_dir {
+        relative_path.parent().unwrap_or(relative_path)
+    } else {
+        relative_path
+    };
+    let (Some(name), Some(tag)) = (tag_path.parent(), tag_path.file_name()) else {
+        return Vec::new();
+    };
+    if name.as_os_str().is_empty() {
+        return Vec::new();
+    }
+
+    let digest = match layout {
+        ImageLayout::Oci => read_resolved_digest(&image_dir),
+        ImageLayout::Legacy => None,
+    };
+    let image = |platform| StoredImage {
+        name: name.to_string_lossy().to_string(),
+        tag: reference_from_dir(&tag.to_string_lossy()),
+        digest: digest.clone(),
+        platform,
+        path: image_dir.clone(),
+    };
+    if platforms.is_empty() {
+        vec![image(None)]
+    } else {
+        platforms.into_iter().map(|p| image(Some(p))).collect()
+    }
+}
+
+/// Reads the digest the image in `image_dir` resolved to when it was pulled,
+/// recorded in the annotations of its `index.json`.
+pub fn read_resolved_digest<P: AsRef<Path>>(image_dir: P) -> Option<String> {
+    let index = fs::read(image_dir.as_ref().join(INDEX_FILE)).ok()?;
+    serde_json::from_slice::<ManifestList>(&index)
+        .ok()?
+        .annotations?
+        .remove(RESOLVED_DIGEST_ANNOTATION)
+}
+
+/// Reads the platforms recorded in the `index.json` of an OCI image layout,
+/// including those of the multi-platform indexes it references, skipping
+/// attestation manifests.
+fn stored_platforms(image_dir: &Path) -> Vec<Platform> {
+    let mut platforms = Vec::new();
+    let mut pending = VecDeque::from([image_dir.join(INDEX_FILE)]);
+    while let Some(path) = pending.pop_front() {
+        let Some(index) = fs::read(path)
+            .ok()
+            .and_then(|index| serde_json::from_slice::<ManifestList>(&index).ok())
+        else {
+            continue;
+        };
+        for entry in index.manifests {
+            if INDEX_MEDIA_TYPES.contains(&entry.media_type.as_str()) {
+                pending.push_back(oci_blob_path(image_dir, &entry.digest));
+            } else if let Some(platform) = entry.platform.filter(|p| !p.is_unknown()) {
+                platforms.push(platform);
+            }
+        }
+    }
+    platforms
+}
+
+/// Recursively walks the given path and collects directories that hold an
+/// image (see [`ImageLayout::detect`]), returning their absolute paths.
 pub fn collect_image_directories<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
     let mut result = Vec::new();
     let path = path.as_ref();
@@ -41,8 +219,7 @@ fn collect_image_directories_recursive(path: &Path, result: &mut Vec<PathBuf>) -
         return Ok(());
     }
 
-    let manifest_path = path.join("manifest.json");
-    if manifest_path.exists() {
+    if ImageLayout::detect(path).is_some() {
         result.push(path.to_path_buf());
         return Ok(());
     }
@@ -51,6 +228,8 @@ fn collect_image_directories_recursive(path: &Path, result: &mut Vec<PathBuf>) -
     for entry in entries
         .flatten()
         .filter(|e| e.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+        .filter(|e| e.file_name() != store::BLOB_STORE_DIR)
+        .filter(|e| e.file_name() != store::LAYER_INDEX_DIR)
     {
         collect_image_directories_recursive(&entry.path(), result)?;
     }
@@ -58,9 +237,29 @@ fn collect_image_directories_recursive(path: &Path, result: &mut Vec<PathBuf>) -
     Ok(())
 }
 
-/// Removes the directory storing the given `image:tag` from the OCI root.
+/// Removes the directory storing every platform of the given image tag or
+/// digest from the OCI root, along with parent directories left empty.
+///
+/// Blobs the image shared through the [`BlobStore`] are kept; [`prune`]
+/// removes the ones no other image references.
 pub fn delete_image<P: AsRef<Path>>(oci_dir: P, image: &str, tag: &str) -> Result<()> {
-    let image_path = oci_dir.as_ref().join(format!("{image}/{tag}"));
+    let oci_dir = oci_dir.as_ref();
+    let image_path = oci_dir.join(image).join(reference_dir(tag));
     fs::remove_dir_all(&image_path)?;
+    remove_empty_parents(&image_path, oci_dir)
+}
+
+/// Removes the now empty ancestors of `path`, stopping at `root`.
+fn remove_empty_parents(path: &Path, root: &Path) -> Result<()> {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) {
+            break;
+        }
+        match fs::remove_dir(dir) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::DirectoryNotEmpty => break,
+            Err(err) => return Err(err),
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,488 @@
+//! Removal of downloaded images and of blobs no image references anymore.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use super::layout::{INDEX_FILE, ImageLayout, oci_blob_path};
+use super::store::{BlobStore, LAYER_INDEX_DIR};
+use super::{collect_stored_images, remove_empty_parents};
+
+/// Selects which images [`prune`] removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneFilter {
+    /// Keeps every image, only removing orphaned blobs.
+    None,
+    /// Removes every image.
+    All,
+    /// Removes images downloaded longer ago than the given duration.
+    OlderThan(Duration),
+    /// Removes images that were neither pulled nor read within the given
+    /// duration (see [`mark_image_used`]).
+    UnusedFor(Duration),
+}
+
+impl PruneFilter {
+    fn matches(self, image_dir: &Path, layout: ImageLayout, now: SystemTime) -> Result<bool> {
+        let older_than =
+            |age: Duration, time: SystemTime| now.duration_since(time).is_ok_and(|t| t > age);
+        let metadata = || fs::metadata(image_dir.join(layout.entry_file()));
+        Ok(match self {
+            Self::None => false,
+            Self::All => true,
+            Self::OlderThan(age) => older_than(age, metadata()?.modified()?),
+            Self::UnusedFor(age) => older_than(age, last_used(&metadata()?)?),
+        })
+    }
+}
+
+/// Age below which unreferenced blobs are kept, as they may belong to a pull
+/// that has not written the index of its image yet. This covers the
+/// `.partial` files of downloads in progress.
+pub const BLOB_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Options accepted by [`prune`].
+#[derive(Debug, Clone)]
+pub struct PruneOptions {
+    /// Images to remove.
+    pub filter: PruneFilter,
+    /// Reports what would be removed without deleting anything.
+    pub dry_run: bool,
+}
+
+/// What [`prune`] removed, or would remove in a dry run.
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    /// `image:tag` of every removed image, followed by its platform if known.
+    pub images: Vec<String>,
+    /// Blob files that no remaining image referenced.
+    pub orphaned_blobs: Vec<PathBuf>,
+    /// Cached layer indexes of layers that no remaining image referenced.
+    pub orphaned_indexes: Vec<PathBuf>,
+    /// Disk space freed. Hard-linked files only count once all of their
+    /// links are removed.
+    pub reclaimed_bytes: u64,
+}
+
+/// Removes the images in `oci_dir` selected by `options.filter`, then every
+/// blob that no remaining image references.
+///
+/// Orphaned blobs are looked for in the shared [`BlobStore`] and in the
+/// `blobs` directory of each remaining OCI image layout, which also cleans up
+/// interrupted downloads. Blobs modified within [`BLOB_GRACE_PERIOD`] are
+/// kept, so that pulls running alongside are not broken. Images whose
+/// manifests cannot be read keep all of their blobs. Cached layer indexes go
+/// along with their layers; they are rebuilt whenever a layer is read again.
+pub fn prune<P: AsRef<Path>>(oci_dir: P, options: &PruneOptions) -> Result<PruneReport> {
+    let oci_dir = oci_dir.as_ref();
+    let now = SystemTime::now();
+    let mut report = PruneReport::default();
+    let mut freed = FreedSpace::default();
+    let mut kept = Vec::new();
+
+    // platforms of a multi-platform layout share its directory
+    let mut images: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    for image in collect_stored_images(oci_dir)? {
+        match images.iter_mut().find(|(path, _)| *path == image.path) {
+            Some((_, names)) => names.push(image.to_string()),
+            None => images.push((image.path.clone(), vec![image.to_string()])),
+        }
+    }
+
+    for (image_dir, names) in images {
+        let Some(layout) = ImageLayout::detect(&image_dir) else {
+            continue;
+        };
+        if !options.filter.matches(&image_dir, layout, now)? {
+            if layout == ImageLayout::Oci {
+                kept.push(image_dir);
+            }
+            continue;
+        }
+
+        freed.add_dir(&image_dir)?;
+        if !options.dry_run {
+            fs::remove_dir_all(&image_dir)?;
+            remove_empty_parents(&image_dir, oci_dir)?;
+        }
+        report.images.extend(names);
+    }
+
+    let mut orphans = Vec::new();
+    let mut referenced = HashSet::new();
+    for image_dir in kept {
+        let blobs = blob_files(&image_dir.join("blobs"))?;
+        match referenced_blobs(&image_dir) {
+            Ok(digests) => {
+                orphans.extend(
+                    blobs
+                        .into_iter()
+                        .filter(|(digest, _)| !digests.contains(digest))
+                        .map(|(_, path)| path),
+                );
+                referenced.extend(digests);
+            }
+            Err(_) => referenced.extend(blobs.into_iter().map(|(digest, _)| digest)),
+        }
+    }
+    let store = BlobStore::in_dir(oci_dir);
+    orphans.extend(
+        blob_files(store.root())?
+            .into_iter()
+            .filter(|(digest, _)| !referenced.contains(digest))
+            .map(|(_, path)| path),
+    );
+
+    for path in orphans {
+        // clock skew can put modification times in the future
+        let modified = fs::symlink_metadata(&path)?.modified()?;
+        if !now
+            .duration_since(modified)
+            .is_ok_and(|age| age >= BLOB_GRACE_PERIOD)
+        {
+            continue;
+        }
+        freed.add_file(&path)?;
+        if !options.dry_run {
+            fs::remove_file(&path)?;
+        }
+        report.orphaned_blobs.push(path);
+    }
+
+    for (name, path) in blob_files(&oci_dir.join(LAYER_INDEX_DIR))? {
+        let digest = name
+            .split_once('.')
+            .map_or(name.as_str(), |(digest, _)| digest);
+        if referenced.contains(digest) {
+            continue;
+        }
+        freed.add_file(&path)?;
+        if !options.dry_run {
+            fs::remove_file(&path)?;
+        }
+        report.orphaned_indexes.push(path);
+    }
+
+    report.reclaimed_bytes = freed.total();
+    Ok(report)
+}
+
+/// Records that the image in `image_dir` was just used, so that
+/// [`PruneFilter::UnusedFor`] keeps it.
+///
+/// The access time of the image's entry file is set explicitly because
+/// filesystems mounted with `noatime` or `relatime` do not track reads.
+pub fn mark_image_used<P: AsRef<Path>>(image_dir: P) -> Result<()> {
+    let image_dir = image_dir.as_ref();
+    let Some(layout) = ImageLayout::detect(image_dir) else {
+        return Ok(());
+    };
+    let file = File::open(image_dir.join(layout.entry_file()))?;
+    file.set_times(FileTimes::new().set_accessed(SystemTime::now()))
+}
+
+/// Returns when an image was last pulled or read.
+fn last_used(metadata: &Metadata) -> Result<SystemTime> {
+    let modified = metadata.modified()?;
+    Ok(metadata
+        .accessed()
+        .map_or(modified, |accessed| accessed.max(modified)))
+}
+
+/// Digests referenced by a manifest or index, whatever its media type.
+#[derive(Deserialize)]
+struct References {
+    config: Option<Reference>,
+    #[serde(default)]
+    layers: Vec<Reference>,
+    #[serde(default)]
+    manifests: Vec<Reference>,
+}
+
+#[derive(Deserialize)]
+struct Reference {
+    digest: String,
+}
+
+/// Returns the digests of all blobs reachable from the `index.json` of an OCI
+/// image layout, including the manifests themselves.
+fn referenced_blobs(image_dir: &Path) -> Result<HashSet<String>> {
+    let mut digests = HashSet::new();
+    let mut pending = vec![image_dir.join(INDEX_FILE)];
+    while let Some(path) = pending.pop() {
+        let references: References = serde_json::from_slice(&fs::read(path)?)?;
+        for manifest in references.manifests {
+            if digests.insert(manifest.digest.clone()) {
+                pending.push(oci_blob_path(image_dir, &manifest.digest));
+            }
+        }
+        digests.extend(references.config.map(|config| config.digest));
+        digests.extend(references.layers.into_iter().map(|layer| layer.digest));
+    }
+    Ok(digests)
+}
+
+/// Lists the files of a `<algorithm>/<hex>` blobs directory keyed by digest.
+fn blob_files(blobs_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    if !blobs_dir.is_dir() {
+        return Ok(files);
+    }
+    for algorithm in fs::read_dir(blobs_dir)? {
+        let algorithm = algorithm?;
+        if !algorithm.file_type()?.is_dir() {
+            continue;
+        }
+        for blob in fs::read_dir(algorithm.path())? {
+            let blob = blob?;
+            if blob.file_type()?.is_file() {
+                let digest = format!(
+                    "{}:{}",
+                    algorithm.file_name().to_string_lossy(),
+                    blob.file_name().to_string_lossy()
+                );
+                files.push((digest, blob.path()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+/// Returns a key identifying the file's data and its number of hard links.
+#[cfg(unix)]
+fn file_id(_path: &Path, metadata: &Metadata) -> (FileId, u64) {
+    use std::os::unix::fs::MetadataExt;
+    ((metadata.dev(), metadata.ino()), metadata.nlink())
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _metadata: &Metadata) -> (FileId, u64) {
+    (path.to_path_buf(), 1)
+}
+
+/// Tallies removed files, counting data only once every link to it is gone.
+#[derive(Default)]
+struct FreedSpace {
+    // size, hard links and removed links per file
+    files: HashMap<FileId, (u64, u64, u64)>,
+}
+
+impl FreedSpace {
+    fn add_file(&mut self, path: &Path) -> Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        let (id, links) = file_id(path, &metadata);
+        self.files.entry(id).or_insert((metadata.len(), links, 0)).2 += 1;
+        Ok(())
+    }
+
+    fn add_dir(&mut self, dir: &Path) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                self.add_dir(&entry.path())?;
+            } else {
+                self.add_file(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn total(&self) -> u64 {
+        self.files
+            .values()
+            .filter(|(_, links, removed)| removed >= links)
+            .map(|(size, _, _)| size)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::sha256_digest;
+    use crate::fs::layout::{OCI_LAYOUT_FILE, REF_NAME_ANNOTATION, digest_path};
+
+    /// Stores an image whose manifest references `layers`, linking them from
+    /// the blob store like the registry client does. The blobs are dated back
+    /// past [`BLOB_GRACE_PERIOD`].
+    fn store_image(oci_dir: &Path, name: &str, layers: &[&[u8]]) -> PathBuf {
+        let store = BlobStore::in_dir(oci_dir);
+        let image_dir = oci_dir.join(name);
+        let mut descriptors = Vec::new();
+        for layer in layers {
+            let digest = sha256_digest(layer);
+            let store_path = store.blob_path(&digest);
+            fs::create_dir_all(store_path.parent().unwrap()).unwrap();
+            fs::write(&store_path, layer).unwrap();
+            set_blob_age(&store_path, 2 * BLOB_GRACE_PERIOD);
+            let blob_path = oci_blob_path(&image_dir, &digest);
+            fs::create_dir_all(blob_path.parent().unwrap()).unwrap();
+            let _ = fs::remove_file(&blob_path);
+            fs::hard_link(&store_path, &blob_path).unwrap();
+            descriptors.push(format!(
+                r#"{{"digest":"{digest}","mediaType":"application/vnd.oci.image.layer.v1.tar","size":{}}}"#,
+                layer.len()
+            ));
+        }
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"layers":[{}]}}"#,
+            descriptors.join(",")
+        );
+        let manifest_digest = sha256_digest(manifest.as_bytes());
+        fs::write(oci_blob_path(&image_dir, &manifest_digest), &manifest).unwrap();
+        fs::write(image_dir.join(OCI_LAYOUT_FILE), "{}").unwrap();
+        fs::write(
+            image_dir.join(INDEX_FILE),
+            format!(
+                r#"{{"schemaVersion":2,"manifests":[{{"digest":"{manifest_digest}","mediaType":"application/vnd.oci.image.manifest.v1+json","size":{},"annotations":{{"{REF_NAME_ANNOTATION}":"v1"}}}}]}}"#,
+                manifest.len()
+            ),
+        )
+        .unwrap();
+        image_dir
+    }
+
+    fn set_blob_age(path: &Path, age: Duration) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    fn set_modified(image_dir: &Path, age: Duration) {
+        let time = SystemTime::now() - age;
+        File::options()
+            .write(true)
+            .open(image_dir.join(INDEX_FILE))
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(time).set_accessed(time))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_prune_images_and_orphaned_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = store_image(dir.path(), "test/old/v1", &[b"shared", b"old layer"]);
+        let new = store_image(dir.path(), "test/new/v1", &[b"shared"]);
+        set_modified(&old, Duration::from_secs(10 * 86400));
+        // blob left behind by an image that was removed earlier
+        let orphan = BlobStore::in_dir(dir.path()).blob_path(&sha256_digest(b"orphan"));
+        fs::write(&orphan, b"orphan").unwrap();
+        set_blob_age(&orphan, 2 * BLOB_GRACE_PERIOD);
+        // cached layer indexes follow their layers
+        let index_path = |layer: &[u8]| {
+            let path = dir
+                .path()
+                .join(LAYER_INDEX_DIR)
+                .join(digest_path(&sha256_digest(layer)))
+                .with_extension("json");
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "{}").unwrap();
+            path
+        };
+        let old_index = index_path(b"old layer");
+        let shared_index = index_path(b"shared");
+
+        let dry_run = prune(
+            dir.path(),
+            &PruneOptions {
+                filter: PruneFilter::OlderThan(Duration::from_secs(86400)),
+                dry_run: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(dry_run.images, vec!["test/old:v1".to_string()]);
+        assert_eq!(dry_run.orphaned_blobs.len(), 2);
+        assert_eq!(dry_run.orphaned_indexes, vec![old_index.clone()]);
+        assert!(old.is_dir() && orphan.is_file());
+
+        let report = prune(
+            dir.path(),
+            &PruneOptions {
+                filter: PruneFilter::OlderThan(Duration::from_secs(86400)),
+                dry_run: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(report.images, dry_run.images);
+        assert_eq!(report.reclaimed_bytes, dry_run.reclaimed_bytes);
+        assert!(!old.exists() && !dir.path().join("test/old").exists());
+        assert!(!orphan.exists());
+        assert!(!old_index.exists() && shared_index.is_file());
+        // "shared" is still used by the new image, so only the old layer, the
+        // orphan and the old image's metadata count as reclaimed
+        assert!(report.reclaimed_bytes >= (b"old layer".len() + b"orphan".len()) as u64);
+        let shared = sha256_digest(b"shared");
+        assert!(BlobStore::in_dir(dir.path()).blob_path(&shared).is_file());
+        assert!(oci_blob_path(&new, &shared).is_file());
+        assert_eq!(
+            crate::fs::collect_images(dir.path()).unwrap(),
+            vec!["test/new:v1".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_prune_unused_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = store_image(dir.path(), "test/app/v1", &[b"layer"]);
+        set_modified(&image, Duration::from_secs(10 * 86400));
+        mark_image_used(&image).unwrap();
+
+        let options = PruneOptions {
+            filter: PruneFilter::UnusedFor(Duration::from_secs(86400)),
+            dry_run: false,
+        };
+        assert!(prune(dir.path(), &options).unwrap().images.is_empty());
+
+        let options = PruneOptions {
+            filter: PruneFilter::All,
+            dry_run: false,
+        };
+        let report = prune(dir.path(), &options).unwrap();
+        assert_eq!(report.images, vec!["test/app:v1".to_string()]);
+        assert_eq!(report.orphaned_blobs.len(), 1);
+        assert!(crate::fs::collect_images(dir.path()).unwrap().is_empty());
+        assert!(!dir.path().join("test").exists());
+    }
+
+    #[test]
+    fn test_prune_keeps_recent_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BlobStore::in_dir(dir.path());
+        let blob = |content: &[u8], suffix: &str, age: Duration| {
+            let path = store.blob_path(&sha256_digest(content));
+            let path = path.with_file_name(format!(
+                "{}{suffix}",
+                path.file_name().unwrap().to_string_lossy()
+            ));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            set_blob_age(&path, age);
+            path
+        };
+        // a pull in progress, and one interrupted long ago
+        let downloaded = blob(b"downloaded", "", Duration::ZERO);
+        let downloading = blob(b"downloading", ".partial", Duration::ZERO);
+        let interrupted = blob(b"interrupted", ".partial", 2 * BLOB_GRACE_PERIOD);
+
+        let options = PruneOptions {
+            filter: PruneFilter::None,
+            dry_run: false,
+        };
+        let report = prune(dir.path(), &options).unwrap();
+        assert_eq!(report.orphaned_blobs, vec![interrupted.clone()]);
+        assert!(downloaded.is_file() && downloading.is_file());
+        assert!(!interrupted.exists());
+    }
+}
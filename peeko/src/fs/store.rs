@@ -0,0 +1,72 @@
+//! Content-addressable blob store shared by all images in a downloads directory.
+
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use super::layout::digest_path;
+
+/// Name of the blob store directory inside the downloads directory. Image
+/// names cannot start with a dot, so it never clashes with an image.
+pub const BLOB_STORE_DIR: &str = ".blobs";
+
+/// Name of the directory next to the blob store caching the file listing of
+/// each layer, stored as `<algorithm>/<hex>.json` by layer digest.
+pub const LAYER_INDEX_DIR: &str = ".index";
+
+/// Blobs shared between images, stored once as `<root>/<algorithm>/<hex>`.
+///
+/// Only verified blobs are moved into the store, so a blob with the expected
+/// size can be reused without downloading it again. Images reference store
+/// blobs through hard links (or copies on filesystems without hard links),
+/// which keeps every image directory a self-contained OCI image layout.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    /// Creates a store rooted at `root`.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns the store used for images downloaded into `oci_dir`.
+    pub fn in_dir<P: AsRef<Path>>(oci_dir: P) -> Self {
+        Self::new(oci_dir.as_ref().join(BLOB_STORE_DIR))
+    }
+
+    /// Returns the store's root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns where the blob with `digest` is stored.
+    pub fn blob_path(&self, digest: &str) -> PathBuf {
+        self.root.join(digest_path(digest))
+    }
+
+    /// Returns whether the blob is present with the expected size.
+    pub async fn contains(&self, digest: &str, size: u64) -> bool {
+        fs::metadata(self.blob_path(digest))
+            .await
+            .is_ok_and(|metadata| metadata.is_file() && metadata.len() == size)
+    }
+
+    /// Makes the stored blob available at `dest`, replacing any file there.
+    pub async fn link_into(&self, digest: &str, dest: &Path) -> Result<()> {
+        let source = self.blob_path(digest);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        match fs::remove_file(dest).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        if fs::hard_link(&source, dest).await.is_err() {
+            fs::copy(&source, dest).await?;
+        }
+        Ok(())
+    }
+}
@@ -4,12 +4,16 @@
 //! artifacts from registries, and computing simple statistics about virtual
 //! filesystems reconstructed from image layers.
 
+/// Digest helpers used to verify downloaded content.
+pub mod digest;
 /// Filesystem helpers for working with OCI image layouts stored on disk.
 pub mod fs;
 /// Types that model OCI image manifests and configs.
 pub mod manifest;
 /// Async readers that reconstruct a virtual filesystem view of image layers.
 pub mod reader;
+/// Parsing of image references such as `ghcr.io/owner/app:tag`.
+pub mod reference;
 /// Clients for talking to OCI compatible registries.
 pub mod registry;
 /// Utilities for summarising reconstructed filesystem trees.
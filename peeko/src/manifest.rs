@@ -1,20 +1,95 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-/// High level representation of OCI manifest documents.
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(tag = "mediaType")]
+use crate::digest::sha256_digest;
+
+/// Media types of single-platform image manifests.
+pub const IMAGE_MANIFEST_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+/// Media types of multi-platform indexes.
+pub const INDEX_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+
+/// Media types of Docker schema 1 manifests, which are converted into an
+/// [`ImageManifest`] when pulled.
+pub const SCHEMA1_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.docker.distribution.manifest.v1+prettyjws",
+    "application/vnd.docker.distribution.manifest.v1+json",
+];
+
+/// Media type of Docker image manifests (schema 2).
+pub const DOCKER_MANIFEST_MEDIA_TYPE: &str = "application/vnd.docker.distribution.manifest.v2+json";
+/// Media type of Docker image configs.
+pub const DOCKER_CONFIG_MEDIA_TYPE: &str = "application/vnd.docker.container.image.v1+json";
+/// Media type of gzip compressed Docker layers.
+pub const DOCKER_LAYER_MEDIA_TYPE: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+
+/// High level representation of OCI and Docker manifest documents.
+///
+/// Deserializing picks the variant from the document's `mediaType`, or from
+/// its shape when the optional `mediaType` is left out: documents listing
+/// `manifests` are indexes.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
 pub enum Manifest {
-    #[serde(rename = "application/vnd.oci.image.manifest.v1+json")]
+    /// An OCI image manifest or a Docker schema 2 manifest.
     OCIManifest(ImageManifest),
 
-    #[serde(rename = "application/vnd.oci.image.index.v1+json")]
+    /// An OCI image index or a Docker manifest list.
     OCIIndex(ManifestList),
 }
 
+impl Manifest {
+    /// Parses a manifest or index from its JSON representation.
+    pub fn from_json(body: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(body)
+    }
+}
+
+impl<'de> Deserialize<'de> for Manifest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let mut value = serde_json::Value::deserialize(deserializer)?;
+        let Some(object) = value.as_object_mut() else {
+            return Err(D::Error::custom("manifest is not a JSON object"));
+        };
+        let media_type = match object.get("mediaType").and_then(|v| v.as_str()) {
+            Some(media_type) => media_type.to_string(),
+            None if object.contains_key("manifests") => INDEX_MEDIA_TYPES[0].to_string(),
+            None => IMAGE_MANIFEST_MEDIA_TYPES[0].to_string(),
+        };
+        object.insert("mediaType".to_string(), media_type.clone().into());
+
+        if IMAGE_MANIFEST_MEDIA_TYPES.contains(&media_type.as_str()) {
+            serde_json::from_value(value)
+                .map(Self::OCIManifest)
+                .map_err(D::Error::custom)
+        } else if INDEX_MEDIA_TYPES.contains(&media_type.as_str()) {
+            serde_json::from_value(value)
+                .map(Self::OCIIndex)
+                .map_err(D::Error::custom)
+        } else {
+            Err(D::Error::custom(format!(
+                "unsupported manifest media type {media_type}"
+            )))
+        }
+    }
+}
+
 /// Representation of `application/vnd.oci.image.manifest.v1+json`.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ImageManifest {
     #[serde(rename = "schemaVersion")]
     /// Schema version declared by the manifest.
@@ -33,8 +108,15 @@ pub struct ImageManifest {
     pub annotations: Option<HashMap<String, String>>,
 }
 
+impl ImageManifest {
+    /// Returns the compressed size of the layers, as downloaded by a pull.
+    pub fn layers_size(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.size).sum()
+    }
+}
+
 /// Generic descriptor that points to a blob stored in the registry.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Descriptor {
     /// SHA digest of the referenced blob.
     pub digest: String,
@@ -43,6 +125,9 @@ pub struct Descriptor {
     pub media_type: String,
     /// Size in bytes of the blob.
     pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Base64 encoded content of the blob, embedded in the manifest.
+    pub data: Option<String>,
 
     // for oci index
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -51,28 +136,34 @@ pub struct Descriptor {
 }
 
 /// Representation of `application/vnd.oci.image.index.v1+json`.
-#[derive(Debug, Deserialize, Serialize)]
+///
+/// Also used for the `index.json` of an OCI image layout.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ManifestList {
     #[serde(rename = "schemaVersion")]
     /// Schema version declared by the manifest.
     pub schema_version: u32,
-    #[serde(rename = "mediaType")]
+    #[serde(rename = "mediaType", default)]
     /// Media type for the manifest list.
     pub media_type: String,
     /// Architectures and platforms included in the manifest list.
     pub manifests: Vec<PlatformManifest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Optional annotations attached to the index.
+    pub annotations: Option<HashMap<String, String>>,
 }
 
 /// Descriptor of a single platform entry inside an OCI index.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PlatformManifest {
     /// SHA digest of the platform-specific manifest.
     pub digest: String,
     #[serde(rename = "mediaType")]
     /// Media type for the manifest.
     pub media_type: String,
-    /// Target platform described by this manifest.
-    pub platform: Platform,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Target platform described by this manifest, when declared.
+    pub platform: Option<Platform>,
     /// Size in bytes of the manifest blob.
     pub size: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -81,7 +172,7 @@ pub struct PlatformManifest {
 }
 
 /// Platform information attached to a platform manifest descriptor.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Platform {
     /// CPU architecture (for example `amd64` or `arm64`).
     pub architecture: String,
@@ -98,6 +189,139 @@ pub struct Platform {
     pub variant: Option<String>,
 }
 
+impl Platform {
+    /// Returns whether the platform is `unknown`, as registries tag the
+    /// attestation manifests of an index.
+    pub fn is_unknown(&self) -> bool {
+        self.os == "unknown" || self.architecture == "unknown"
+    }
+}
+
+impl fmt::Display for Platform {
+    /// Formats the platform as `os/architecture[/variant]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{variant}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when a platform is not written `os/architecture[/variant]`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid platform {0}, expected os/architecture[/variant]")]
+pub struct InvalidPlatform(pub String);
+
+/// Optional filters used to pick a specific platform when downloading multi-arch images.
+///
+/// Parses from the `os/architecture[/variant]` form used by container tools,
+/// for example `linux/arm64/v8`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlatformParam {
+    /// Specific CPU architecture to fetch.
+    pub architecture: Option<String>,
+    /// Specific operating system to fetch.
+    pub os: Option<String>,
+    /// CPU variant (for example `arm/v7`) to fetch.
+    pub variant: Option<String>,
+}
+
+impl PlatformParam {
+    /// Returns the platform of the machine running this code, using the
+    /// architecture names of the OCI image spec. Other operating systems
+    /// than Windows run Linux images.
+    pub fn host() -> Self {
+        let architecture = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "x86" => "386",
+            "aarch64" => "arm64",
+            "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+            "loongarch64" => "loong64",
+            arch => arch,
+        };
+        let os = match std::env::consts::OS {
+            "windows" => "windows",
+            _ => "linux",
+        };
+        Self {
+            architecture: Some(architecture.to_string()),
+            os: Some(os.to_string()),
+            variant: None,
+        }
+    }
+
+    /// Returns whether `platform` satisfies every filter that is set. A
+    /// variant filter also accepts platforms that do not declare a variant.
+    pub fn matches(&self, platform: &Platform) -> bool {
+        let matches = |filter: &Option<String>, value: &str| {
+            filter.as_deref().is_none_or(|filter| filter == value)
+        };
+        matches(&self.os, &platform.os)
+            && matches(&self.architecture, &platform.architecture)
+            && platform
+                .variant
+                .as_deref()
+                .is_none_or(|variant| matches(&self.variant, variant))
+    }
+
+    /// Returns whether an index entry is a build for this platform. Entries
+    /// without a platform only match an empty filter, and attestation
+    /// manifests, tagged with an `unknown` platform, never match.
+    pub(crate) fn matches_entry(&self, entry: &PlatformManifest) -> bool {
+        match &entry.platform {
+            Some(platform) => !platform.is_unknown() && self.matches(platform),
+            None => *self == Self::default(),
+        }
+    }
+}
+
+impl From<&Platform> for PlatformParam {
+    fn from(platform: &Platform) -> Self {
+        Self {
+            architecture: Some(platform.architecture.clone()),
+            os: Some(platform.os.clone()),
+            variant: platform.variant.clone(),
+        }
+    }
+}
+
+impl FromStr for PlatformParam {
+    type Err = InvalidPlatform;
+
+    fn from_str(s: &str) -> Result<Self, InvalidPlatform> {
+        let invalid = || InvalidPlatform(s.to_string());
+        let mut parts = s.split('/').map(|part| {
+            (!part.is_empty())
+                .then(|| part.to_string())
+                .ok_or_else(invalid)
+        });
+        let os = parts.next().transpose()?;
+        let architecture = parts.next().transpose()?;
+        let variant = parts.next().transpose()?;
+        if os.is_none() || architecture.is_none() || parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            architecture,
+            os,
+            variant,
+        })
+    }
+}
+
+impl fmt::Display for PlatformParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = [&self.os, &self.architecture, &self.variant];
+        let parts: Vec<&str> = parts.into_iter().flatten().map(String::as_str).collect();
+        if parts.is_empty() {
+            write!(f, "any")
+        } else {
+            write!(f, "{}", parts.join("/"))
+        }
+    }
+}
+
 /// Returns the file extension associated with a descriptor's media type.
 pub fn get_file_type(media_type: &str) -> &str {
     match media_type.rsplit_once('+') {
@@ -109,17 +333,192 @@ pub fn get_file_type(media_type: &str) -> &str {
     }
 }
 
+/// Representation of a Docker schema 1 manifest
+/// (`application/vnd.docker.distribution.manifest.v1+prettyjws`).
+///
+/// Layers are listed top-most first, each paired with the legacy image
+/// config at the same position in `history`. Signatures are ignored.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Schema1Manifest {
+    #[serde(rename = "schemaVersion")]
+    /// Schema version declared by the manifest, always `1`.
+    pub schema_version: u32,
+    #[serde(default)]
+    /// Repository name of the image.
+    pub name: String,
+    #[serde(default)]
+    /// Tag the manifest was pushed under.
+    pub tag: String,
+    #[serde(default)]
+    /// CPU architecture of the image.
+    pub architecture: String,
+    #[serde(rename = "fsLayers")]
+    /// Layer blobs, top-most first.
+    pub fs_layers: Vec<FsLayer>,
+    /// Legacy image configs matching `fs_layers`.
+    pub history: Vec<V1History>,
+}
+
+/// Layer reference inside a schema 1 manifest.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FsLayer {
+    #[serde(rename = "blobSum")]
+    /// Digest of the compressed layer blob.
+    pub blob_sum: String,
+}
+
+/// History entry of a schema 1 manifest.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct V1History {
+    #[serde(rename = "v1Compatibility")]
+    /// JSON encoded legacy image config of the matching layer.
+    pub v1_compatibility: String,
+}
+
+/// Fields of a legacy image config used to rebuild the image history.
+#[derive(Debug, Default, Deserialize)]
+struct V1Compatibility {
+    #[serde(default)]
+    created: String,
+    #[serde(default)]
+    container_config: Option<V1ContainerConfig>,
+    #[serde(default)]
+    throwaway: bool,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct V1ContainerConfig {
+    #[serde(rename = "Cmd", default)]
+    cmd: Option<Vec<String>>,
+}
+
+/// Keys of a legacy image config that have no place in an image config.
+const V1_ONLY_CONFIG_KEYS: &[&str] = &["id", "parent", "Size", "throwaway", "layer_id"];
+
+impl Schema1Manifest {
+    /// Converts the manifest into a Docker schema 2 [`ImageManifest`].
+    ///
+    /// Layers are listed bottom-most first and empty (`throwaway`) layers are
+    /// dropped. Schema 1 manifests do not record layer sizes, so the sizes of
+    /// the returned layers are `0` and must be filled in by the caller. The
+    /// image config is rebuilt from the top-most legacy config and embedded in
+    /// the config descriptor's `data`; its `rootfs.diff_ids` are left empty as
+    /// they are only known after decompressing the layers.
+    pub fn to_image_manifest(&self) -> serde_json::Result<ImageManifest> {
+        let mut layers = Vec::new();
+        let mut history = Vec::new();
+        for (fs_layer, entry) in self.fs_layers.iter().zip(&self.history).rev() {
+            let v1: V1Compatibility = serde_json::from_str(&entry.v1_compatibility)?;
+            let created_by = v1
+                .container_config
+                .and_then(|config| config.cmd)
+                .map(|cmd| cmd.join(" "))
+                .unwrap_or_default();
+            history.push(HistoryEntry {
+                created: v1.created,
+                created_by,
+                empty_layer: v1.throwaway,
+                comment: v1.comment,
+            });
+            if !v1.throwaway {
+                layers.push(Descriptor {
+                    digest: fs_layer.blob_sum.clone(),
+                    media_type: DOCKER_LAYER_MEDIA_TYPE.to_string(),
+                    size: 0,
+                    data: None,
+                    annotations: None,
+                });
+            }
+        }
+
+        let mut config = match self.history.first() {
+            Some(entry) => serde_json::from_str(&entry.v1_compatibility)?,
+            None => serde_json::Value::Object(Default::default()),
+        };
+        if let Some(config) = config.as_object_mut() {
+            for key in V1_ONLY_CONFIG_KEYS {
+                config.remove(*key);
+            }
+            config
+                .entry("architecture")
+                .or_insert_with(|| self.architecture.clone().into());
+            config.insert("history".to_string(), serde_json::to_value(&history)?);
+            let rootfs = RootFs {
+                fs_type: "layers".to_string(),
+                diff_ids: Vec::new(),
+            };
+            config.insert("rootfs".to_string(), serde_json::to_value(&rootfs)?);
+        }
+        let config = serde_json::to_vec(&config)?;
+
+        Ok(ImageManifest {
+            schema_version: 2,
+            media_type: DOCKER_MANIFEST_MEDIA_TYPE.to_string(),
+            config: Descriptor {
+                digest: sha256_digest(&config),
+                media_type: DOCKER_CONFIG_MEDIA_TYPE.to_string(),
+                size: config.len() as u64,
+                data: Some(STANDARD.encode(&config)),
+                annotations: None,
+            },
+            layers,
+            annotations: None,
+        })
+    }
+}
+
+/// Protected header of a schema 1 manifest signature, telling how to rebuild
+/// the payload that was signed.
+#[derive(Deserialize)]
+struct Schema1Protected {
+    #[serde(rename = "formatLength")]
+    format_length: usize,
+    #[serde(rename = "formatTail")]
+    format_tail: String,
+}
+
+/// Returns the payload of a schema 1 manifest, over which its digest is
+/// computed: a signed manifest without the `signatures` spliced into it.
+/// Unsigned manifests are their own payload.
+///
+/// Returns `None` when the protected header of a signature is malformed.
+pub fn schema1_payload(body: &[u8]) -> Option<Vec<u8>> {
+    let document: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let Some(signature) = document["signatures"].as_array().and_then(|s| s.first()) else {
+        return Some(body.to_vec());
+    };
+    let protected = URL_SAFE_NO_PAD
+        .decode(signature["protected"].as_str()?.trim_end_matches('='))
+        .ok()?;
+    let protected: Schema1Protected = serde_json::from_slice(&protected).ok()?;
+    let mut payload = body.get(..protected.format_length)?.to_vec();
+    payload.extend(
+        URL_SAFE_NO_PAD
+            .decode(protected.format_tail.trim_end_matches('='))
+            .ok()?,
+    );
+    Some(payload)
+}
+
 /// Runtime configuration extracted from an image config blob.
+///
+/// Only `architecture`, `os` and `rootfs` are required by the image spec, so
+/// the other fields default to empty values.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageConfig {
     /// CPU architecture (for example `amd64` or `arm64`).
     pub architecture: String,
     /// Operating system (for example `linux`).
     pub os: String,
+    #[serde(default)]
     /// Container runtime settings.
     pub config: ContainerConfig,
+    #[serde(default)]
     /// Timestamp when the image was created.
     pub created: String,
+    #[serde(default)]
     /// History describing how the image layers were produced.
     pub history: Vec<HistoryEntry>,
     /// Root filesystem diff IDs.
@@ -127,7 +526,7 @@ pub struct ImageConfig {
 }
 
 /// Container runtime options section inside an image config.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ContainerConfig {
     #[serde(rename = "Hostname")]
     /// Default hostname assigned to containers started from the image.
@@ -177,8 +576,10 @@ pub struct ContainerConfig {
 /// Detailed history line for how an image layer was produced.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoryEntry {
+    #[serde(default)]
     /// Timestamp when the layer was created.
     pub created: String,
+    #[serde(default)]
     /// Command that produced the layer.
     pub created_by: String,
 
@@ -200,3 +601,127 @@ pub struct RootFs {
     /// Digest list representing layer diff IDs.
     pub diff_ids: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_from_json() {
+        let list = br#"{"schemaVersion":2,
+            "mediaType":"application/vnd.docker.distribution.manifest.list.v2+json",
+            "manifests":[{"digest":"sha256:aa","mediaType":"application/vnd.docker.distribution.manifest.v2+json",
+                "size":1,"platform":{"architecture":"amd64","os":"linux"}}]}"#;
+        let Manifest::OCIIndex(list) = Manifest::from_json(list).unwrap() else {
+            panic!("expected an index");
+        };
+        assert_eq!(list.media_type, INDEX_MEDIA_TYPES[1]);
+        assert_eq!(list.manifests.len(), 1);
+
+        let manifest = br#"{"schemaVersion":2,
+            "mediaType":"application/vnd.docker.distribution.manifest.v2+json",
+            "config":{"digest":"sha256:bb","mediaType":"application/vnd.docker.container.image.v1+json","size":2},
+            "layers":[]}"#;
+        let Manifest::OCIManifest(manifest) = Manifest::from_json(manifest).unwrap() else {
+            panic!("expected a manifest");
+        };
+        assert_eq!(manifest.media_type, DOCKER_MANIFEST_MEDIA_TYPE);
+
+        // `mediaType` is optional in OCI documents
+        let index = br#"{"schemaVersion":2,"manifests":[]}"#;
+        assert!(matches!(
+            Manifest::from_json(index),
+            Ok(Manifest::OCIIndex(_))
+        ));
+        let unknown = br#"{"schemaVersion":2,"mediaType":"text/html"}"#;
+        assert!(Manifest::from_json(unknown).is_err());
+    }
+
+    #[test]
+    fn test_schema1_to_image_manifest() {
+        let v1 = |value: serde_json::Value| V1History {
+            v1_compatibility: value.to_string(),
+        };
+        let schema1 = Schema1Manifest {
+            schema_version: 1,
+            name: "library/app".to_string(),
+            tag: "v1".to_string(),
+            architecture: "arm64".to_string(),
+            fs_layers: ["sha256:empty", "sha256:top", "sha256:base"]
+                .map(|blob_sum| FsLayer {
+                    blob_sum: blob_sum.to_string(),
+                })
+                .to_vec(),
+            history: vec![
+                v1(serde_json::json!({
+                    "id": "3", "parent": "2", "os": "linux", "throwaway": true,
+                    "config": {"Cmd": ["sh"]},
+                    "container_config": {"Cmd": ["/bin/sh", "-c", "#(nop) CMD [\"sh\"]"]},
+                })),
+                v1(serde_json::json!({"id": "2", "parent": "1", "created": "2020-01-02"})),
+                v1(serde_json::json!({"id": "1", "created": "2020-01-01"})),
+            ],
+        };
+
+        let manifest = schema1.to_image_manifest().unwrap();
+        assert_eq!(manifest.media_type, DOCKER_MANIFEST_MEDIA_TYPE);
+        let layers: Vec<_> = manifest.layers.iter().map(|l| l.digest.as_str()).collect();
+        assert_eq!(layers, ["sha256:base", "sha256:top"]);
+        assert!(
+            manifest
+                .layers
+                .iter()
+                .all(|l| l.media_type == DOCKER_LAYER_MEDIA_TYPE)
+        );
+
+        let config = STANDARD
+            .decode(manifest.config.data.as_ref().unwrap())
+            .unwrap();
+        assert_eq!(manifest.config.digest, sha256_digest(&config));
+        assert_eq!(manifest.config.size, config.len() as u64);
+        let config: serde_json::Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["architecture"], "arm64");
+        assert_eq!(config["config"]["Cmd"][0], "sh");
+        assert!(config.get("id").is_none() && config.get("parent").is_none());
+        let history = config["history"].as_array().unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0]["created"], "2020-01-01");
+        assert_eq!(history[2]["empty_layer"], true);
+        assert_eq!(config["rootfs"]["type"], "layers");
+    }
+
+    #[test]
+    fn test_schema1_payload() {
+        let payload = b"{\n   \"schemaVersion\": 1,\n   \"name\": \"library/app\"\n}";
+        assert_eq!(schema1_payload(payload).unwrap(), payload);
+
+        let format_length = payload.len() - 2;
+        let protected = URL_SAFE_NO_PAD.encode(format!(
+            r#"{{"formatLength":{format_length},"formatTail":"{}"}}"#,
+            URL_SAFE_NO_PAD.encode("\n}")
+        ));
+        let mut signed = payload[..format_length].to_vec();
+        signed.extend(format!(r#","signatures":[{{"protected":"{protected}"}}]"#).as_bytes());
+        signed.extend(b"\n}");
+        assert_eq!(schema1_payload(&signed).unwrap(), payload);
+
+        let garbled = String::from_utf8(signed).unwrap().replace(&protected, "!");
+        assert!(schema1_payload(garbled.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_parse_platform_param() {
+        let platform: PlatformParam = "linux/arm64/v8".parse().unwrap();
+        assert_eq!(platform.os.as_deref(), Some("linux"));
+        assert_eq!(platform.architecture.as_deref(), Some("arm64"));
+        assert_eq!(platform.variant.as_deref(), Some("v8"));
+        assert_eq!(platform.to_string(), "linux/arm64/v8");
+        assert_eq!(
+            "linux/amd64".parse::<PlatformParam>().unwrap().variant,
+            None
+        );
+        for invalid in ["linux", "linux//v8", "linux/arm/v7/x", ""] {
+            assert!(invalid.parse::<PlatformParam>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -1,27 +1,17 @@
-use flate2::read::GzDecoder;
-use std::{
-    fs::File,
-    io::{BufReader, Read, Result},
-};
-use tar::Archive;
-use zstd;
+use std::io::{BufReader, Read};
 
-use std::path::Path;
+use flate2::read::MultiGzDecoder;
 
-pub(crate) fn read_tar_file<P: AsRef<Path>>(archive_path: P) -> Result<Archive<Box<dyn Read>>> {
-    let file = File::open(archive_path)?;
-    let reader = BufReader::new(file);
-    Ok(Archive::new(Box::new(reader)))
-}
-
-pub(crate) fn read_gzip_file<P: AsRef<Path>>(archive_path: P) -> Result<Archive<Box<dyn Read>>> {
-    let file = File::open(archive_path)?;
-    let decoder = GzDecoder::new(file);
-    Ok(Archive::new(Box::new(decoder)))
-}
+use super::image_reader::{ImageReaderError, Result};
 
-pub(crate) fn read_zstd_file<P: AsRef<Path>>(archive_path: P) -> Result<Archive<Box<dyn Read>>> {
-    let file = File::open(archive_path)?;
-    let decoder = zstd::Decoder::new(file)?;
-    Ok(Archive::new(Box::new(decoder)))
+/// Wraps a layer blob in the decoder for its `file_type`, yielding its tar
+/// stream.
+pub(crate) fn decompress<'a, R: Read + 'a>(blob: R, file_type: &str) -> Result<Box<dyn Read + 'a>> {
+    Ok(match file_type {
+        "tar" => Box::new(BufReader::new(blob)),
+        // eStargz and other seekable layers concatenate many gzip members
+        "gzip" => Box::new(MultiGzDecoder::new(blob)),
+        "zstd" => Box::new(zstd::Decoder::new(blob)?),
+        _ => return Err(ImageReaderError::UnsupportedFileType(file_type.to_string())),
+    })
 }
@@ -2,9 +2,11 @@
 
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Component, Path};
 use std::rc::{Rc, Weak};
 
+use super::symlink;
+
 /// Node within a directory tree backed by reference-counted pointers.
 #[derive(Debug)]
 pub struct TreeNode {
@@ -12,6 +14,10 @@ pub struct TreeNode {
     pub children: RefCell<HashMap<String, Rc<TreeNode>>>,
     pub parent: RefCell<Weak<TreeNode>>,
     pub is_dir: bool,
+    /// Kind of special files such as device nodes, printed after the name.
+    pub label: Option<String>,
+    /// Target of symbolic links.
+    pub symlink: Option<String>,
 }
 
 impl TreeNode {
@@ -44,11 +50,15 @@ impl TreeNode {
         } else {
             let connector = if is_last { "└── " } else { "├── " };
             println!(
-                "{}{}{}{}",
+                "{}{}{}{}{}",
                 prefix,
                 connector,
                 &self.name,
-                if self.is_dir { "/" } else { "" }
+                if self.is_dir { "/" } else { "" },
+                self.label
+                    .as_ref()
+                    .map(|label| format!(" ({label})"))
+                    .unwrap_or_default()
             );
             format!("{}{}", prefix, if is_last { "    " } else { "│   " })
         };
@@ -84,14 +94,29 @@ impl DirectoryTree {
                 children: RefCell::new(HashMap::new()),
                 parent: RefCell::new(Weak::new()),
                 is_dir: true,
+                label: None,
+                symlink: None,
             }),
         }
     }
 
     /// Inserts a new path into the tree, creating intermediate directories as needed.
     pub fn add_path<P: AsRef<Path>>(&self, path: P, is_dir: bool) {
+        self.insert(path.as_ref(), is_dir, None, None);
+    }
+
+    /// Inserts a symbolic link pointing at `target`.
+    pub fn add_symlink<P: AsRef<Path>>(&self, path: P, target: String) {
+        self.insert(path.as_ref(), false, None, Some(target));
+    }
+
+    /// Inserts a special file, such as a device node, described by `label`.
+    pub fn add_labelled_path<P: AsRef<Path>>(&self, path: P, label: String) {
+        self.insert(path.as_ref(), false, Some(label), None);
+    }
+
+    fn insert(&self, path: &Path, is_dir: bool, label: Option<String>, symlink: Option<String>) {
         let mut components: Vec<_> = path
-            .as_ref()
             .components()
             .filter_map(|c| c.as_os_str().to_str())
             .collect();
@@ -119,6 +144,8 @@ impl DirectoryTree {
                             children: RefCell::new(HashMap::new()),
                             parent: RefCell::new(Rc::downgrade(&current)),
                             is_dir: index != last_index || is_dir,
+                            label: label.clone().filter(|_| index == last_index),
+                            symlink: symlink.clone().filter(|_| index == last_index),
                         })
                     }),
             );
@@ -128,28 +155,31 @@ impl DirectoryTree {
     }
 
     /// Finds a node inside the tree by path returning a shared pointer to it.
+    ///
+    /// Symbolic links along the path are followed, so that `/lib/apk` is
+    /// found when `/lib` links to `usr/lib`. Returns `None` for paths running
+    /// into a symlink loop.
     pub fn find(&self, path: &str) -> Option<Rc<TreeNode>> {
-        if path.eq("/") {
-            return Some(Rc::clone(&self.root));
-        }
-        let components: Vec<_> = path.trim_matches('/').split('/').collect();
-        if components.is_empty() {
-            return None;
-        }
+        let resolved = symlink::resolve(Path::new(path), true, |path| {
+            self.node(path)?.symlink.clone()
+        })?;
+        self.node(&resolved)
+    }
 
+    /// Returns the node at `path` without following symbolic links.
+    fn node(&self, path: &Path) -> Option<Rc<TreeNode>> {
         let mut current = Rc::clone(&self.root);
-        for component in components {
-            let node = current.children.borrow().get(component).map(Rc::clone);
-            match node {
-                Some(node) => {
-                    current = node;
-                }
-                None => {
-                    return None;
-                }
-            }
+        for component in path.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            let node = current
+                .children
+                .borrow()
+                .get(name.to_str()?)
+                .map(Rc::clone)?;
+            current = node;
         }
-
         Some(current)
     }
 
@@ -177,4 +207,20 @@ mod tests {
         let node = tree.find(path).unwrap();
         assert_eq!(node.pwd(true), path);
     }
+
+    #[test]
+    fn test_find_through_symlinks() {
+        let tree = DirectoryTree::new();
+        tree.add_path("usr/lib/apk", true);
+        tree.add_symlink("lib", "usr/lib".to_string());
+        tree.add_symlink("usr/lib/self", "/lib".to_string());
+        tree.add_symlink("loop", "loop".to_string());
+        assert_eq!(tree.find("/lib/apk").unwrap().pwd(true), "/usr/lib/apk");
+        assert_eq!(
+            tree.find("/lib/self/self/apk").unwrap().pwd(true),
+            "/usr/lib/apk"
+        );
+        assert!(tree.find("/loop").is_none());
+        assert!(tree.find("/lib/missing").is_none());
+    }
 }
@@ -0,0 +1,222 @@
+//! Layers, images and registries shared by the tests of the reader and the
+//! registry client.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use super::toc::{
+    ESTARGZ_TOC_DIGEST_ANNOTATION, ESTARGZ_TOC_NAME, ZSTD_CHUNKED_MANIFEST_CHECKSUM_ANNOTATION,
+    ZSTD_CHUNKED_MANIFEST_POSITION_ANNOTATION, estargz_footer,
+};
+use crate::digest::sha256_digest;
+use crate::manifest::{Descriptor, ImageManifest};
+use crate::registry::mock::{MockRegistry, MockResponse};
+
+pub(crate) const TAR_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+pub(crate) const GZIP_LAYER: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+pub(crate) const ZSTD_LAYER: &str = "application/vnd.oci.image.layer.v1.tar+zstd";
+
+/// Entry of a [`tar_layer`].
+pub(crate) enum Entry<'a> {
+    Dir(&'a str),
+    File(&'a str, &'a [u8]),
+    Symlink(&'a str, &'a str),
+    Hardlink(&'a str, &'a str),
+    /// Entry with a prepared header, for the types and fields the other
+    /// variants do not cover.
+    Raw(&'a str, Box<tar::Header>, &'a [u8]),
+}
+
+/// Builds an uncompressed layer holding `entries`. Names and link targets are
+/// stored as given, including the `./` or `/` prefixes the builder of the tar
+/// crate would strip.
+pub(crate) fn tar_layer(entries: &[Entry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        let (path, data, link_name): (&str, &[u8], &str) = match *entry {
+            Entry::Raw(path, ref raw, data) => {
+                header = (**raw).clone();
+                (path, data, "")
+            }
+            Entry::Dir(path) => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                (path, b"", "")
+            }
+            Entry::File(path, data) => {
+                header.set_mode(0o644);
+                (path, data, "")
+            }
+            Entry::Symlink(path, target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o777);
+                (path, b"", target)
+            }
+            Entry::Hardlink(path, target) => {
+                header.set_entry_type(tar::EntryType::Link);
+                header.set_mode(0o644);
+                (path, b"", target)
+            }
+        };
+        let gnu = header.as_gnu_mut().unwrap();
+        gnu.name[..path.len()].copy_from_slice(path.as_bytes());
+        gnu.linkname[..link_name.len()].copy_from_slice(link_name.as_bytes());
+        if !matches!(entry, Entry::Raw(..)) {
+            header.set_size(data.len() as u64);
+        }
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Describes `blob` as content of `media_type`.
+pub(crate) fn descriptor(media_type: &str, blob: &[u8]) -> Descriptor {
+    Descriptor {
+        media_type: media_type.to_string(),
+        digest: sha256_digest(blob),
+        size: blob.len() as u64,
+        data: None,
+        annotations: None,
+    }
+}
+
+/// Builds an eStargz layer holding `files`, returning the blob and its
+/// annotations. Names ending with `/` are directories. The blob starts with
+/// bytes that are not gzip at all, as readers following the TOC never look
+/// at them.
+pub(crate) fn estargz_layer(files: &[(&str, &[u8])]) -> (Vec<u8>, HashMap<String, String>) {
+    let mut blob = b"not gzip".to_vec();
+    let entries: Vec<serde_json::Value> = files
+        .iter()
+        .map(|(name, content)| {
+            if name.ends_with('/') {
+                return serde_json::json!({"name": name, "type": "dir"});
+            }
+            let offset = blob.len();
+            blob.extend(gzip(content));
+            serde_json::json!({
+                "name": name,
+                "type": "reg",
+                "size": content.len(),
+                "offset": offset,
+                "chunkDigest": sha256_digest(content),
+            })
+        })
+        .collect();
+    let toc = serde_json::to_vec(&serde_json::json!({"version": 1, "entries": entries})).unwrap();
+
+    let member = tar_layer(&[Entry::File(ESTARGZ_TOC_NAME, &toc)]);
+    let toc_offset = blob.len() as u64;
+    blob.extend(gzip(&member));
+    blob.extend(estargz_footer(toc_offset));
+    let annotations = HashMap::from([(
+        ESTARGZ_TOC_DIGEST_ANNOTATION.to_string(),
+        sha256_digest(&toc),
+    )]);
+    (blob, annotations)
+}
+
+/// Builds a zstd:chunked layer holding `files`, returning the blob and its
+/// annotations. Like [`estargz_layer`] the blob starts with junk.
+pub(crate) fn zstd_chunked_layer(files: &[(&str, &[u8])]) -> (Vec<u8>, HashMap<String, String>) {
+    let mut blob = b"not zstd".to_vec();
+    let entries: Vec<serde_json::Value> = files
+        .iter()
+        .map(|(name, content)| {
+            let offset = blob.len();
+            blob.extend(zstd::encode_all(*content, 0).unwrap());
+            serde_json::json!({
+                "name": name,
+                "type": "reg",
+                "size": content.len(),
+                "offset": offset,
+                "endOffset": blob.len(),
+            })
+        })
+        .collect();
+    let toc = serde_json::to_vec(&serde_json::json!({"version": 1, "entries": entries})).unwrap();
+    let manifest = zstd::encode_all(&toc[..], 0).unwrap();
+    let position = format!("{}:{}:{}:1", blob.len(), manifest.len(), toc.len());
+    let annotations = HashMap::from([
+        (
+            ZSTD_CHUNKED_MANIFEST_CHECKSUM_ANNOTATION.to_string(),
+            sha256_digest(&manifest),
+        ),
+        (
+            ZSTD_CHUNKED_MANIFEST_POSITION_ANNOTATION.to_string(),
+            position,
+        ),
+    ]);
+    blob.extend(manifest);
+    (blob, annotations)
+}
+
+/// Builds a single-platform image for `os` made of `layers`, returning its
+/// manifest and blobs keyed by digest.
+pub(crate) fn image(
+    os: &str,
+    layers: Vec<(Descriptor, Vec<u8>)>,
+) -> (Vec<u8>, HashMap<String, Vec<u8>>) {
+    let config = format!(
+        r#"{{"architecture":"amd64","os":"{os}","rootfs":{{"type":"layers","diff_ids":[]}}}}"#
+    )
+    .into_bytes();
+    let config_descriptor = descriptor("application/vnd.oci.image.config.v1+json", &config);
+    let mut blobs = HashMap::from([(config_descriptor.digest.clone(), config)]);
+    let mut descriptors = Vec::new();
+    for (descriptor, blob) in layers {
+        blobs.insert(descriptor.digest.clone(), blob);
+        descriptors.push(descriptor);
+    }
+    let manifest = serde_json::to_vec(&ImageManifest {
+        schema_version: 2,
+        media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+        config: config_descriptor,
+        layers: descriptors,
+        annotations: None,
+    })
+    .unwrap();
+    (manifest, blobs)
+}
+
+/// Serves manifests keyed by request path and blobs keyed by digest, honouring
+/// range requests for blobs.
+pub(crate) async fn serve_images(
+    manifests: HashMap<String, Vec<u8>>,
+    blobs: HashMap<String, Vec<u8>>,
+) -> MockRegistry {
+    MockRegistry::start(move |req| {
+        if let Some(manifest) = manifests.get(&req.path) {
+            let media_type =
+                serde_json::from_slice::<serde_json::Value>(manifest).unwrap()["mediaType"]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
+            return MockResponse::new(200)
+                .header("Content-Type", &media_type)
+                .header("Docker-Content-Digest", &sha256_digest(manifest))
+                .body(manifest.clone());
+        }
+        let digest = req.path.rsplit_once("/blobs/").map(|(_, digest)| digest);
+        match digest.and_then(|digest| blobs.get(digest)) {
+            Some(blob) => MockResponse::ranged(req, blob),
+            None => MockResponse::new(404),
+        }
+    })
+    .await
+}
+
+/// Serves `test/app:v1`, a single-platform image made of `layers`.
+pub(crate) async fn serve_image(layers: Vec<(Descriptor, Vec<u8>)>) -> MockRegistry {
+    let (manifest, blobs) = image("linux", layers);
+    let manifests = HashMap::from([("/v2/test/app/manifests/v1".to_string(), manifest)]);
+    serve_images(manifests, blobs).await
+}
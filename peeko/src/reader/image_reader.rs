@@ -1,13 +1,28 @@
-use std::io::Read;
+use std::collections::{HashMap, VecDeque};
+use std::io::SeekFrom;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 use super::archive_utils;
 use super::dir_tree::DirectoryTree;
+use super::index_cache::{self, IndexCache};
+use super::layer_index::{self, DataLocation, LayerIndex};
+use super::remote::RemoteLayers;
+use super::seek_index::{self, SeekIndex};
+use super::toc::{self, TocFormat};
 use super::vfs::{FileEntry, VirtualFileSystem};
-use crate::manifest::{ImageManifest, get_file_type};
+use crate::fs::ImageLayout;
+use crate::fs::layout::{INDEX_FILE, LEGACY_MANIFEST_FILE, oci_blob_path};
+use crate::manifest::{
+    Descriptor, IMAGE_MANIFEST_MEDIA_TYPES, INDEX_MEDIA_TYPES, ImageConfig, ImageManifest,
+    ManifestList, PlatformManifest, PlatformParam, get_file_type,
+};
+use crate::reference::ImageReference;
+use crate::registry::{RegistryClient, RegistryError};
 
 /// Errors produced when building or using the asynchronous image reader.
 #[derive(Error, Debug)]
@@ -24,176 +39,340 @@ pub enum ImageReaderError {
     #[error("Unsupported file type: {0}")]
     UnsupportedFileType(String),
 
+    #[error("No image manifest found in {0}")]
+    ManifestNotFound(String),
+
+    #[error("No manifest for platform {0}")]
+    PlatformNotFound(String),
+
     #[error("File/Directory not found: {0}")]
     NotFound(String),
 
     #[error("Not a file: {0}")]
     NotAFile(String),
-}
 
-/// Convenient result alias that uses [`ImageReaderError`].
-pub type Result<T> = std::result::Result<T, ImageReaderError>;
+    #[error("Reading sparse files is not supported: {0}")]
+    SparseFile(String),
 
-async fn load_manifest<P: AsRef<Path>>(image_dir: P) -> Result<ImageManifest> {
-    let manifest_path = image_dir.as_ref().join("manifest.json");
-    let manifest = fs::read_to_string(manifest_path).await?;
-    let manifest: ImageManifest = serde_json::from_str(&manifest)?;
-    Ok(manifest)
-}
+    #[error("Hard link {0} points to {1}, which is not a regular file of the image")]
+    BrokenHardlink(String, String),
 
-async fn load_layer<P: AsRef<Path>>(
-    layer_path: P,
-    file_type: &str,
-    layer_index: usize,
-    vfs: &mut VirtualFileSystem,
-) -> Result<()> {
-    let mut archive = match file_type {
-        "tar" => archive_utils::read_tar_file(layer_path)?,
-        "gzip" => archive_utils::read_gzip_file(layer_path)?,
-        "zstd" => archive_utils::read_zstd_file(layer_path)?,
-        _ => return Err(ImageReaderError::UnsupportedFileType(file_type.to_string())),
-    };
+    #[error("Too many levels of symbolic links: {0}")]
+    SymlinkLoop(String),
 
-    for entry in archive.entries()? {
-        let entry = entry?;
-        let path = entry.path()?.to_path_buf();
-        let header = entry.header();
+    #[error("Registry error: {0}")]
+    RegistryError(#[from] RegistryError),
 
-        // 处理 whiteout 文件
-        if let Some(filename) = path.file_name() {
-            let filename_str = filename.to_string_lossy();
+    #[error("Invalid table of contents: {0}")]
+    InvalidToc(String),
+}
 
-            if filename_str.starts_with(".wh.") {
-                if filename_str == ".wh..wh..opq" {
-                    // 删除整个目录内容
-                    if let Some(parent) = path.parent() {
-                        vfs.clear_directory(parent);
-                    }
-                } else {
-                    // 删除特定文件
-                    let target_name = filename_str.strip_prefix(".wh.").unwrap();
-                    if let Some(parent) = path.parent() {
-                        let target_path = parent.join(target_name);
-                        vfs.delete_entry(&target_path);
+/// Convenient result alias that uses [`ImageReaderError`].
+pub type Result<T> = std::result::Result<T, ImageReaderError>;
+
+/// Loads the image manifest of `image_dir` for `platform`, detecting its
+/// storage layout.
+async fn load_manifest(
+    image_dir: &Path,
+    platform: &PlatformParam,
+) -> Result<(ImageLayout, ImageManifest)> {
+    let not_found = || ImageReaderError::ManifestNotFound(image_dir.to_string_lossy().to_string());
+    let layout = ImageLayout::detect(image_dir).ok_or_else(not_found)?;
+
+    let manifest = match layout {
+        ImageLayout::Oci => {
+            let index = fs::read_to_string(image_dir.join(INDEX_FILE)).await?;
+            let index: ManifestList = serde_json::from_str(&index)?;
+            let descriptor = find_manifest(image_dir, index, platform)
+                .await?
+                .ok_or_else(|| {
+                    if *platform == PlatformParam::default() {
+                        not_found()
+                    } else {
+                        ImageReaderError::PlatformNotFound(platform.to_string())
                     }
-                }
-                continue;
-            }
+                })?;
+            fs::read_to_string(oci_blob_path(image_dir, &descriptor.digest)).await?
         }
+        ImageLayout::Legacy => fs::read_to_string(image_dir.join(LEGACY_MANIFEST_FILE)).await?,
+    };
+    let manifest: ImageManifest = serde_json::from_str(&manifest)?;
+    Ok((layout, manifest))
+}
 
-        match header.entry_type() {
-            tar::EntryType::Regular => vfs.add_entry(
-                path,
-                FileEntry::File {
-                    size: entry.size(),
-                    layer_index,
-                },
-            ),
-            tar::EntryType::Directory => vfs.add_entry(path, FileEntry::Directory { layer_index }),
-            tar::EntryType::Symlink | tar::EntryType::Link => {
-                if let Ok(Some(link_name)) = header.link_name() {
-                    vfs.add_entry(
-                        path,
-                        FileEntry::Symlink {
-                            target: link_name.to_string_lossy().to_string(),
-                            layer_index,
-                        },
-                    );
-                }
-            }
-            _ => {}
+/// Finds the image manifest for `platform` in an OCI layout's `index.json`,
+/// descending into the multi-platform indexes it references.
+async fn find_manifest(
+    image_dir: &Path,
+    index: ManifestList,
+    platform: &PlatformParam,
+) -> Result<Option<PlatformManifest>> {
+    let mut entries = VecDeque::from(index.manifests);
+    while let Some(entry) = entries.pop_front() {
+        if INDEX_MEDIA_TYPES.contains(&entry.media_type.as_str()) {
+            let nested = fs::read(oci_blob_path(image_dir, &entry.digest)).await?;
+            let nested: ManifestList = serde_json::from_slice(&nested)?;
+            entries.extend(nested.manifests);
+        } else if IMAGE_MANIFEST_MEDIA_TYPES.contains(&entry.media_type.as_str())
+            && platform.matches_entry(&entry)
+        {
+            return Ok(Some(entry));
         }
     }
-    Ok(())
+    Ok(None)
 }
 
-async fn read_file_from_layer<LP: AsRef<Path>, FP: AsRef<Path>>(
-    layer_path: LP,
-    file_type: &str,
-    file_path: FP,
-) -> Result<Vec<u8>> {
-    let layer_path = layer_path.as_ref();
-    let file_path = file_path.as_ref();
-
-    let mut archive = match file_type {
-        "tar" => archive_utils::read_tar_file(layer_path)?,
-        "gzip" => archive_utils::read_gzip_file(layer_path)?,
-        "zstd" => archive_utils::read_zstd_file(layer_path)?,
-        _ => return Err(ImageReaderError::UnsupportedFileType(file_type.to_string())),
-    };
+/// Indexes a layer blob on disk, from its table of contents when it is an
+/// eStargz or zstd:chunked layer.
+async fn load_layer(
+    layer_path: &Path,
+    layer: &Descriptor,
+    layer_index: usize,
+) -> Result<LayerIndex> {
+    let read_blob = async |range| read_blob_range(layer_path, range).await;
+    if let Some(format) = TocFormat::detect(layer) {
+        return Ok(LayerIndex {
+            changes: toc::read_toc(&format, layer, layer_index, read_blob).await?,
+            seek: None,
+        });
+    }
 
-    let target = archive.entries()?.find(|entry| match entry {
-        Ok(entry) => entry.path().is_ok_and(|path| path.eq(file_path)),
-        Err(_) => false,
-    });
-
-    match target {
-        Some(Ok(mut entry)) => {
-            let mut buf = Vec::new();
-            match entry.read_to_end(&mut buf) {
-                Ok(_) => Ok(buf),
-                Err(err) => Err(err.into()),
-            }
-        }
-        Some(Err(err)) => Err(err.into()),
-        None => Err(ImageReaderError::NotFound(
-            file_path.to_string_lossy().to_string(),
-        )),
+    let file_type = get_file_type(&layer.media_type);
+    let blob = std::fs::File::open(layer_path)?;
+    let mut index = layer_index::index_blob(blob, file_type, layer_index)?;
+    if file_type == "zstd" {
+        index.seek = seek_index::zstd_seek_table(layer.size, read_blob).await?;
     }
+    Ok(index)
+}
+
+/// Reads `range` of a blob on disk.
+async fn read_blob_range(path: &Path, range: Range<u64>) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let mut data = vec![0; range.end.saturating_sub(range.start) as usize];
+    file.read_exact(&mut data).await?;
+    Ok(data)
 }
 
-/// Constructs an `ImageReader` from an OCI image directory on disk.
+/// Constructs an `ImageReader` from an image directory on disk.
 ///
-/// The directory must contain a `manifest.json` and all layer blobs (named
-/// `<digest>.<extension>`). Layers are replayed in order to build the virtual
-/// filesystem that powers the reader.
+/// The directory must be an OCI image layout (`oci-layout`, `index.json` and
+/// `blobs/`) or use the legacy format with a `manifest.json` and layer blobs
+/// named `<digest>.<extension>`. Layers are replayed in order to build the
+/// virtual filesystem that powers the reader.
+///
+/// Images stored in a downloads directory share an index of each layer's
+/// files next to the blob store, so only the first open of a layer reads it.
+///
+/// When the layout holds several platforms the first one is opened; use
+/// [`build_platform_image_reader`] to pick another.
 pub async fn build_image_reader<P: AsRef<Path>>(image_dir: P) -> Result<ImageReader> {
+    build_platform_image_reader(image_dir, &PlatformParam::default()).await
+}
+
+/// Constructs an `ImageReader` for the `platform` build stored in a
+/// multi-platform OCI image layout, such as one written by
+/// [`RegistryClient::download_all_platforms`](crate::registry::RegistryClient::download_all_platforms).
+pub async fn build_platform_image_reader<P: AsRef<Path>>(
+    image_dir: P,
+    platform: &PlatformParam,
+) -> Result<ImageReader> {
     let image_dir = image_dir.as_ref();
-    let manifest = load_manifest(image_dir).await?;
+    let (layout, manifest) = load_manifest(image_dir, platform).await?;
+    // best effort: a read-only image directory is still readable
+    let _ = crate::fs::mark_image_used(image_dir);
 
-    let mut vfs = VirtualFileSystem::new();
+    let cache = IndexCache::for_image(image_dir);
+    let mut layers = Vec::with_capacity(manifest.layers.len());
     for (layer_index, layer) in manifest.layers.iter().enumerate() {
-        let file_type = get_file_type(&layer.media_type);
-        let layer_path = image_dir.join(format!("{}.{}", layer.digest, file_type));
-        load_layer(layer_path, file_type, layer_index, &mut vfs).await?;
+        let layer_path = layout.blob_path(image_dir, &layer.digest, &layer.media_type);
+        let index = index_cache::load_or_index(cache.as_ref(), layer, layer_index, async || {
+            load_layer(&layer_path, layer, layer_index).await
+        })
+        .await?;
+        layers.push(index);
     }
 
-    Ok(ImageReader {
+    let source = LayerSource::Local {
         image_dir: image_dir.to_path_buf(),
-        manifest,
-        vfs,
-    })
+        layout,
+    };
+    Ok(ImageReader::new(source, manifest, layers))
+}
+
+/// Constructs an `ImageReader` for an image that stays in its registry.
+///
+/// Only the manifest, the config and what is needed to list the layers are
+/// fetched: layers carrying an eStargz or zstd:chunked table of contents
+/// are listed from it alone, other layers are streamed once without being
+/// stored. [`ImageReader::read_file`] then fetches file contents on demand
+/// with HTTP range requests.
+///
+/// `platform` picks the manifest of a multi-platform image, as in
+/// [`RegistryClient::download_image`].
+pub async fn build_remote_image_reader(
+    client: RegistryClient,
+    image: &ImageReference,
+    platform: Option<&PlatformParam>,
+) -> Result<ImageReader> {
+    let remote = client.inspect_image(image, platform).await?;
+    let source = RemoteLayers::new(client, &image.repository);
+    let layers = source.index_layers(&remote.manifest.layers).await?;
+    Ok(ImageReader::new(
+        LayerSource::Remote(Box::new(source)),
+        remote.manifest,
+        layers,
+    ))
+}
+
+/// Where an [`ImageReader`] reads its layers from.
+enum LayerSource {
+    /// Blobs of an image directory.
+    Local {
+        image_dir: PathBuf,
+        layout: ImageLayout,
+    },
+    /// Blobs left in the registry.
+    Remote(Box<RemoteLayers>),
+}
+
+/// Reads the manifest and config of the `platform` build stored in
+/// `image_dir` without replaying its layers.
+pub async fn load_image_config<P: AsRef<Path>>(
+    image_dir: P,
+    platform: &PlatformParam,
+) -> Result<(ImageManifest, ImageConfig)> {
+    let image_dir = image_dir.as_ref();
+    let (layout, manifest) = load_manifest(image_dir, platform).await?;
+    let config = &manifest.config;
+    let config = fs::read(layout.blob_path(image_dir, &config.digest, &config.media_type)).await?;
+    let config: ImageConfig = serde_json::from_slice(&config)?;
+    Ok((manifest, config))
 }
 
 /// Provides filesystem-style access to an OCI image's layers and metadata.
 pub struct ImageReader {
-    image_dir: PathBuf,
+    source: LayerSource,
     manifest: ImageManifest,
     vfs: VirtualFileSystem,
+    /// Where the content of each file is stored in its layer.
+    locations: HashMap<PathBuf, DataLocation>,
+    /// Where decompression of each layer can start.
+    seek_indexes: Vec<Option<SeekIndex>>,
 }
 
 impl ImageReader {
-    /// Reads the raw bytes of a file inside the reconstructed filesystem.
+    /// Builds the virtual filesystem by replaying the changes of each layer
+    /// in order.
+    fn new(source: LayerSource, manifest: ImageManifest, layers: Vec<LayerIndex>) -> Self {
+        let mut vfs = VirtualFileSystem::new();
+        let mut locations = HashMap::new();
+        let mut seek_indexes = Vec::with_capacity(layers.len());
+        for layer in layers {
+            layer_index::apply_changes(&mut vfs, &mut locations, layer.changes);
+            seek_indexes.push(layer.seek);
+        }
+        Self {
+            source,
+            manifest,
+            vfs,
+            locations,
+            seek_indexes,
+        }
+    }
+
+    /// Reads the raw bytes of a file inside the reconstructed filesystem,
+    /// following symbolic links within the image root.
     ///
-    /// Returns an error when the path does not exist or addresses a directory.
+    /// Returns an error when the path does not exist, addresses a directory
+    /// or runs into a symlink loop.
     pub async fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
-        let path = path.as_ref();
+        let path = self.resolve_path(path.as_ref(), true)?;
+        self.read_entry(&path).await
+    }
+
+    /// Reads the raw bytes of a file like [`read_file`](Self::read_file),
+    /// except that a symbolic link at `path` itself is not followed.
+    pub async fn read_file_no_follow<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        let path = self.resolve_path(path.as_ref(), false)?;
+        self.read_entry(&path).await
+    }
+
+    /// Resolves the symbolic links along `path`, see
+    /// [`VirtualFileSystem::resolve_path`].
+    pub fn resolve_path(&self, path: &Path, follow_last: bool) -> Result<PathBuf> {
+        self.vfs
+            .resolve_path(path, follow_last)
+            .ok_or_else(|| ImageReaderError::SymlinkLoop(path.to_string_lossy().to_string()))
+    }
+
+    /// Reads the file at the resolved `path`.
+    async fn read_entry(&self, path: &Path) -> Result<Vec<u8>> {
         let entry = self
             .vfs
             .get_entry(path)
             .ok_or_else(|| ImageReaderError::NotFound(path.to_string_lossy().to_string()))?;
-        if let FileEntry::File { layer_index, .. } = entry {
-            let layer = &self.manifest.layers[*layer_index];
-            let file_type = get_file_type(&layer.media_type);
-            let layer_path = self
-                .image_dir
-                .join(format!("{}.{}", layer.digest, file_type));
-            let content = read_file_from_layer(&layer_path, file_type, path).await?;
-            Ok(content)
-        } else {
-            Err(ImageReaderError::NotAFile(
+        let (FileEntry::File {
+            size, layer_index, ..
+        }
+        | FileEntry::Hardlink {
+            size, layer_index, ..
+        }) = entry
+        else {
+            match entry {
+                FileEntry::Sparse { .. } => {
+                    return Err(ImageReaderError::SparseFile(
+                        path.to_string_lossy().to_string(),
+                    ));
+                }
+                FileEntry::Symlink { target, .. } => {
+                    return Err(ImageReaderError::NotAFile(format!(
+                        "{} (symbolic link to {target})",
+                        path.display()
+                    )));
+                }
+                _ => {}
+            }
+            return Err(ImageReaderError::NotAFile(
                 path.to_string_lossy().to_string(),
-            ))
+            ));
+        };
+        let layer = &self.manifest.layers[*layer_index];
+        let location = self.locations.get(path).ok_or_else(|| match entry {
+            // the target is missing or has no content to share
+            FileEntry::Hardlink { target, .. } => {
+                ImageReaderError::BrokenHardlink(path.to_string_lossy().to_string(), target.clone())
+            }
+            _ => ImageReaderError::NotFound(path.to_string_lossy().to_string()),
+        })?;
+        let seek = self.seek_indexes[*layer_index].as_ref();
+        match &self.source {
+            LayerSource::Local { image_dir, layout } => {
+                let layer_path = layout.blob_path(image_dir, &layer.digest, &layer.media_type);
+                let read_blob = async |range| read_blob_range(&layer_path, range).await;
+                match location {
+                    DataLocation::Chunks(chunks) => {
+                        let format = TocFormat::detect(layer)
+                            .ok_or_else(|| ImageReaderError::InvalidToc(layer.digest.clone()))?;
+                        toc::read_chunks(&format, chunks, *size, read_blob).await
+                    }
+                    DataLocation::Tar { offset } => {
+                        let range = *offset..offset + size;
+                        if let Some(content) =
+                            seek_index::read_layer_range(layer, seek, range, read_blob).await?
+                        {
+                            return Ok(content);
+                        }
+                        // without a seek index the layer is decompressed up to the file
+                        let blob = std::fs::File::open(&layer_path)?;
+                        let stream =
+                            archive_utils::decompress(blob, get_file_type(&layer.media_type))?;
+                        Ok(seek_index::read_range(stream, *offset, *size)?)
+                    }
+                }
+            }
+            LayerSource::Remote(remote) => remote.read_file(layer, location, seek, *size).await,
         }
     }
 
@@ -225,8 +404,16 @@ impl ImageReader {
         }
     }
 
-    /// Returns metadata associated with a path in the virtual filesystem.
+    /// Returns metadata associated with a path in the virtual filesystem,
+    /// following symbolic links.
     pub fn get_file_metadata(&self, path: &str) -> Option<&FileEntry> {
-        self.vfs.get_entry(PathBuf::from(path))
+        self.vfs.get_entry(self.vfs.resolve_path(path, true)?)
+    }
+
+    /// Returns metadata associated with a path like
+    /// [`get_file_metadata`](Self::get_file_metadata), describing a symbolic
+    /// link at `path` itself rather than its target.
+    pub fn get_symlink_metadata(&self, path: &str) -> Option<&FileEntry> {
+        self.vfs.get_entry(self.vfs.resolve_path(path, false)?)
     }
 }
@@ -0,0 +1,185 @@
+//! On-disk cache of layer indexes shared by the images of a downloads
+//! directory.
+//!
+//! Layers are immutable, so the changes listed for a layer digest stay valid
+//! for every image using it. Reopening an image then only reads one small
+//! file per layer instead of decompressing the layers again.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::image_reader::Result;
+use super::layer_index::{LayerChange, LayerIndex};
+use crate::fs::layout::digest_path;
+use crate::fs::store::{BLOB_STORE_DIR, LAYER_INDEX_DIR};
+use crate::manifest::Descriptor;
+
+/// Version of the cached format, bumped whenever [`LayerIndex`] changes so
+/// that stale indexes are rebuilt instead of misread.
+const INDEX_VERSION: u32 = 6;
+
+/// Index of one layer as stored on disk.
+#[derive(Serialize, Deserialize)]
+struct CachedIndex<C> {
+    version: u32,
+    digest: String,
+    size: u64,
+    layer: C,
+}
+
+/// Layer indexes stored as `<root>/<algorithm>/<hex>.json`.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexCache {
+    root: PathBuf,
+}
+
+impl IndexCache {
+    /// Returns the cache of the downloads directory `oci_dir`, if it holds a
+    /// blob store.
+    pub(crate) fn in_dir(oci_dir: &Path) -> Option<Self> {
+        oci_dir.join(BLOB_STORE_DIR).is_dir().then(|| Self {
+            root: oci_dir.join(LAYER_INDEX_DIR),
+        })
+    }
+
+    /// Returns the cache of the downloads directory `image_dir` is stored in.
+    /// Image directories outside of a downloads directory have none.
+    pub(crate) fn for_image(image_dir: &Path) -> Option<Self> {
+        image_dir.ancestors().skip(1).find_map(Self::in_dir)
+    }
+
+    fn index_path(&self, digest: &str) -> PathBuf {
+        self.root.join(digest_path(digest)).with_extension("json")
+    }
+
+    /// Loads the index cached for `layer`, attributing its entries to
+    /// `layer_index`. Missing, unreadable and mismatching indexes are
+    /// ignored so that the layer is indexed again.
+    pub(crate) async fn load(&self, layer: &Descriptor, layer_index: usize) -> Option<LayerIndex> {
+        let data = fs::read(self.index_path(&layer.digest)).await.ok()?;
+        let index: CachedIndex<LayerIndex> = serde_json::from_slice(&data).ok()?;
+        if index.version != INDEX_VERSION
+            || index.digest != layer.digest
+            || index.size != layer.size
+        {
+            return None;
+        }
+        let mut index = index.layer;
+        for change in &mut index.changes {
+            if let LayerChange::Add { entry, .. } = change {
+                entry.set_layer_index(layer_index);
+            }
+        }
+        Some(index)
+    }
+
+    /// Stores the index of `layer`.
+    pub(crate) async fn store(
+        &self,
+        layer: &Descriptor,
+        index: &LayerIndex,
+    ) -> std::io::Result<()> {
+        let index = CachedIndex {
+            version: INDEX_VERSION,
+            digest: layer.digest.clone(),
+            size: layer.size,
+            layer: index,
+        };
+        let data = serde_json::to_vec(&index)?;
+        let path = self.index_path(&layer.digest);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // written aside first, so that concurrent readers never see half an index
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, data).await?;
+        fs::rename(&tmp_path, &path).await
+    }
+}
+
+/// Returns the index of `layer` from `cache`, or builds it with `index` and
+/// caches it for the next time.
+pub(crate) async fn load_or_index(
+    cache: Option<&IndexCache>,
+    layer: &Descriptor,
+    layer_index: usize,
+    index: impl AsyncFnOnce() -> Result<LayerIndex>,
+) -> Result<LayerIndex> {
+    let Some(cache) = cache else {
+        return index().await;
+    };
+    if let Some(cached) = cache.load(layer, layer_index).await {
+        return Ok(cached);
+    }
+    let built = index().await?;
+    // best effort: the layer is indexed again next time
+    let _ = cache.store(layer, &built).await;
+    Ok(built)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::fixtures::{GZIP_LAYER, descriptor};
+    use crate::reader::layer_index::DataLocation;
+    use crate::reader::vfs::{FileEntry, FileMetadata};
+
+    #[tokio::test]
+    async fn test_index_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let image_dir = dir.path().join("library/alpine/latest/linux-amd64");
+        std::fs::create_dir_all(&image_dir).unwrap();
+        assert!(IndexCache::for_image(&image_dir).is_none());
+        std::fs::create_dir(dir.path().join(BLOB_STORE_DIR)).unwrap();
+        let cache = IndexCache::for_image(&image_dir).unwrap();
+
+        let layer = descriptor(GZIP_LAYER, b"layer");
+        let changes = vec![
+            LayerChange::Add {
+                path: PathBuf::from("etc/hostname"),
+                entry: FileEntry::File {
+                    size: 4,
+                    layer_index: 0,
+                    metadata: FileMetadata::default(),
+                },
+                location: Some(DataLocation::Tar { offset: 512 }),
+            },
+            LayerChange::Whiteout(PathBuf::from("etc/motd")),
+        ];
+        let mut indexed = 0;
+        let mut load = async |layer_index| {
+            load_or_index(Some(&cache), &layer, layer_index, async || {
+                indexed += 1;
+                Ok(LayerIndex {
+                    changes: changes.clone(),
+                    seek: None,
+                })
+            })
+            .await
+            .unwrap()
+            .changes
+        };
+        load(0).await;
+        // the second image using the layer finds it at another position
+        let cached = load(2).await;
+        assert_eq!(indexed, 1);
+        assert!(matches!(
+            &cached[0],
+            LayerChange::Add {
+                entry: FileEntry::File { layer_index: 2, .. },
+                location: Some(DataLocation::Tar { offset: 512 }),
+                ..
+            }
+        ));
+        assert!(matches!(&cached[1], LayerChange::Whiteout(path) if path == Path::new("etc/motd")));
+
+        // indexes that do not describe the layer are ignored
+        let mut other = layer.clone();
+        other.size += 1;
+        assert!(cache.load(&other, 0).await.is_none());
+        std::fs::write(cache.index_path(&layer.digest), b"{").unwrap();
+        assert!(cache.load(&layer, 0).await.is_none());
+    }
+}
@@ -0,0 +1,661 @@
+//! Listings of the changes a layer makes to the filesystem below it.
+//!
+//! Layers are indexed once, from their tar stream or their table of
+//! contents, and the resulting changes are replayed in layer order to build
+//! the [`VirtualFileSystem`].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufReader, Read};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tar::Archive;
+
+use super::archive_utils;
+use super::image_reader::Result;
+use super::seek_index::{CHECKPOINT_SPAN, GzipReader, SeekIndex};
+use super::vfs::{FileEntry, FileMetadata, VirtualFileSystem};
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Where the content of a regular file is stored inside its layer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum DataLocation {
+    /// Offset of the content in the uncompressed tar stream.
+    Tar { offset: u64 },
+    /// Compressed chunks listed by the layer's table of contents, in order.
+    Chunks(Vec<Chunk>),
+}
+
+/// Piece of a file stored in an eStargz or zstd:chunked layer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Chunk {
+    /// Range of the layer blob holding the compressed chunk. An empty range
+    /// stands for a hole of `size` zero bytes.
+    pub compressed: Range<u64>,
+    /// Bytes preceding the chunk once `compressed` is decompressed.
+    pub inner_offset: u64,
+    /// Uncompressed size of the chunk.
+    pub size: u64,
+    /// Digest of the uncompressed chunk, when recorded.
+    pub digest: Option<String>,
+}
+
+/// Change a layer makes to the filesystem, in archive order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum LayerChange {
+    /// Adds or replaces the entry at `path`.
+    Add {
+        path: PathBuf,
+        entry: FileEntry,
+        location: Option<DataLocation>,
+    },
+    /// `.wh.<name>` marker removing `path` from the layers below.
+    Whiteout(PathBuf),
+    /// `.wh..wh..opq` marker hiding the contents of the directory below.
+    Opaque(PathBuf),
+}
+
+/// Listing of a layer along with what is needed to read its files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct LayerIndex {
+    pub changes: Vec<LayerChange>,
+    /// Points where decompression of the layer can start, when it has any.
+    pub seek: Option<SeekIndex>,
+}
+
+/// Returns `path` relative to the layer root, so that `./etc/passwd`,
+/// `/etc/passwd` and `etc/passwd` name the same entry. `..` cannot climb
+/// above the root.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+    normalized
+}
+
+/// Returns the whiteout change `path` stands for, if it is a whiteout marker.
+pub(crate) fn whiteout(path: &Path) -> Option<LayerChange> {
+    let name = path.file_name()?.to_string_lossy();
+    let parent = path.parent().unwrap_or(Path::new(""));
+    if name == OPAQUE_WHITEOUT {
+        Some(LayerChange::Opaque(parent.to_path_buf()))
+    } else {
+        name.strip_prefix(WHITEOUT_PREFIX)
+            .map(|target| LayerChange::Whiteout(parent.join(target)))
+    }
+}
+
+/// Indexes a layer blob of the given `file_type`, recording checkpoints for
+/// gzip layers. The blob is read to its end, so that a digest computed while
+/// it streams covers all of it.
+pub(crate) fn index_blob<R: Read>(
+    blob: R,
+    file_type: &str,
+    layer_index: usize,
+) -> Result<LayerIndex> {
+    if file_type == "gzip" {
+        let mut reader = GzipReader::new(BufReader::new(blob)).with_checkpoints(CHECKPOINT_SPAN);
+        let changes = index_stream(&mut reader, layer_index)?;
+        return Ok(LayerIndex {
+            changes,
+            seek: Some(SeekIndex::Gzip(reader.into_checkpoints())),
+        });
+    }
+    let changes = index_stream(archive_utils::decompress(blob, file_type)?, layer_index)?;
+    Ok(LayerIndex {
+        changes,
+        seek: None,
+    })
+}
+
+/// Indexes a tar stream, then consumes the padding after its last entry.
+fn index_stream<R: Read>(mut stream: R, layer_index: usize) -> io::Result<Vec<LayerChange>> {
+    let changes = index_archive(&mut Archive::new(stream.by_ref()), layer_index)?;
+    io::copy(&mut stream, &mut io::sink())?;
+    Ok(changes)
+}
+
+/// Reads the changes made by the tar stream of a layer.
+fn index_archive<R: Read>(
+    archive: &mut Archive<R>,
+    layer_index: usize,
+) -> io::Result<Vec<LayerChange>> {
+    let mut changes = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = normalize_path(&entry.path()?);
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+        if let Some(change) = whiteout(&path) {
+            changes.push(change);
+            continue;
+        }
+
+        let metadata = tar_metadata(&mut entry)?;
+        let (entry, location) = match entry.header().entry_type() {
+            // the tar crate reports the size of sparse files with their holes
+            tar::EntryType::GNUSparse => (
+                FileEntry::Sparse {
+                    size: entry.size(),
+                    layer_index,
+                    metadata,
+                },
+                None,
+            ),
+            tar::EntryType::Regular => (
+                FileEntry::File {
+                    size: entry.size(),
+                    layer_index,
+                    metadata,
+                },
+                Some(DataLocation::Tar {
+                    offset: entry.raw_file_position(),
+                }),
+            ),
+            tar::EntryType::Directory => (
+                FileEntry::Directory {
+                    layer_index,
+                    metadata,
+                },
+                None,
+            ),
+            tar::EntryType::Symlink => match entry.link_name() {
+                Ok(Some(link_name)) => (
+                    FileEntry::Symlink {
+                        target: link_name.to_string_lossy().to_string(),
+                        layer_index,
+                        metadata,
+                    },
+                    None,
+                ),
+                _ => continue,
+            },
+            tar::EntryType::Link => match entry.link_name() {
+                Ok(Some(link_name)) => (
+                    hardlink(&link_name.to_string_lossy(), layer_index, metadata),
+                    None,
+                ),
+                _ => continue,
+            },
+            tar::EntryType::Char => {
+                let (major, minor) = device_numbers(entry.header());
+                (
+                    FileEntry::CharDevice {
+                        major,
+                        minor,
+                        layer_index,
+                        metadata,
+                    },
+                    None,
+                )
+            }
+            tar::EntryType::Block => {
+                let (major, minor) = device_numbers(entry.header());
+                (
+                    FileEntry::BlockDevice {
+                        major,
+                        minor,
+                        layer_index,
+                        metadata,
+                    },
+                    None,
+                )
+            }
+            tar::EntryType::Fifo => (
+                FileEntry::Fifo {
+                    layer_index,
+                    metadata,
+                },
+                None,
+            ),
+            _ => continue,
+        };
+        changes.push(LayerChange::Add {
+            path,
+            entry,
+            location,
+        });
+    }
+    resolve_hardlinks(&mut changes);
+    Ok(changes)
+}
+
+/// Returns the major and minor numbers of a device node.
+fn device_numbers(header: &tar::Header) -> (u32, u32) {
+    (
+        header.device_major().ok().flatten().unwrap_or_default(),
+        header.device_minor().ok().flatten().unwrap_or_default(),
+    )
+}
+
+/// Returns a hard link to `link_name`, a path relative to the root of the
+/// layer. Its content is filled in by [`resolve_hardlinks`], or when the layer
+/// is applied if the target comes from a lower layer.
+pub(crate) fn hardlink(link_name: &str, layer_index: usize, metadata: FileMetadata) -> FileEntry {
+    FileEntry::Hardlink {
+        target: normalize_path(Path::new(link_name))
+            .to_string_lossy()
+            .to_string(),
+        size: 0,
+        layer_index,
+        metadata,
+    }
+}
+
+/// Gives hard links the size and content location of the file they link to
+/// when it was added earlier in the same layer.
+pub(crate) fn resolve_hardlinks(changes: &mut [LayerChange]) {
+    let mut contents: HashMap<PathBuf, (u64, Option<DataLocation>)> = HashMap::new();
+    for change in changes {
+        let LayerChange::Add {
+            path,
+            entry,
+            location,
+        } = change
+        else {
+            continue;
+        };
+        if let FileEntry::Hardlink { target, size, .. } = entry
+            && let Some((target_size, target_location)) = contents.get(Path::new(target))
+        {
+            *size = *target_size;
+            location.clone_from(target_location);
+        }
+        match entry.content_size() {
+            Some(size) => contents.insert(path.clone(), (size, location.clone())),
+            None => contents.remove(path),
+        };
+    }
+}
+
+/// Reads the metadata of a tar entry. PAX records override the header
+/// fields they extend, and `SCHILY.xattr.<name>` records carry the extended
+/// attributes. Header fields some writers leave blank read as zero.
+fn tar_metadata<R: Read>(entry: &mut tar::Entry<R>) -> io::Result<FileMetadata> {
+    let header = entry.header();
+    let mut metadata = FileMetadata {
+        mode: header.mode().unwrap_or_default() & 0o7777,
+        uid: header.uid().unwrap_or_default(),
+        gid: header.gid().unwrap_or_default(),
+        user: header
+            .username()
+            .ok()
+            .flatten()
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+        group: header
+            .groupname()
+            .ok()
+            .flatten()
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+        mtime: header.mtime().unwrap_or_default(),
+        xattrs: BTreeMap::new(),
+    };
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(metadata);
+    };
+    for extension in extensions {
+        let extension = extension?;
+        let Ok(key) = extension.key() else {
+            continue;
+        };
+        let value = || extension.value().ok().map(str::to_string);
+        // times may have a fractional part
+        let number = || value().and_then(|v| v.split('.').next()?.parse().ok());
+        match key {
+            "uid" => metadata.uid = number().unwrap_or(metadata.uid),
+            "gid" => metadata.gid = number().unwrap_or(metadata.gid),
+            "mtime" => metadata.mtime = number().unwrap_or(metadata.mtime),
+            "uname" => metadata.user = value().or(metadata.user),
+            "gname" => metadata.group = value().or(metadata.group),
+            _ => {
+                if let Some(name) = key.strip_prefix(PAX_XATTR_PREFIX) {
+                    let value = extension.value_bytes().to_vec();
+                    metadata.xattrs.insert(name.to_string(), value);
+                }
+            }
+        }
+    }
+    Ok(metadata)
+}
+
+/// Replays the changes of one layer on top of `vfs`, recording where the
+/// content of added files is stored.
+///
+/// Like overlayfs, whiteouts and opaque markers only hide entries of lower
+/// layers, wherever they appear in the layer: they are applied before its
+/// entries are added. Whiting out a directory hides everything below it, and
+/// so does replacing a directory with another kind of entry.
+pub(crate) fn apply_changes(
+    vfs: &mut VirtualFileSystem,
+    locations: &mut HashMap<PathBuf, DataLocation>,
+    changes: Vec<LayerChange>,
+) {
+    // paths removed themselves, and directories whose lower contents are hidden
+    let mut removed = HashSet::new();
+    let mut hidden_dirs = HashSet::new();
+    let mut added = Vec::with_capacity(changes.len());
+    for change in changes {
+        match change {
+            LayerChange::Add {
+                path,
+                entry,
+                location,
+            } => {
+                if !matches!(entry, FileEntry::Directory { .. })
+                    && let Some(FileEntry::Directory { .. }) = vfs.get_entry(&path)
+                {
+                    hidden_dirs.insert(path.clone());
+                }
+                added.push((path, entry, location));
+            }
+            LayerChange::Whiteout(path) => {
+                removed.insert(path.clone());
+                hidden_dirs.insert(path);
+            }
+            LayerChange::Opaque(dir) => {
+                hidden_dirs.insert(dir);
+            }
+        }
+    }
+    if !removed.is_empty() || !hidden_dirs.is_empty() {
+        let hidden = |path: &Path| {
+            removed.contains(path)
+                || path
+                    .ancestors()
+                    .skip(1)
+                    .any(|dir| hidden_dirs.contains(dir))
+        };
+        vfs.retain(|path, _| !hidden(path));
+        locations.retain(|path, _| !hidden(path));
+    }
+
+    for (path, mut entry, mut location) in added {
+        // links to a file of a lower layer share its content there
+        if let FileEntry::Hardlink {
+            target,
+            size,
+            layer_index,
+            ..
+        } = &mut entry
+            && location.is_none()
+            && let Some(target_entry) = vfs.get_entry(target.as_str())
+            && let Some(target_size) = target_entry.content_size()
+        {
+            *size = target_size;
+            *layer_index = target_entry.layer_index();
+            location = locations.get(Path::new(target)).cloned();
+        }
+        match location {
+            Some(location) => locations.insert(path.clone(), location),
+            None => locations.remove(&path),
+        };
+        vfs.add_entry(path, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::fixtures::{Entry, tar_layer};
+
+    #[test]
+    fn test_tar_metadata() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(2);
+        header.set_mode(0o104755);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("root").unwrap();
+        header.set_mtime(1_700_000_000);
+        builder
+            .append_pax_extensions([
+                ("SCHILY.xattr.security.capability", &[1, 0, 0, 2][..]),
+                ("gname", b"wheel"),
+                ("mtime", b"1700000001.25"),
+            ])
+            .unwrap();
+        builder
+            .append_data(&mut header, "usr/bin/ping", &b"hi"[..])
+            .unwrap();
+        let blob = builder.into_inner().unwrap();
+
+        let index = index_blob(&blob[..], "tar", 0).unwrap();
+        let [
+            LayerChange::Add {
+                entry: FileEntry::File { metadata, .. },
+                ..
+            },
+        ] = &index.changes[..]
+        else {
+            panic!("unexpected changes {:?}", index.changes);
+        };
+        assert_eq!(metadata.mode, 0o4755);
+        assert!(metadata.is_setid());
+        assert_eq!(metadata.user.as_deref(), Some("root"));
+        assert_eq!(metadata.group.as_deref(), Some("wheel"));
+        assert_eq!(metadata.mtime, 1_700_000_001);
+        assert_eq!(metadata.xattrs["security.capability"], vec![1, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_hardlinks() {
+        let (vfs, locations) = replay_layers(&[
+            tar_layer(&[
+                Entry::File("bin/busybox", b"busybox"),
+                Entry::Hardlink("bin/sh", "./bin/busybox"),
+            ]),
+            tar_layer(&[Entry::Hardlink("bin/ls", "/bin/busybox")]),
+        ]);
+
+        let offset = |path: &str| match locations.get(Path::new(path)) {
+            Some(DataLocation::Tar { offset }) => *offset,
+            location => panic!("unexpected location {location:?} of {path}"),
+        };
+        for link in ["bin/sh", "bin/ls"] {
+            let Some(FileEntry::Hardlink {
+                target,
+                size,
+                layer_index,
+                ..
+            }) = vfs.get_entry(link)
+            else {
+                panic!("{link} is not a hard link");
+            };
+            assert_eq!(target, "bin/busybox");
+            assert_eq!(*size, 7);
+            // the content is read from the layer holding the target
+            assert_eq!(*layer_index, 0);
+            assert_eq!(offset(link), offset("bin/busybox"));
+        }
+    }
+
+    #[test]
+    fn test_special_files() {
+        let device = |entry_type, major, minor| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_device_major(major).unwrap();
+            header.set_device_minor(minor).unwrap();
+            Box::new(header)
+        };
+        // 512 bytes of data at the end of a 1 MiB file
+        let mut sparse = tar::Header::new_gnu();
+        sparse.set_entry_type(tar::EntryType::GNUSparse);
+        sparse.set_size(512);
+        let gnu = sparse.as_gnu_mut().unwrap();
+        gnu.sparse[0].set_offset(1024 * 1024 - 512);
+        gnu.sparse[0].set_length(512);
+        gnu.set_real_size(1024 * 1024);
+        let blob = tar_layer(&[
+            Entry::Raw("dev/null", device(tar::EntryType::Char, 1, 3), b""),
+            Entry::Raw("dev/sda", device(tar::EntryType::Block, 8, 0), b""),
+            Entry::Raw("run/initctl", device(tar::EntryType::Fifo, 0, 0), b""),
+            Entry::Raw("var/log/lastlog", Box::new(sparse), &[1u8; 512]),
+        ]);
+
+        let index = index_blob(&blob[..], "tar", 0).unwrap();
+        let entries: HashMap<_, _> = index
+            .changes
+            .into_iter()
+            .filter_map(|change| match change {
+                LayerChange::Add { path, entry, .. } => Some((path, entry)),
+                _ => None,
+            })
+            .collect();
+        let kind = |path: &str| entries[Path::new(path)].special_kind().unwrap();
+        assert_eq!(kind("dev/null"), "char device 1, 3");
+        assert_eq!(kind("dev/sda"), "block device 8, 0");
+        assert_eq!(kind("run/initctl"), "fifo");
+        assert!(matches!(
+            entries[Path::new("var/log/lastlog")],
+            FileEntry::Sparse { size: 1048576, .. }
+        ));
+    }
+
+    /// Replays the layers `blobs` on an empty filesystem.
+    fn replay_layers(blobs: &[Vec<u8>]) -> (VirtualFileSystem, HashMap<PathBuf, DataLocation>) {
+        let mut vfs = VirtualFileSystem::new();
+        let mut locations = HashMap::new();
+        for (layer_index, blob) in blobs.iter().enumerate() {
+            let index = index_blob(&blob[..], "tar", layer_index).unwrap();
+            apply_changes(&mut vfs, &mut locations, index.changes);
+        }
+        (vfs, locations)
+    }
+
+    /// Replays layers whose entries are written `dir/`, `link -> target`,
+    /// `link => target` for hard links, or `file`, holding its own path.
+    /// Returns the resulting paths, with `/` after directories, and the paths
+    /// whose content is located.
+    fn replay(layers: &[&[&'static str]]) -> (Vec<String>, Vec<String>) {
+        let entry = |spec: &'static str| {
+            if let Some((path, target)) = spec.split_once(" -> ") {
+                Entry::Symlink(path, target)
+            } else if let Some((path, target)) = spec.split_once(" => ") {
+                Entry::Hardlink(path, target)
+            } else if spec.ends_with('/') {
+                Entry::Dir(spec)
+            } else {
+                Entry::File(spec, spec.as_bytes())
+            }
+        };
+        let blobs: Vec<_> = layers
+            .iter()
+            .map(|entries| tar_layer(&entries.iter().map(|spec| entry(spec)).collect::<Vec<_>>()))
+            .collect();
+        let (vfs, locations) = replay_layers(&blobs);
+        let mut paths: Vec<_> = vfs
+            .get_entries()
+            .iter()
+            .map(|(path, entry)| match entry {
+                FileEntry::Directory { .. } => format!("{}/", path.display()),
+                _ => path.display().to_string(),
+            })
+            .collect();
+        paths.sort();
+        let mut located: Vec<_> = locations
+            .keys()
+            .map(|path| path.display().to_string())
+            .collect();
+        located.sort();
+        (paths, located)
+    }
+
+    #[test]
+    fn test_directory_whiteout_hides_children() {
+        let (paths, located) = replay(&[
+            &[
+                "etc/",
+                "etc/app/",
+                "etc/app/conf",
+                "etc/app/sub/x",
+                "etc/apps",
+            ],
+            &["etc/.wh.app"],
+        ]);
+        assert_eq!(paths, ["etc/", "etc/apps"]);
+        assert_eq!(located, ["etc/apps"]);
+
+        // a later layer can bring the directory back, without its old contents
+        let (paths, _) = replay(&[
+            &["etc/app/", "etc/app/conf", "etc/app/old"],
+            &["etc/.wh.app"],
+            &["etc/app/", "etc/app/conf"],
+        ]);
+        assert_eq!(paths, ["etc/app/", "etc/app/conf"]);
+    }
+
+    #[test]
+    fn test_opaque_directory_keeps_its_own_layer() {
+        // the marker follows the new entries, as written by some builders
+        let (paths, _) = replay(&[
+            &["opt/", "opt/old", "opt/lib/", "opt/lib/a", "optional"],
+            &["opt/", "opt/new", "opt/lib/", "opt/.wh..wh..opq"],
+        ]);
+        assert_eq!(paths, ["opt/", "opt/lib/", "opt/new", "optional"]);
+
+        let (paths, _) = replay(&[&["a", "b/", "b/c"], &[".wh..wh..opq", "d"]]);
+        assert_eq!(paths, ["d"]);
+    }
+
+    #[test]
+    fn test_whiteouts_of_normalized_paths() {
+        let (paths, located) = replay(&[
+            &["./", "./usr/", "./usr/bin/tool", "./usr/lib/x", "/etc/motd"],
+            &[
+                "usr/bin/.wh.tool",
+                "./usr/lib/.wh..wh..opq",
+                "./etc/.wh.motd",
+            ],
+        ]);
+        assert_eq!(paths, ["usr/"]);
+        assert!(located.is_empty());
+    }
+
+    #[test]
+    fn test_links_to_whited_out_paths() {
+        // like on overlayfs, symlinks dangle and hard links keep the content
+        let (paths, located) = replay(&[
+            &["bin/busybox", "bin/sh -> busybox", "bin/ash => bin/busybox"],
+            &["bin/.wh.busybox"],
+        ]);
+        assert_eq!(paths, ["bin/ash", "bin/sh"]);
+        assert_eq!(located, ["bin/ash"]);
+
+        // whiteouts apply first, so a link to the removed path has no content
+        let (paths, located) = replay(&[&["etc/conf"], &["etc/.wh.conf", "etc/link => etc/conf"]]);
+        assert_eq!(paths, ["etc/link"]);
+        assert!(located.is_empty());
+    }
+
+    #[test]
+    fn test_entry_replacing_directory() {
+        let (paths, located) = replay(&[
+            &["var/", "var/run/", "var/run/pid", "var/runner"],
+            &["var/run"],
+        ]);
+        assert_eq!(paths, ["var/", "var/run", "var/runner"]);
+        assert_eq!(located, ["var/run", "var/runner"]);
+
+        // directories replacing directories are merged
+        let (paths, _) = replay(&[&["srv/", "srv/a"], &["srv/", "srv/b"]]);
+        assert_eq!(paths, ["srv/", "srv/a", "srv/b"]);
+    }
+}
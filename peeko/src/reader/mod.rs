@@ -2,10 +2,26 @@
 
 mod archive_utils;
 mod dir_tree;
+#[cfg(test)]
+pub(crate) mod fixtures;
 mod image_reader;
+mod index_cache;
+mod layer_index;
+mod remote;
+mod seek_index;
+mod symlink;
+mod toc;
 pub mod vfs;
 
+/// Filesystem-style view of an image built from its layers.
+pub use image_reader::ImageReader;
 /// Error type returned by the asynchronous image reader.
 pub use image_reader::ImageReaderError;
 /// Build a high level image reader from an unpacked OCI image directory.
 pub use image_reader::build_image_reader;
+/// Build an image reader for one platform of a multi-platform image directory.
+pub use image_reader::build_platform_image_reader;
+/// Build an image reader that fetches layers from the registry on demand.
+pub use image_reader::build_remote_image_reader;
+/// Read the manifest and config of a stored image without its layers.
+pub use image_reader::load_image_config;
@@ -0,0 +1,298 @@
+//! Layers read straight from a registry for images that were not pulled.
+//!
+//! Layers are indexed by streaming them once, or from their table of
+//! contents when they carry one, and file contents are fetched on demand
+//! with HTTP range requests.
+
+use std::io::{self, Read};
+use std::ops::Range;
+
+use futures_util::{StreamExt, TryStreamExt, stream};
+use tokio::sync::mpsc;
+
+use super::archive_utils;
+use super::image_reader::{ImageReaderError, Result};
+use super::index_cache::{self, IndexCache};
+use super::layer_index::{self, DataLocation, LayerIndex};
+use super::seek_index::{self, SeekIndex};
+use super::toc::{self, TocFormat};
+use crate::digest::Digester;
+use crate::manifest::{Descriptor, get_file_type};
+use crate::registry::{RegistryClient, RegistryError};
+
+/// Body chunks buffered between a download and the thread decoding it.
+const STREAM_BUFFER: usize = 16;
+
+/// Layers of an image fetched from its registry.
+pub(crate) struct RemoteLayers {
+    client: RegistryClient,
+    repository: String,
+    /// Index cache of the client's downloads directory, if it has one.
+    cache: Option<IndexCache>,
+}
+
+impl RemoteLayers {
+    pub(crate) fn new(client: RegistryClient, repository: &str) -> Self {
+        Self {
+            cache: IndexCache::in_dir(client.downloads_dir()),
+            client,
+            repository: repository.to_string(),
+        }
+    }
+
+    /// Lists the changes of every layer, fetching several layers at a time.
+    /// Layers already indexed in the downloads directory are not fetched.
+    pub(crate) async fn index_layers(&self, layers: &[Descriptor]) -> Result<Vec<LayerIndex>> {
+        stream::iter(layers.iter().enumerate())
+            .map(|(layer_index, layer)| {
+                index_cache::load_or_index(
+                    self.cache.as_ref(),
+                    layer,
+                    layer_index,
+                    async move || self.index_layer(layer, layer_index).await,
+                )
+            })
+            .buffered(self.client.concurrent_downloads().max(1))
+            .try_collect()
+            .await
+    }
+
+    /// Indexes a layer from its table of contents, or by streaming the whole
+    /// layer when it has none.
+    async fn index_layer(&self, layer: &Descriptor, layer_index: usize) -> Result<LayerIndex> {
+        let read_blob = async |range| self.fetch_range(layer, range).await;
+        if let Some(format) = TocFormat::detect(layer) {
+            return Ok(LayerIndex {
+                changes: toc::read_toc(&format, layer, layer_index, read_blob).await?,
+                seek: None,
+            });
+        }
+
+        let file_type = get_file_type(&layer.media_type);
+        let mut index = {
+            let file_type = file_type.to_string();
+            self.stream_layer(layer, move |blob| {
+                layer_index::index_blob(blob, &file_type, layer_index)
+            })
+            .await?
+        };
+        if file_type == "zstd" {
+            index.seek = seek_index::zstd_seek_table(layer.size, read_blob).await?;
+        }
+        Ok(index)
+    }
+
+    /// Reads the `size` bytes of a file stored at `location` in `layer`.
+    ///
+    /// Chunks listed by a table of contents, files of uncompressed layers
+    /// and files of layers with a seek index are fetched with range
+    /// requests. Files of other layers are read by streaming the layer up to
+    /// the end of the file.
+    pub(crate) async fn read_file(
+        &self,
+        layer: &Descriptor,
+        location: &DataLocation,
+        seek: Option<&SeekIndex>,
+        size: u64,
+    ) -> Result<Vec<u8>> {
+        let read_blob = async |range| self.fetch_range(layer, range).await;
+        match location {
+            DataLocation::Chunks(chunks) => {
+                let format = TocFormat::detect(layer)
+                    .ok_or_else(|| ImageReaderError::InvalidToc(layer.digest.clone()))?;
+                toc::read_chunks(&format, chunks, size, read_blob).await
+            }
+            DataLocation::Tar { offset } => {
+                let range = *offset..offset + size;
+                if let Some(content) =
+                    seek_index::read_layer_range(layer, seek, range, read_blob).await?
+                {
+                    return Ok(content);
+                }
+                let offset = *offset;
+                let file_type = get_file_type(&layer.media_type).to_string();
+                self.stream_layer(layer, move |blob| {
+                    let stream = archive_utils::decompress(blob, &file_type)?;
+                    Ok(seek_index::read_range(stream, offset, size)?)
+                })
+                .await
+            }
+        }
+    }
+
+    async fn fetch_range(&self, layer: &Descriptor, range: Range<u64>) -> Result<Vec<u8>> {
+        let data = self
+            .client
+            .fetch_blob_range(&self.repository, &layer.digest, range)
+            .await?;
+        Ok(data)
+    }
+
+    /// Downloads a layer and hands the blob to `read`, which runs on a
+    /// blocking thread.
+    ///
+    /// The download stops as soon as `read` returns. When it consumed the
+    /// whole layer the layer is checked against its digest.
+    async fn stream_layer<T, F>(&self, layer: &Descriptor, read: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(Box<dyn Read>) -> Result<T> + Send + 'static,
+    {
+        let response = self
+            .client
+            .open_blob(&self.repository, &layer.digest)
+            .await?;
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let reading =
+            tokio::task::spawn_blocking(move || read(Box::new(ChannelReader::new(receiver))));
+
+        let mut digester = Digester::for_digest(&layer.digest);
+        let mut complete = true;
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(RegistryError::from)?;
+            if let Some(digester) = &mut digester {
+                digester.update(&chunk);
+            }
+            if sender.send(chunk.to_vec()).await.is_err() {
+                complete = false;
+                break;
+            }
+        }
+        drop(sender);
+
+        let result = reading.await.map_err(io::Error::other)??;
+        if complete && let Some(digester) = digester {
+            let actual = digester.finalize();
+            if actual != layer.digest {
+                return Err(RegistryError::BlobDigestMismatch {
+                    expected: layer.digest.clone(),
+                    actual,
+                }
+                .into());
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Blocking reader over the body chunks an async task sends.
+struct ChannelReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl ChannelReader {
+    fn new(receiver: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::build_remote_image_reader;
+    use crate::reader::fixtures::{
+        Entry, GZIP_LAYER, TAR_LAYER, descriptor, estargz_layer, gzip, serve_image, tar_layer,
+    };
+    use crate::registry::mock::MockRegistry;
+
+    fn layer_requests(registry: &MockRegistry, layer: &Descriptor) -> Vec<Option<String>> {
+        registry
+            .requests()
+            .into_iter()
+            .filter(|req| req.path.ends_with(&layer.digest))
+            .map(|req| req.headers.get("range").cloned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_remote_reader_streams_layers() {
+        let base = gzip(&tar_layer(&[
+            Entry::File("etc/hostname", b"peeko"),
+            Entry::File("etc/motd", b"hello"),
+        ]));
+        let top = tar_layer(&[
+            Entry::File("etc/hostname", b"override"),
+            Entry::File("usr/bin/tool", b"#!/bin/sh"),
+            Entry::Hardlink("usr/bin/dangling", "usr/bin/missing"),
+        ]);
+        let base_layer = descriptor(GZIP_LAYER, &base);
+        let top_layer = descriptor(TAR_LAYER, &top);
+        let registry =
+            serve_image(vec![(base_layer.clone(), base), (top_layer.clone(), top)]).await;
+
+        let client = RegistryClient::new(registry.url());
+        let image = "test/app:v1".parse().unwrap();
+        let reader = build_remote_image_reader(client, &image, None)
+            .await
+            .unwrap();
+        assert!(reader.get_file_metadata("usr/bin/tool").is_some());
+        assert_eq!(reader.read_file("etc/hostname").await.unwrap(), b"override");
+        assert_eq!(reader.read_file("etc/motd").await.unwrap(), b"hello");
+        assert!(matches!(
+            reader.read_file("usr/bin/dangling").await,
+            Err(ImageReaderError::BrokenHardlink(link, target))
+                if link == "usr/bin/dangling" && target == "usr/bin/missing"
+        ));
+
+        // the uncompressed layer is read with a range request after indexing
+        let top_requests = layer_requests(&registry, &top_layer);
+        assert_eq!(top_requests.len(), 2);
+        assert!(top_requests[0].is_none());
+        assert!(top_requests[1].is_some());
+        // so is the compressed layer, from its closest checkpoint
+        let base_requests = layer_requests(&registry, &base_layer);
+        assert_eq!(base_requests.len(), 2);
+        assert!(base_requests[0].is_none());
+        assert!(base_requests[1].is_some());
+    }
+
+    #[tokio::test]
+    async fn test_remote_reader_uses_estargz_toc() {
+        let (blob, annotations) = estargz_layer(&[
+            ("etc/", b""),
+            ("etc/hostname", b"peeko"),
+            ("etc/empty", b""),
+        ]);
+        let mut descriptor = descriptor(GZIP_LAYER, &blob);
+        descriptor.annotations = Some(annotations);
+        let registry = serve_image(vec![(descriptor.clone(), blob)]).await;
+
+        let client = RegistryClient::new(registry.url());
+        let image = "test/app:v1".parse().unwrap();
+        let reader = build_remote_image_reader(client, &image, None)
+            .await
+            .unwrap();
+        assert!(reader.get_file_metadata("etc").is_some());
+        assert_eq!(reader.read_file("etc/hostname").await.unwrap(), b"peeko");
+        assert_eq!(reader.read_file("etc/empty").await.unwrap(), b"");
+
+        let requests = layer_requests(&registry, &descriptor);
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(Option::is_some));
+    }
+}
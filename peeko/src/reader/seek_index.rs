@@ -0,0 +1,553 @@
+//! Random access into the tar stream of compressed layers.
+//!
+//! Gzip layers are indexed zran-style: while a layer is decompressed once,
+//! the state of the decompressor is saved every [`CHECKPOINT_SPAN`] bytes at
+//! a deflate block boundary, together with the 32 KiB window the next blocks
+//! may refer to. Decompression can then resume from the checkpoint closest
+//! to a file instead of the start of the layer. Zstd layers written in the
+//! seekable format carry a seek table listing independent frames, which
+//! serve the same purpose.
+
+use std::io::{self, BufRead, Read, Write};
+use std::ops::Range;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::Crc;
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::inflate_flags::{
+    TINFL_FLAG_HAS_MORE_INPUT, TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY,
+};
+use miniz_oxide::inflate::core::{
+    BlockBoundaryState, DecompressorOxide, TINFL_LZ_DICT_SIZE, decompress,
+};
+use serde::{Deserialize, Serialize};
+
+use super::image_reader::Result;
+use crate::manifest::{Descriptor, get_file_type};
+
+/// Uncompressed bytes between two gzip checkpoints. Each checkpoint stores a
+/// compressed 32 KiB window, so this trades index size for read latency.
+pub(crate) const CHECKPOINT_SPAN: u64 = 4 << 20;
+
+const GZIP_MAGIC: [u8; 3] = [0x1f, 0x8b, 8];
+const GZIP_FEXTRA: u8 = 1 << 2;
+const GZIP_FNAME: u8 = 1 << 3;
+const GZIP_FCOMMENT: u8 = 1 << 4;
+const GZIP_FHCRC: u8 = 1 << 1;
+
+const ZSTD_SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+const ZSTD_SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+const ZSTD_SEEK_TABLE_FOOTER_SIZE: u64 = 9;
+const ZSTD_SEEK_TABLE_CHECKSUM_FLAG: u8 = 1 << 7;
+
+/// Points of a compressed layer where decompression can start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum SeekIndex {
+    /// Checkpoints of a gzip layer, by increasing uncompressed offset.
+    Gzip(Vec<Checkpoint>),
+    /// Frames of a seekable zstd layer, followed by the end of the last one.
+    Zstd(Vec<Frame>),
+}
+
+/// Deflate block boundary of a gzip layer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    /// Offset of the first compressed byte of the next block.
+    compressed: u64,
+    /// Number of bits of the previous byte that belong to the next block.
+    bits: u8,
+    /// Those bits, in the low bits of the byte.
+    bit_buf: u8,
+    /// Offset of the checkpoint in the uncompressed stream.
+    uncompressed: u64,
+    /// Last 32 KiB of uncompressed data, deflated and base64 encoded.
+    window: String,
+}
+
+/// Start of an independent zstd frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Frame {
+    compressed: u64,
+    uncompressed: u64,
+}
+
+impl SeekIndex {
+    /// Returns the start of the uncompressed stream and of the blob where
+    /// decompression has to begin to reach `offset`.
+    fn start(&self, offset: u64) -> (u64, u64) {
+        match self {
+            Self::Gzip(checkpoints) => checkpoints
+                .iter()
+                .rev()
+                .find(|checkpoint| checkpoint.uncompressed <= offset)
+                .map_or((0, 0), |checkpoint| {
+                    (checkpoint.uncompressed, checkpoint.compressed)
+                }),
+            Self::Zstd(frames) => frames
+                .iter()
+                .rev()
+                .find(|frame| frame.uncompressed <= offset)
+                .map_or((0, 0), |frame| (frame.uncompressed, frame.compressed)),
+        }
+    }
+
+    /// Returns the part of a blob of `blob_size` bytes holding the
+    /// uncompressed `range`.
+    pub(crate) fn compressed_range(&self, range: &Range<u64>, blob_size: u64) -> Range<u64> {
+        let (_, start) = self.start(range.start);
+        let end = match self {
+            Self::Gzip(checkpoints) => checkpoints
+                .iter()
+                .find(|checkpoint| checkpoint.uncompressed >= range.end)
+                .map(|checkpoint| checkpoint.compressed),
+            Self::Zstd(frames) => frames
+                .iter()
+                .find(|frame| frame.uncompressed >= range.end)
+                .map(|frame| frame.compressed),
+        };
+        start..end.unwrap_or(blob_size).max(start)
+    }
+
+    /// Decompresses the uncompressed `range` from `blob`, the part of the
+    /// layer returned by [`compressed_range`](Self::compressed_range).
+    pub(crate) fn read(&self, range: &Range<u64>, blob: &[u8]) -> io::Result<Vec<u8>> {
+        let (start, _) = self.start(range.start);
+        let reader: Box<dyn Read + '_> = match self {
+            Self::Gzip(checkpoints) => {
+                match checkpoints.iter().rev().find(|c| c.uncompressed == start) {
+                    Some(checkpoint) if start > 0 => {
+                        Box::new(GzipReader::resume(blob, checkpoint)?)
+                    }
+                    _ => Box::new(GzipReader::new(blob)),
+                }
+            }
+            Self::Zstd(_) => Box::new(zstd::Decoder::with_buffer(blob)?),
+        };
+        read_range(reader, range.start - start, range.end - range.start)
+    }
+}
+
+/// Reads `size` bytes of `reader` after skipping `skip` bytes.
+pub(crate) fn read_range<R: Read>(mut reader: R, skip: u64, size: u64) -> io::Result<Vec<u8>> {
+    io::copy(&mut reader.by_ref().take(skip), &mut io::sink())?;
+    let mut content = Vec::with_capacity(size as usize);
+    reader.take(size).read_to_end(&mut content)?;
+    if content.len() as u64 != size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(content)
+}
+
+/// Reads the uncompressed `range` of `layer` through `read_blob` when the
+/// layer is an uncompressed tar or has a seek index. Returns `None` when the
+/// layer can only be decompressed from its start.
+pub(crate) async fn read_layer_range(
+    layer: &Descriptor,
+    seek: Option<&SeekIndex>,
+    range: Range<u64>,
+    read_blob: impl AsyncFn(Range<u64>) -> Result<Vec<u8>>,
+) -> Result<Option<Vec<u8>>> {
+    if range.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+    if get_file_type(&layer.media_type) == "tar" {
+        return read_blob(range).await.map(Some);
+    }
+    let Some(seek) = seek else {
+        return Ok(None);
+    };
+    let blob = read_blob(seek.compressed_range(&range, layer.size)).await?;
+    Ok(Some(seek.read(&range, &blob)?))
+}
+
+/// Reads the seek table of a zstd layer of `size` bytes, if it was written
+/// in the seekable format.
+pub(crate) async fn zstd_seek_table(
+    size: u64,
+    read_blob: impl AsyncFn(Range<u64>) -> Result<Vec<u8>>,
+) -> Result<Option<SeekIndex>> {
+    if size < ZSTD_SEEK_TABLE_FOOTER_SIZE + 8 {
+        return Ok(None);
+    }
+    let footer = read_blob(size - ZSTD_SEEK_TABLE_FOOTER_SIZE..size).await?;
+    let frames = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+    let descriptor = footer[4];
+    if u32::from_le_bytes(footer[5..].try_into().unwrap()) != ZSTD_SEEKABLE_MAGIC {
+        return Ok(None);
+    }
+    let entry_size = if descriptor & ZSTD_SEEK_TABLE_CHECKSUM_FLAG != 0 {
+        12
+    } else {
+        8
+    };
+    // skippable frame header, entries and footer
+    let table_size = 8 + frames * entry_size + ZSTD_SEEK_TABLE_FOOTER_SIZE;
+    if table_size > size {
+        return Ok(None);
+    }
+    let table = read_blob(size - table_size..size - ZSTD_SEEK_TABLE_FOOTER_SIZE).await?;
+    let word = |offset: usize| u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap());
+    if word(0) != ZSTD_SKIPPABLE_MAGIC || word(4) as u64 != table_size - 8 {
+        return Ok(None);
+    }
+
+    let mut index = Vec::with_capacity(frames as usize + 1);
+    let (mut compressed, mut uncompressed) = (0, 0);
+    for entry in 0..frames as usize {
+        index.push(Frame {
+            compressed,
+            uncompressed,
+        });
+        let offset = 8 + entry * entry_size as usize;
+        compressed += word(offset) as u64;
+        uncompressed += word(offset + 4) as u64;
+    }
+    if compressed != size - table_size {
+        return Ok(None);
+    }
+    index.push(Frame {
+        compressed,
+        uncompressed,
+    });
+    Ok(Some(SeekIndex::Zstd(index)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GzipState {
+    Header,
+    Inflate,
+    Trailer,
+    Done,
+}
+
+/// Decompresses a (possibly multi-member) gzip stream, optionally recording
+/// checkpoints along the way.
+pub(crate) struct GzipReader<R> {
+    inner: R,
+    /// Offset of the next byte of `inner` in the compressed stream.
+    position: u64,
+    state: GzipState,
+    decompressor: Box<DecompressorOxide>,
+    /// Ring buffer the decompressor writes to, holding the 32 KiB window.
+    window: Box<[u8]>,
+    window_pos: usize,
+    /// Part of `window` decompressed but not returned yet.
+    pending: Range<usize>,
+    /// Bytes decompressed so far, in total and in the current member.
+    total_out: u64,
+    member_out: u64,
+    /// Checksum of the current member, unless it was entered mid-way.
+    crc: Option<Crc>,
+    span: Option<u64>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl<R: BufRead> GzipReader<R> {
+    /// Decompresses `inner` from the start of the gzip stream.
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            position: 0,
+            state: GzipState::Header,
+            decompressor: Box::default(),
+            window: vec![0; TINFL_LZ_DICT_SIZE].into_boxed_slice(),
+            window_pos: 0,
+            pending: 0..0,
+            total_out: 0,
+            member_out: 0,
+            crc: None,
+            span: None,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Decompresses `inner`, the gzip stream from `checkpoint` onwards.
+    fn resume(inner: R, checkpoint: &Checkpoint) -> io::Result<Self> {
+        let mut window = Vec::with_capacity(TINFL_LZ_DICT_SIZE);
+        let deflated = STANDARD
+            .decode(&checkpoint.window)
+            .map_err(io::Error::other)?;
+        flate2::read::DeflateDecoder::new(&deflated[..]).read_to_end(&mut window)?;
+        if window.len() != TINFL_LZ_DICT_SIZE {
+            return Err(invalid_data("invalid checkpoint window"));
+        }
+        let state = BlockBoundaryState {
+            num_bits: checkpoint.bits,
+            bit_buf: checkpoint.bit_buf,
+            ..Default::default()
+        };
+        Ok(Self {
+            position: checkpoint.compressed,
+            state: GzipState::Inflate,
+            decompressor: Box::new(DecompressorOxide::from_block_boundary_state(&state)),
+            window: window.into_boxed_slice(),
+            total_out: checkpoint.uncompressed,
+            ..Self::new(inner)
+        })
+    }
+
+    /// Records a checkpoint every `span` uncompressed bytes.
+    pub(crate) fn with_checkpoints(mut self, span: u64) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Returns the checkpoints recorded so far.
+    pub(crate) fn into_checkpoints(self) -> Vec<Checkpoint> {
+        self.checkpoints
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; 10];
+        self.read_bytes(&mut header)?;
+        if header[..3] != GZIP_MAGIC {
+            return Err(invalid_data("invalid gzip header"));
+        }
+        let flags = header[3];
+        if flags & GZIP_FEXTRA != 0 {
+            let mut len = [0; 2];
+            self.read_bytes(&mut len)?;
+            self.read_bytes(&mut vec![0; u16::from_le_bytes(len) as usize])?;
+        }
+        for flag in [GZIP_FNAME, GZIP_FCOMMENT] {
+            if flags & flag != 0 {
+                let mut byte = [1];
+                while byte[0] != 0 {
+                    self.read_bytes(&mut byte)?;
+                }
+            }
+        }
+        if flags & GZIP_FHCRC != 0 {
+            self.read_bytes(&mut [0; 2])?;
+        }
+
+        self.state = GzipState::Inflate;
+        self.decompressor.init();
+        self.member_out = 0;
+        self.crc = Some(Crc::new());
+        Ok(())
+    }
+
+    fn inflate(&mut self) -> io::Result<()> {
+        let input = self.inner.fill_buf()?;
+        let mut flags = 0;
+        if !input.is_empty() {
+            flags |= TINFL_FLAG_HAS_MORE_INPUT;
+        }
+        if self.span.is_some() {
+            flags |= TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY;
+        }
+        let (status, consumed, produced) = decompress(
+            &mut self.decompressor,
+            input,
+            &mut self.window,
+            self.window_pos,
+            flags,
+        );
+        self.inner.consume(consumed);
+        self.position += consumed as u64;
+        self.pending = self.window_pos..self.window_pos + produced;
+        self.window_pos = (self.window_pos + produced) % TINFL_LZ_DICT_SIZE;
+        self.total_out += produced as u64;
+        self.member_out += produced as u64;
+
+        match status {
+            TINFLStatus::Done => self.state = GzipState::Trailer,
+            TINFLStatus::BlockBoundary => self.checkpoint()?,
+            TINFLStatus::NeedsMoreInput | TINFLStatus::HasMoreOutput => {}
+            // what was decompressed is returned before failing
+            _ if produced > 0 => {}
+            TINFLStatus::FailedCannotMakeProgress => {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            _ => return Err(invalid_data("invalid deflate stream")),
+        }
+        Ok(())
+    }
+
+    /// Saves the state of the decompressor if `span` bytes were decompressed
+    /// since the last checkpoint.
+    fn checkpoint(&mut self) -> io::Result<()> {
+        let Some(span) = self.span else {
+            return Ok(());
+        };
+        let last = self.checkpoints.last().map_or(0, |c| c.uncompressed);
+        if self.total_out < last + span {
+            return Ok(());
+        }
+        let Some(state) = self.decompressor.block_boundary_state() else {
+            return Ok(());
+        };
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&self.window[self.window_pos..])?;
+        encoder.write_all(&self.window[..self.window_pos])?;
+        self.checkpoints.push(Checkpoint {
+            compressed: self.position,
+            bits: state.num_bits,
+            bit_buf: state.bit_buf,
+            uncompressed: self.total_out,
+            window: STANDARD.encode(encoder.finish()?),
+        });
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> io::Result<()> {
+        let mut trailer = [0; 8];
+        self.read_bytes(&mut trailer)?;
+        if let Some(crc) = self.crc.take() {
+            let expected_crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+            let expected_size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+            if crc.sum() != expected_crc || self.member_out as u32 != expected_size {
+                return Err(invalid_data("corrupt gzip stream"));
+            }
+        }
+        // gzip members can be concatenated
+        self.state = if self.inner.fill_buf()?.is_empty() {
+            GzipState::Done
+        } else {
+            GzipState::Header
+        };
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.pending.is_empty() {
+                let len = buf.len().min(self.pending.len());
+                let data = &self.window[self.pending.start..self.pending.start + len];
+                buf[..len].copy_from_slice(data);
+                if let Some(crc) = &mut self.crc {
+                    crc.update(data);
+                }
+                self.pending.start += len;
+                return Ok(len);
+            }
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            match self.state {
+                GzipState::Header => self.read_header()?,
+                GzipState::Inflate => self.inflate()?,
+                GzipState::Trailer => self.read_trailer()?,
+                GzipState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::fixtures::gzip;
+
+    /// Random text, which spans many deflate blocks.
+    fn sample(len: usize) -> Vec<u8> {
+        let mut rng = fastrand::Rng::with_seed(7);
+        (0..len).map(|_| rng.alphabetic() as u8).collect()
+    }
+
+    async fn read_slice(blob: &[u8], range: Range<u64>) -> Result<Vec<u8>> {
+        Ok(blob[range.start as usize..range.end as usize].to_vec())
+    }
+
+    #[test]
+    fn test_gzip_checkpoints() {
+        let data = sample(1 << 20);
+        let (first, second) = data.split_at(400_000);
+        // members of a multi-member stream resume like a single one
+        let mut blob = gzip(first);
+        blob.extend(gzip(second));
+
+        let mut reader = GzipReader::new(&blob[..]).with_checkpoints(16 << 10);
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert!(decompressed == data);
+        let index = SeekIndex::Gzip(reader.into_checkpoints());
+        let SeekIndex::Gzip(checkpoints) = &index else {
+            unreachable!()
+        };
+        assert!(checkpoints.len() > 8);
+
+        let size = data.len() as u64;
+        for range in [0..100, 123_456..130_000, 399_000..401_000, size - 10..size] {
+            let compressed = index.compressed_range(&range, blob.len() as u64);
+            let content = index
+                .read(
+                    &range,
+                    &blob[compressed.start as usize..compressed.end as usize],
+                )
+                .unwrap();
+            assert!(content == data[range.start as usize..range.end as usize]);
+        }
+        // the end of the stream does not need the start of the blob
+        let compressed = index.compressed_range(&(size - 10..size), blob.len() as u64);
+        assert!(compressed.start > blob.len() as u64 / 2);
+        let compressed = index.compressed_range(&(0..100), blob.len() as u64);
+        assert!(compressed.end < blob.len() as u64 / 2);
+
+        let mut corrupt = gzip(first);
+        let crc = corrupt.len() - 8;
+        corrupt[crc] ^= 1;
+        assert!(
+            GzipReader::new(&corrupt[..])
+                .read_to_end(&mut Vec::new())
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zstd_seek_table() {
+        let data = sample(300_000);
+        let mut blob = Vec::new();
+        let mut entries = Vec::new();
+        for frame in data.chunks(64 << 10) {
+            let compressed = zstd::encode_all(frame, 0).unwrap();
+            entries.extend((compressed.len() as u32).to_le_bytes());
+            entries.extend((frame.len() as u32).to_le_bytes());
+            blob.extend(compressed);
+        }
+        let frames = data.len().div_ceil(64 << 10) as u32;
+        blob.extend(ZSTD_SKIPPABLE_MAGIC.to_le_bytes());
+        blob.extend((entries.len() as u32 + 9).to_le_bytes());
+        blob.extend(entries);
+        blob.extend(frames.to_le_bytes());
+        blob.push(0);
+        blob.extend(ZSTD_SEEKABLE_MAGIC.to_le_bytes());
+
+        let size = blob.len() as u64;
+        let index = zstd_seek_table(size, async |range| read_slice(&blob, range).await)
+            .await
+            .unwrap()
+            .unwrap();
+        for range in [0..10, 70_000..140_000, 299_990..300_000] {
+            let compressed = index.compressed_range(&range, size);
+            let content = index
+                .read(
+                    &range,
+                    &blob[compressed.start as usize..compressed.end as usize],
+                )
+                .unwrap();
+            assert!(content == data[range.start as usize..range.end as usize]);
+        }
+
+        // plain zstd streams have no seek table
+        let plain = zstd::encode_all(&data[..], 0).unwrap();
+        let size = plain.len() as u64;
+        let table = zstd_seek_table(size, async |range| read_slice(&plain, range).await);
+        assert!(table.await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,113 @@
+//! Resolution of symbolic links within the root of an image.
+//!
+//! Paths are resolved the way the kernel would inside a container: absolute
+//! targets start again from the image root, relative targets from the
+//! directory holding the link, and `..` never climbs above the root.
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Component, Path, PathBuf};
+
+/// Number of symbolic links followed while resolving one path, as on Linux.
+pub(crate) const MAX_SYMLINKS: usize = 40;
+
+/// Resolves the symbolic links along `path`, returning the path of the entry
+/// it designates relative to the image root. The last component is only
+/// followed when `follow_last` is set.
+///
+/// `read_link` returns the target of the symbolic link at a resolved path, or
+/// `None` for other entries. Components that do not exist are kept as they
+/// are, so that the caller reports them missing. Returns `None` when the path
+/// runs into a symlink loop or goes through more than [`MAX_SYMLINKS`] links.
+pub(crate) fn resolve(
+    path: &Path,
+    follow_last: bool,
+    read_link: impl Fn(&Path) -> Option<String>,
+) -> Option<PathBuf> {
+    let mut pending = components(path);
+    let mut resolved = PathBuf::new();
+    // expansions seen so far: running into one again means a loop
+    let mut seen = HashSet::new();
+    while let Some(component) = pending.pop_front() {
+        if component == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&component);
+        let target = if follow_last || !pending.is_empty() {
+            read_link(&candidate)
+        } else {
+            None
+        };
+        let Some(target) = target else {
+            resolved = candidate;
+            continue;
+        };
+        if seen.len() == MAX_SYMLINKS || !seen.insert((candidate, pending.clone())) {
+            return None;
+        }
+        if target.starts_with('/') {
+            resolved = PathBuf::new();
+        }
+        for component in components(Path::new(&target)).into_iter().rev() {
+            pending.push_front(component);
+        }
+    }
+    Some(resolved)
+}
+
+/// Splits `path` into its names and `..`, dropping the root and `.`.
+fn components(path: &Path) -> VecDeque<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            Component::ParentDir => Some("..".to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let links: HashMap<&Path, &str> = [
+            ("lib", "usr/lib"),
+            ("bin", "usr/bin"),
+            ("usr/bin/sh", "/bin/busybox"),
+            ("usr/lib/libc.so", "../../lib/ld-musl.so.1"),
+            ("loop/a", "b"),
+            ("loop/b", "./a"),
+            ("deep", "deep/x"),
+        ]
+        .into_iter()
+        .map(|(link, target)| (Path::new(link), target))
+        .collect();
+        let resolve = |path: &str, follow_last| {
+            resolve(Path::new(path), follow_last, |path| {
+                links.get(path).map(|target| target.to_string())
+            })
+        };
+
+        assert_eq!(
+            resolve("/bin/sh", true).unwrap(),
+            Path::new("usr/bin/busybox")
+        );
+        assert_eq!(resolve("bin/sh", false).unwrap(), Path::new("usr/bin/sh"));
+        assert_eq!(
+            resolve("/lib/libc.so", true).unwrap(),
+            Path::new("usr/lib/ld-musl.so.1")
+        );
+        assert_eq!(
+            resolve("/../etc/./passwd", true).unwrap(),
+            Path::new("etc/passwd")
+        );
+        assert_eq!(resolve("/", true).unwrap(), Path::new(""));
+        assert!(resolve("loop/a", true).is_none());
+        assert_eq!(resolve("loop/a", false).unwrap(), Path::new("loop/a"));
+        assert!(resolve("deep", true).is_none());
+    }
+}
@@ -0,0 +1,674 @@
+//! Tables of contents of eStargz and zstd:chunked layers.
+//!
+//! Both formats compress every file separately and append a JSON listing of
+//! the layer's entries with the compressed offset of each file, so the layer
+//! can be listed and single files extracted without decompressing the rest.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+
+use super::image_reader::{ImageReaderError, Result};
+use super::layer_index::{self, Chunk, DataLocation, LayerChange};
+use super::vfs::{FileEntry, FileMetadata};
+use crate::digest::Digester;
+use crate::manifest::Descriptor;
+
+/// Layer annotation holding the digest of an eStargz TOC.
+pub const ESTARGZ_TOC_DIGEST_ANNOTATION: &str = "containerd.io/snapshot/stargz/toc.digest";
+/// Layer annotation holding the digest of a compressed zstd:chunked manifest.
+pub const ZSTD_CHUNKED_MANIFEST_CHECKSUM_ANNOTATION: &str =
+    "io.github.containers.zstd-chunked.manifest-checksum";
+/// Layer annotation locating the zstd:chunked manifest as
+/// `<offset>:<length>:<uncompressed length>:<type>`.
+pub const ZSTD_CHUNKED_MANIFEST_POSITION_ANNOTATION: &str =
+    "io.github.containers.zstd-chunked.manifest-position";
+
+/// Size of the footer closing an eStargz layer.
+const ESTARGZ_FOOTER_SIZE: u64 = 51;
+/// Size of the footer written by the first stargz implementations.
+const LEGACY_ESTARGZ_FOOTER_SIZE: usize = 47;
+/// Name of the tar entry holding an eStargz TOC.
+pub(crate) const ESTARGZ_TOC_NAME: &str = "stargz.index.json";
+/// Entries eStargz adds for its own bookkeeping.
+const ESTARGZ_INTERNAL_NAMES: &[&str] = &[
+    ESTARGZ_TOC_NAME,
+    ".prefetch.landmark",
+    ".no.prefetch.landmark",
+];
+
+/// Layer format that carries a table of contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TocFormat {
+    /// Gzip members per file with the TOC located by a footer.
+    Estargz { toc_digest: String },
+    /// Zstd frames per file with the TOC at the annotated position.
+    ZstdChunked {
+        manifest_digest: String,
+        position: Range<u64>,
+    },
+}
+
+impl TocFormat {
+    /// Detects the format from the annotations of a layer descriptor.
+    pub(crate) fn detect(layer: &Descriptor) -> Option<Self> {
+        let annotations = layer.annotations.as_ref()?;
+        if let Some(digest) = annotations.get(ZSTD_CHUNKED_MANIFEST_CHECKSUM_ANNOTATION)
+            && let Some(position) = annotations
+                .get(ZSTD_CHUNKED_MANIFEST_POSITION_ANNOTATION)
+                .map(String::as_str)
+                .and_then(parse_position)
+        {
+            return Some(Self::ZstdChunked {
+                manifest_digest: digest.clone(),
+                position,
+            });
+        }
+        annotations
+            .get(ESTARGZ_TOC_DIGEST_ANNOTATION)
+            .map(|digest| Self::Estargz {
+                toc_digest: digest.clone(),
+            })
+    }
+
+    /// Returns the compression of the chunks.
+    fn compression(&self) -> Compression {
+        match self {
+            Self::Estargz { .. } => Compression::Gzip,
+            Self::ZstdChunked { .. } => Compression::Zstd,
+        }
+    }
+}
+
+/// Compression of the chunks of a layer with a table of contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// Reads `<offset>:<length>:...` into the range it describes.
+fn parse_position(position: &str) -> Option<Range<u64>> {
+    let mut parts = position.split(':');
+    let offset: u64 = parts.next()?.parse().ok()?;
+    let length: u64 = parts.next()?.parse().ok()?;
+    Some(offset..offset.checked_add(length)?)
+}
+
+/// Returns the offset of the TOC from the footer of an eStargz layer, given
+/// the last [`ESTARGZ_FOOTER_SIZE`] bytes of the blob, along with the size
+/// of the footer found.
+fn estargz_toc_offset(footer: &[u8]) -> Option<(u64, u64)> {
+    // current footer: gzip header with an `SG` extra subfield of 22 bytes
+    if footer.len() >= ESTARGZ_FOOTER_SIZE as usize {
+        let footer = &footer[footer.len() - ESTARGZ_FOOTER_SIZE as usize..];
+        if is_gzip_with_extra(footer, 26) && &footer[12..16] == b"SG\x16\x00" {
+            return parse_offset(&footer[16..38]).map(|offset| (offset, ESTARGZ_FOOTER_SIZE));
+        }
+    }
+    // legacy footer: the 22 bytes are the whole extra field
+    if footer.len() >= LEGACY_ESTARGZ_FOOTER_SIZE {
+        let footer = &footer[footer.len() - LEGACY_ESTARGZ_FOOTER_SIZE..];
+        if is_gzip_with_extra(footer, 22) {
+            return parse_offset(&footer[12..34])
+                .map(|offset| (offset, LEGACY_ESTARGZ_FOOTER_SIZE as u64));
+        }
+    }
+    None
+}
+
+fn is_gzip_with_extra(header: &[u8], extra_len: u16) -> bool {
+    header[..3] == [0x1f, 0x8b, 0x08]
+        && header[3] & 0x04 != 0
+        && u16::from_le_bytes([header[10], header[11]]) == extra_len
+}
+
+/// Parses `%016xSTARGZ`.
+fn parse_offset(field: &[u8]) -> Option<u64> {
+    let (hex, magic) = field.split_at(16);
+    if magic != b"STARGZ" {
+        return None;
+    }
+    u64::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+}
+
+/// Table of contents shared by eStargz and zstd:chunked.
+#[derive(Debug, Deserialize)]
+struct Toc {
+    entries: Vec<TocEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TocEntry {
+    name: String,
+    #[serde(rename = "type")]
+    entry_type: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    link_name: String,
+    #[serde(default)]
+    dev_major: u32,
+    #[serde(default)]
+    dev_minor: u32,
+    #[serde(default)]
+    offset: u64,
+    /// Exclusive end of the compressed chunk, recorded by zstd:chunked only.
+    #[serde(default)]
+    end_offset: u64,
+    #[serde(default)]
+    chunk_offset: u64,
+    #[serde(default)]
+    chunk_size: u64,
+    #[serde(default)]
+    chunk_digest: Option<String>,
+    #[serde(default)]
+    inner_offset: u64,
+    /// `zeros` for holes of zstd:chunked sparse files.
+    #[serde(default)]
+    chunk_type: Option<String>,
+    #[serde(default)]
+    mode: u32,
+    #[serde(default)]
+    uid: u64,
+    #[serde(default)]
+    gid: u64,
+    #[serde(default)]
+    user_name: Option<String>,
+    #[serde(default)]
+    group_name: Option<String>,
+    /// RFC 3339 modification time.
+    #[serde(default, rename = "modtime")]
+    mod_time: Option<String>,
+    /// Extended attributes with base64 encoded values.
+    #[serde(default)]
+    xattrs: HashMap<String, String>,
+}
+
+impl TocEntry {
+    fn metadata(&self) -> FileMetadata {
+        FileMetadata {
+            mode: self.mode & 0o7777,
+            uid: self.uid,
+            gid: self.gid,
+            user: self.user_name.clone().filter(|name| !name.is_empty()),
+            group: self.group_name.clone().filter(|name| !name.is_empty()),
+            mtime: self
+                .mod_time
+                .as_deref()
+                .and_then(parse_rfc3339)
+                .unwrap_or_default(),
+            xattrs: self
+                .xattrs
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), STANDARD.decode(value).ok()?)))
+                .collect(),
+        }
+    }
+}
+
+/// Parses an RFC 3339 timestamp such as `2024-05-01T12:30:00.5+02:00` into
+/// seconds since the Unix epoch.
+fn parse_rfc3339(time: &str) -> Option<u64> {
+    let number = |range: Range<usize>| -> Option<i64> { time.get(range)?.parse().ok() };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    let zone = time
+        .get(19..)?
+        .trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match zone.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let hours: i64 = zone.get(1..3)?.parse().ok()?;
+            let minutes: i64 = zone.get(4..6)?.parse().ok()?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+    // days from the civil date, after Howard Hinnant's algorithm
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(seconds).ok()
+}
+
+/// Lists the changes of a layer from its table of contents, reading the
+/// footer and the TOC with `read_range` instead of the whole blob.
+pub(crate) async fn read_toc(
+    format: &TocFormat,
+    layer: &Descriptor,
+    layer_index: usize,
+    read_range: impl AsyncFn(Range<u64>) -> Result<Vec<u8>>,
+) -> Result<Vec<LayerChange>> {
+    match format {
+        TocFormat::Estargz { toc_digest } => {
+            let footer_start = layer.size.saturating_sub(ESTARGZ_FOOTER_SIZE);
+            let footer = read_range(footer_start..layer.size).await?;
+            let (toc_offset, footer_size) =
+                estargz_toc_offset(&footer).ok_or_else(|| invalid_toc("no eStargz footer"))?;
+            let member = read_range(toc_offset..layer.size - footer_size).await?;
+            read_estargz_toc(&member, toc_digest, toc_offset, layer_index)
+        }
+        TocFormat::ZstdChunked {
+            manifest_digest,
+            position,
+        } => {
+            let manifest = read_range(position.clone()).await?;
+            read_zstd_chunked_toc(&manifest, manifest_digest, layer_index)
+        }
+    }
+}
+
+/// Reads a file stored as `chunks` of a layer in `format`, fetching and
+/// decompressing only those chunks.
+pub(crate) async fn read_chunks(
+    format: &TocFormat,
+    chunks: &[Chunk],
+    size: u64,
+    read_range: impl AsyncFn(Range<u64>) -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size as usize);
+    for chunk in chunks {
+        let data = read_range(chunk.compressed.clone()).await?;
+        content.extend(decode_chunk(format.compression(), chunk, &data)?);
+    }
+    Ok(content)
+}
+
+/// Decodes the TOC member of an eStargz layer, the blob range between the
+/// offset given by the footer and the footer, and lists its changes.
+///
+/// `toc_offset` also ends the compressed range of the last file.
+fn read_estargz_toc(
+    member: &[u8],
+    toc_digest: &str,
+    toc_offset: u64,
+    layer_index: usize,
+) -> Result<Vec<LayerChange>> {
+    let mut archive = tar::Archive::new(MultiGzDecoder::new(member));
+    let mut toc = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == ESTARGZ_TOC_NAME {
+            let mut json = Vec::new();
+            entry.read_to_end(&mut json)?;
+            toc = Some(json);
+            break;
+        }
+    }
+    let json = toc.ok_or_else(|| invalid_toc("no stargz.index.json in the TOC"))?;
+    verify_digest(&json, toc_digest)?;
+    let toc: Toc = serde_json::from_slice(&json)?;
+    Ok(changes(toc, layer_index, Some(toc_offset)))
+}
+
+/// Decodes the compressed manifest of a zstd:chunked layer and lists its
+/// changes.
+fn read_zstd_chunked_toc(
+    manifest: &[u8],
+    manifest_digest: &str,
+    layer_index: usize,
+) -> Result<Vec<LayerChange>> {
+    verify_digest(manifest, manifest_digest)?;
+    let json = zstd::decode_all(manifest)?;
+    let toc: Toc = serde_json::from_slice(&json)?;
+    Ok(changes(toc, layer_index, None))
+}
+
+fn verify_digest(data: &[u8], expected: &str) -> Result<()> {
+    let mut digester = Digester::for_digest(expected)
+        .ok_or_else(|| invalid_toc(&format!("unsupported digest {expected}")))?;
+    digester.update(data);
+    let actual = digester.finalize();
+    if actual != expected {
+        return Err(invalid_toc(&format!(
+            "digest {actual} instead of {expected}"
+        )));
+    }
+    Ok(())
+}
+
+fn invalid_toc(reason: &str) -> ImageReaderError {
+    ImageReaderError::InvalidToc(reason.to_string())
+}
+
+/// Turns TOC entries into layer changes.
+///
+/// eStargz records only where each chunk starts, so a chunk ends where the
+/// next one starts, or at `data_end` for the last one.
+fn changes(toc: Toc, layer_index: usize, data_end: Option<u64>) -> Vec<LayerChange> {
+    let starts: BTreeSet<u64> = toc
+        .entries
+        .iter()
+        .filter(|entry| match entry.entry_type.as_str() {
+            "reg" => entry.size > 0,
+            "chunk" => true,
+            _ => false,
+        })
+        .map(|entry| entry.offset)
+        .chain(data_end)
+        .collect();
+    let chunk = |entry: &TocEntry, file_size: u64| {
+        let size = match entry.chunk_size {
+            0 => file_size.saturating_sub(entry.chunk_offset),
+            size => size,
+        };
+        let end = if entry.end_offset > entry.offset {
+            entry.end_offset
+        } else {
+            starts
+                .range(entry.offset + 1..)
+                .next()
+                .copied()
+                .unwrap_or(entry.offset)
+        };
+        let compressed = if entry.chunk_type.as_deref() == Some("zeros") {
+            0..0
+        } else {
+            entry.offset..end
+        };
+        Chunk {
+            compressed,
+            inner_offset: entry.inner_offset,
+            size,
+            digest: entry.chunk_digest.clone(),
+        }
+    };
+
+    let mut changes = Vec::new();
+    // index in `changes` and size of every file, to attach its later chunks
+    let mut files: HashMap<PathBuf, (usize, u64)> = HashMap::new();
+    for entry in &toc.entries {
+        let path = layer_index::normalize_path(Path::new(&entry.name));
+        if path.as_os_str().is_empty() || ESTARGZ_INTERNAL_NAMES.contains(&entry.name.as_str()) {
+            continue;
+        }
+        if let Some(change) = layer_index::whiteout(&path) {
+            changes.push(change);
+            continue;
+        }
+        let (file_entry, location) = match entry.entry_type.as_str() {
+            "reg" => {
+                let chunks = if entry.size > 0 {
+                    vec![chunk(entry, entry.size)]
+                } else {
+                    Vec::new()
+                };
+                files.insert(path.clone(), (changes.len(), entry.size));
+                (
+                    FileEntry::File {
+                        size: entry.size,
+                        layer_index,
+                        metadata: entry.metadata(),
+                    },
+                    Some(DataLocation::Chunks(chunks)),
+                )
+            }
+            "chunk" => {
+                if let Some(&(index, size)) = files.get(&path)
+                    && let Some(LayerChange::Add {
+                        location: Some(DataLocation::Chunks(chunks)),
+                        ..
+                    }) = changes.get_mut(index)
+                {
+                    chunks.push(chunk(entry, size));
+                }
+                continue;
+            }
+            "dir" => (
+                FileEntry::Directory {
+                    layer_index,
+                    metadata: entry.metadata(),
+                },
+                None,
+            ),
+            "symlink" => (
+                FileEntry::Symlink {
+                    target: entry.link_name.clone(),
+                    layer_index,
+                    metadata: entry.metadata(),
+                },
+                None,
+            ),
+            "hardlink" => (
+                layer_index::hardlink(&entry.link_name, layer_index, entry.metadata()),
+                None,
+            ),
+            "char" => (
+                FileEntry::CharDevice {
+                    major: entry.dev_major,
+                    minor: entry.dev_minor,
+                    layer_index,
+                    metadata: entry.metadata(),
+                },
+                None,
+            ),
+            "block" => (
+                FileEntry::BlockDevice {
+                    major: entry.dev_major,
+                    minor: entry.dev_minor,
+                    layer_index,
+                    metadata: entry.metadata(),
+                },
+                None,
+            ),
+            "fifo" => (
+                FileEntry::Fifo {
+                    layer_index,
+                    metadata: entry.metadata(),
+                },
+                None,
+            ),
+            _ => continue,
+        };
+        changes.push(LayerChange::Add {
+            path,
+            entry: file_entry,
+            location,
+        });
+    }
+    layer_index::resolve_hardlinks(&mut changes);
+    changes
+}
+
+/// Decompresses `data`, the compressed range of `chunk`, and returns the
+/// chunk's content.
+fn decode_chunk(compression: Compression, chunk: &Chunk, data: &[u8]) -> Result<Vec<u8>> {
+    if chunk.compressed.is_empty() {
+        return Ok(vec![0; chunk.size as usize]);
+    }
+    let mut decoder: Box<dyn Read> = match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(data)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(data)?),
+    };
+    io::copy(
+        &mut decoder.by_ref().take(chunk.inner_offset),
+        &mut io::sink(),
+    )?;
+    let mut content = Vec::with_capacity(chunk.size as usize);
+    decoder.take(chunk.size).read_to_end(&mut content)?;
+    if content.len() as u64 != chunk.size {
+        return Err(invalid_toc(&format!(
+            "chunk at {} holds {} bytes instead of {}",
+            chunk.compressed.start,
+            content.len(),
+            chunk.size
+        )));
+    }
+    if let Some(digest) = &chunk.digest
+        && let Some(mut digester) = Digester::for_digest(digest)
+    {
+        digester.update(&content);
+        if digester.finalize() != *digest {
+            return Err(invalid_toc(&format!(
+                "chunk at {} does not match {digest}",
+                chunk.compressed.start
+            )));
+        }
+    }
+    Ok(content)
+}
+
+/// Builds the footer eStargz writers append to a layer whose TOC starts at
+/// `toc_offset`.
+#[cfg(test)]
+pub(crate) fn estargz_footer(toc_offset: u64) -> Vec<u8> {
+    let mut footer = vec![0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 26, 0];
+    footer.extend_from_slice(b"SG\x16\x00");
+    footer.extend_from_slice(format!("{toc_offset:016x}STARGZ").as_bytes());
+    footer.extend_from_slice(&[1, 0, 0, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0]);
+    footer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::fixtures::{
+        GZIP_LAYER, ZSTD_LAYER, descriptor, estargz_layer, image, zstd_chunked_layer,
+    };
+
+    #[test]
+    fn test_estargz_footer() {
+        let footer = estargz_footer(1234);
+        assert_eq!(footer.len(), ESTARGZ_FOOTER_SIZE as usize);
+        assert_eq!(estargz_toc_offset(&footer), Some((1234, 51)));
+
+        let mut legacy = vec![0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 22, 0];
+        legacy.extend_from_slice(format!("{:016x}STARGZ", 99).as_bytes());
+        legacy.extend_from_slice(&[1, 0, 0, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(estargz_toc_offset(&legacy), Some((99, 47)));
+        assert_eq!(estargz_toc_offset(&[0; 51]), None);
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339("2024-05-01T12:30:00Z"), Some(1_714_566_600));
+        assert_eq!(
+            parse_rfc3339("2024-05-01T14:30:00.123456789+02:00"),
+            Some(1_714_566_600)
+        );
+        assert_eq!(parse_rfc3339("yesterday"), None);
+    }
+
+    #[test]
+    fn test_toc_changes() {
+        let toc: Toc = serde_json::from_str(
+            r#"{"version":1,"entries":[
+                {"name":"./","type":"dir"},
+                {"name":"bin/","type":"dir"},
+                {"name":"bin/big","type":"reg","size":10,"offset":100,"endOffset":150,"chunkSize":6,
+                 "mode":2541,"uid":0,"gid":50,"userName":"root","groupName":"staff",
+                 "modtime":"2024-05-01T12:30:00Z","xattrs":{"security.capability":"AQAAAg=="}},
+                {"name":"bin/big","type":"chunk","offset":150,"endOffset":180,"chunkOffset":6,"chunkSize":0},
+                {"name":"bin/sparse","type":"reg","size":8,"chunkType":"zeros","chunkSize":8},
+                {"name":"bin/sh","type":"symlink","linkName":"big"},
+                {"name":"etc/.wh.passwd","type":"reg"},
+                {"name":"stargz.index.json","type":"reg","size":1,"offset":200}
+            ]}"#,
+        )
+        .unwrap();
+        let changes = changes(toc, 2, None);
+        assert_eq!(changes.len(), 5);
+        let LayerChange::Add {
+            path,
+            entry:
+                FileEntry::File {
+                    size: 10,
+                    layer_index: 2,
+                    metadata,
+                },
+            location: Some(DataLocation::Chunks(chunks)),
+        } = &changes[1]
+        else {
+            panic!("unexpected change {:?}", changes[1]);
+        };
+        assert_eq!(path, &PathBuf::from("bin/big"));
+        assert_eq!(metadata.permissions(), "rwsr-xr-x");
+        assert_eq!((metadata.uid, metadata.gid), (0, 50));
+        assert_eq!(metadata.group.as_deref(), Some("staff"));
+        assert_eq!(metadata.mtime, 1_714_566_600);
+        assert_eq!(metadata.xattrs["security.capability"], vec![1, 0, 0, 2]);
+        let ranges: Vec<_> = chunks
+            .iter()
+            .map(|c| (c.compressed.clone(), c.size))
+            .collect();
+        assert_eq!(ranges, vec![(100..150, 6), (150..180, 4)]);
+        let LayerChange::Add {
+            location: Some(DataLocation::Chunks(chunks)),
+            ..
+        } = &changes[2]
+        else {
+            panic!("unexpected change {:?}", changes[2]);
+        };
+        assert!(chunks[0].compressed.is_empty());
+        assert_eq!(
+            decode_chunk(Compression::Zstd, &chunks[0], &[]).unwrap(),
+            vec![0; 8]
+        );
+        assert!(
+            matches!(&changes[4], LayerChange::Whiteout(path) if path == &PathBuf::from("etc/passwd"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_local_layers_from_toc() {
+        let (base, base_annotations) =
+            estargz_layer(&[("etc/hostname", b"base"), ("etc/motd", b"hello")]);
+        let (top, top_annotations) = zstd_chunked_layer(&[("etc/hostname", b"top")]);
+        let mut base_layer = descriptor(GZIP_LAYER, &base);
+        base_layer.annotations = Some(base_annotations);
+        let mut top_layer = descriptor(ZSTD_LAYER, &top);
+        top_layer.annotations = Some(top_annotations);
+        let (manifest, blobs) = image("linux", vec![(base_layer, base), (top_layer, top)]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let blobs_dir = dir.path().join("blobs/sha256");
+        std::fs::create_dir_all(&blobs_dir).unwrap();
+        let write_blob = |data: &[u8]| {
+            let digest = crate::digest::sha256_digest(data);
+            std::fs::write(blobs_dir.join(digest.trim_start_matches("sha256:")), data).unwrap();
+            digest
+        };
+        for blob in blobs.values() {
+            write_blob(blob);
+        }
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": write_blob(&manifest),
+                "size": manifest.len(),
+            }],
+        });
+        std::fs::write(dir.path().join("index.json"), index.to_string()).unwrap();
+        std::fs::write(
+            dir.path().join("oci-layout"),
+            r#"{"imageLayoutVersion":"1.0.0"}"#,
+        )
+        .unwrap();
+
+        let reader = crate::reader::build_image_reader(dir.path()).await.unwrap();
+        assert_eq!(reader.read_file("etc/hostname").await.unwrap(), b"top");
+        assert_eq!(reader.read_file("etc/motd").await.unwrap(), b"hello");
+    }
+}
@@ -1,22 +1,194 @@
 //! Lightweight virtual filesystem for materialising file listings in OCI images.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+
 use super::dir_tree::DirectoryTree;
+use super::symlink;
 
 /// Metadata recorded for each entry tracked by the virtual filesystem.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FileEntry {
     /// Regular file along with its size and layer index.
-    File { size: u64, layer_index: usize },
+    File {
+        size: u64,
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
     /// Directory created in the given layer.
-    Directory { layer_index: usize },
+    Directory {
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
     /// Symbolic link pointing at `target`.
-    Symlink { target: String, layer_index: usize },
+    Symlink {
+        target: String,
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
+    /// Hard link to the regular file at `target`, whose `size` bytes of
+    /// content are stored in the layer at `layer_index`.
+    Hardlink {
+        target: String,
+        size: u64,
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
+    /// Character device node such as `/dev/null`.
+    CharDevice {
+        major: u32,
+        minor: u32,
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
+    /// Block device node such as `/dev/sda`.
+    BlockDevice {
+        major: u32,
+        minor: u32,
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
+    /// Named pipe.
+    Fifo {
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
+    /// GNU sparse file of `size` bytes once its holes are filled with zeros.
+    /// Its content is not indexed.
+    Sparse {
+        size: u64,
+        layer_index: usize,
+        metadata: FileMetadata,
+    },
 }
 
+impl FileEntry {
+    /// Returns the index of the layer that last wrote the entry.
+    pub fn layer_index(&self) -> usize {
+        match self {
+            Self::File { layer_index, .. }
+            | Self::Directory { layer_index, .. }
+            | Self::Symlink { layer_index, .. }
+            | Self::Hardlink { layer_index, .. }
+            | Self::CharDevice { layer_index, .. }
+            | Self::BlockDevice { layer_index, .. }
+            | Self::Fifo { layer_index, .. }
+            | Self::Sparse { layer_index, .. } => *layer_index,
+        }
+    }
+
+    /// Returns the size of the content of regular files and hard links.
+    pub fn content_size(&self) -> Option<u64> {
+        match self {
+            Self::File { size, .. } | Self::Hardlink { size, .. } => Some(*size),
+            _ => None,
+        }
+    }
+
+    /// Returns a short description of device nodes, FIFOs and sparse files,
+    /// which are unexpected in most images.
+    pub fn special_kind(&self) -> Option<String> {
+        match self {
+            Self::CharDevice { major, minor, .. } => Some(format!("char device {major}, {minor}")),
+            Self::BlockDevice { major, minor, .. } => {
+                Some(format!("block device {major}, {minor}"))
+            }
+            Self::Fifo { .. } => Some("fifo".to_string()),
+            Self::Sparse { .. } => Some("sparse file".to_string()),
+            _ => None,
+        }
+    }
+
+    /// Returns the ownership, permissions and attributes of the entry.
+    pub fn metadata(&self) -> &FileMetadata {
+        match self {
+            Self::File { metadata, .. }
+            | Self::Directory { metadata, .. }
+            | Self::Symlink { metadata, .. }
+            | Self::Hardlink { metadata, .. }
+            | Self::CharDevice { metadata, .. }
+            | Self::BlockDevice { metadata, .. }
+            | Self::Fifo { metadata, .. }
+            | Self::Sparse { metadata, .. } => metadata,
+        }
+    }
+
+    /// Attributes the entry to the layer at `index`.
+    pub(crate) fn set_layer_index(&mut self, index: usize) {
+        match self {
+            Self::File { layer_index, .. }
+            | Self::Directory { layer_index, .. }
+            | Self::Symlink { layer_index, .. }
+            | Self::Hardlink { layer_index, .. }
+            | Self::CharDevice { layer_index, .. }
+            | Self::BlockDevice { layer_index, .. }
+            | Self::Fifo { layer_index, .. }
+            | Self::Sparse { layer_index, .. } => *layer_index = index,
+        }
+    }
+}
+
+/// Ownership, permissions, modification time and extended attributes of an
+/// entry, as recorded in the tar header (or table of contents) of its layer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMetadata {
+    /// Permission bits, including the setuid, setgid and sticky bits.
+    pub mode: u32,
+    /// Numeric owner.
+    pub uid: u64,
+    /// Numeric group.
+    pub gid: u64,
+    /// Owner name, when the layer records one.
+    pub user: Option<String>,
+    /// Group name, when the layer records one.
+    pub group: Option<String>,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: u64,
+    /// Extended attributes by name, such as `security.capability`.
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl FileMetadata {
+    /// Returns whether the setuid or setgid bit is set.
+    pub fn is_setid(&self) -> bool {
+        self.mode & (MODE_SETUID | MODE_SETGID) != 0
+    }
+
+    /// Renders the permission bits the way `ls -l` does, for example
+    /// `rwsr-xr-x`.
+    pub fn permissions(&self) -> String {
+        let mode = self.mode;
+        let bit = |mask: u32, c: char| if mode & mask != 0 { c } else { '-' };
+        let special =
+            |exec: u32, special: u32, set: char| match (mode & exec != 0, mode & special != 0) {
+                (true, true) => set,
+                (false, true) => set.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            };
+        [
+            bit(0o400, 'r'),
+            bit(0o200, 'w'),
+            special(0o100, MODE_SETUID, 's'),
+            bit(0o040, 'r'),
+            bit(0o020, 'w'),
+            special(0o010, MODE_SETGID, 's'),
+            bit(0o004, 'r'),
+            bit(0o002, 'w'),
+            special(0o001, MODE_STICKY, 't'),
+        ]
+        .iter()
+        .collect()
+    }
+}
+
+const MODE_SETUID: u32 = 0o4000;
+const MODE_SETGID: u32 = 0o2000;
+const MODE_STICKY: u32 = 0o1000;
+
 /// In-memory index of filesystem entries extracted from image layers.
 pub struct VirtualFileSystem {
     // 路径 -> 文件条目
@@ -41,17 +213,21 @@ impl VirtualFileSystem {
         self.entries.get(path.as_ref())
     }
 
-    /// Deletes the entry at `path`.
-    pub fn delete_entry(&mut self, path: &PathBuf) {
-        self.entries.remove(path);
+    /// Resolves the symbolic links along `path` within the filesystem root,
+    /// including the last component when `follow_last` is set. Returns `None`
+    /// when the path runs into a symlink loop.
+    pub fn resolve_path<P: AsRef<Path>>(&self, path: P, follow_last: bool) -> Option<PathBuf> {
+        symlink::resolve(path.as_ref(), follow_last, |path| {
+            match self.entries.get(path)? {
+                FileEntry::Symlink { target, .. } => Some(target.clone()),
+                _ => None,
+            }
+        })
     }
 
-    /// Removes all entries contained inside the directory `dir`.
-    pub fn clear_directory(&mut self, dir: &Path) {
-        let dir_str = dir.to_string_lossy();
-        let dir_prefix = format!("{dir_str}/");
-        self.entries
-            .retain(|path, _| !path.to_string_lossy().starts_with(&dir_prefix));
+    /// Keeps only the entries for which `keep` returns `true`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path, &FileEntry) -> bool) {
+        self.entries.retain(|path, entry| keep(path, entry));
     }
 
     /// Returns a view of the raw entry map.
@@ -64,8 +240,11 @@ impl VirtualFileSystem {
         let tree = DirectoryTree::new();
 
         for (path, file_entry) in &self.entries {
-            let is_dir = matches!(file_entry, FileEntry::Directory { .. });
-            tree.add_path(path, is_dir);
+            match (file_entry, file_entry.special_kind()) {
+                (FileEntry::Symlink { target, .. }, _) => tree.add_symlink(path, target.clone()),
+                (_, Some(kind)) => tree.add_labelled_path(path, kind),
+                (entry, None) => tree.add_path(path, matches!(entry, FileEntry::Directory { .. })),
+            }
         }
 
         tree
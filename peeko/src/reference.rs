@@ -0,0 +1,397 @@
+//! Image references such as `nginx`, `ghcr.io/owner/app:1.0` or
+//! `localhost:5000/app@sha256:<hex>`, following the grammar of the
+//! distribution project:
+//!
+//! ```text
+//! reference  := name [ ":" tag ] [ "@" digest ]
+//! name       := [ domain "/" ] path-component ( "/" path-component )*
+//! domain     := host [ ":" port ]
+//! ```
+//!
+//! The first component of a name is a domain when it contains a `.`, a `:`
+//! or an uppercase letter, or is `localhost`. Names without a domain live on
+//! Docker Hub, where single-component names belong to the `library/`
+//! namespace.
+
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// Domain of Docker Hub as written in image references.
+pub const DOCKER_HUB_DOMAIN: &str = "docker.io";
+/// Host serving the Docker Hub registry API.
+pub const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+/// Tag used when a reference names neither a tag nor a digest.
+pub const DEFAULT_TAG: &str = "latest";
+
+/// Longest repository name, including the domain, accepted by registries.
+const NAME_MAX_LENGTH: usize = 255;
+/// Longest tag accepted by registries.
+const TAG_MAX_LENGTH: usize = 128;
+
+/// Failures raised while parsing an image reference.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ReferenceError {
+    #[error("Invalid reference format: {0}")]
+    InvalidFormat(String),
+
+    #[error("Invalid registry {0}")]
+    InvalidRegistry(String),
+
+    #[error("Invalid repository name {0}, expected lowercase letters, digits and separators")]
+    InvalidRepository(String),
+
+    #[error("Invalid tag {0}")]
+    InvalidTag(String),
+
+    #[error("Invalid digest {0}, expected <algorithm>:<hex>")]
+    InvalidDigest(String),
+}
+
+/// A parsed image reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageReference {
+    /// Registry host with an optional port, for example `ghcr.io` or
+    /// `localhost:5000`. Docker Hub is `registry-1.docker.io`.
+    pub registry: String,
+    /// Repository inside the registry, for example `library/nginx`.
+    pub repository: String,
+    /// Tag of the image, `latest` when neither a tag nor a digest is given.
+    pub tag: Option<String>,
+    /// Digest pinning the image manifest, for example `sha256:<hex>`.
+    pub digest: Option<String>,
+}
+
+impl ImageReference {
+    /// Returns what to request from the registry: the digest when given,
+    /// which pins the image, and the tag otherwise.
+    pub fn reference(&self) -> &str {
+        self.digest
+            .as_deref()
+            .or(self.tag.as_deref())
+            .unwrap_or(DEFAULT_TAG)
+    }
+
+    /// Returns the URL of the registry's API endpoint.
+    pub fn registry_url(&self) -> String {
+        registry_url(&self.registry)
+    }
+
+    /// Returns whether the image lives on Docker Hub.
+    pub fn is_docker_hub(&self) -> bool {
+        self.registry == DOCKER_HUB_REGISTRY
+    }
+}
+
+impl FromStr for ImageReference {
+    type Err = ReferenceError;
+
+    /// Parses `[domain/]repository[:tag][@digest]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ReferenceError::InvalidFormat(s.to_string()));
+        }
+        let (name, digest) = match s.split_once('@') {
+            Some((name, digest)) => (name, Some(parse_digest(digest)?)),
+            None => (s, None),
+        };
+        // a colon after the last slash separates the tag, any other belongs
+        // to the port of the domain
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, Some(parse_tag(tag)?)),
+            _ => (name, None),
+        };
+        if name.len() > NAME_MAX_LENGTH {
+            return Err(ReferenceError::InvalidFormat(s.to_string()));
+        }
+
+        let (domain, path) = match name.split_once('/') {
+            Some((domain, path)) if is_domain(domain) => (domain, path),
+            _ => (DOCKER_HUB_DOMAIN, name),
+        };
+        if !is_valid_domain(domain) {
+            return Err(ReferenceError::InvalidRegistry(domain.to_string()));
+        }
+        if !path.split('/').all(is_path_component) {
+            return Err(ReferenceError::InvalidRepository(path.to_string()));
+        }
+
+        let registry = normalize_registry(domain);
+        let repository = if registry == DOCKER_HUB_REGISTRY && !path.contains('/') {
+            format!("library/{path}")
+        } else {
+            path.to_string()
+        };
+        let tag = match (&tag, &digest) {
+            (None, None) => Some(DEFAULT_TAG.to_string()),
+            _ => tag,
+        };
+        Ok(Self {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+}
+
+impl fmt::Display for ImageReference {
+    /// Formats the reference as `registry/repository[:tag][@digest]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{digest}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Turns a registry host (`ghcr.io`, `docker.io`, `localhost:5000`) or URL
+/// into the URL of its API endpoint.
+///
+/// Docker Hub aliases map to `registry-1.docker.io`. Registries on the local
+/// machine are reached over plain HTTP, like Docker does by default.
+pub fn registry_url(registry: &str) -> String {
+    if registry.starts_with("https://") || registry.starts_with("http://") {
+        return registry.trim_end_matches('/').to_string();
+    }
+    let registry = normalize_registry(registry.trim_end_matches('/'));
+    let host = match registry.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => &registry,
+    };
+    let scheme = match host {
+        "localhost" | "127.0.0.1" | "[::1]" => "http",
+        _ => "https",
+    };
+    format!("{scheme}://{registry}")
+}
+
+/// Maps the Docker Hub aliases to the host serving its API.
+fn normalize_registry(domain: &str) -> String {
+    match domain {
+        "docker.io" | "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB_REGISTRY.to_string(),
+        _ => domain.to_string(),
+    }
+}
+
+/// Returns whether the first component of a name is a domain rather than
+/// the start of a Docker Hub repository.
+fn is_domain(component: &str) -> bool {
+    component.contains(['.', ':'])
+        || component == "localhost"
+        || component.bytes().any(|b| b.is_ascii_uppercase())
+}
+
+/// Checks `host[:port]`, where the host is a domain name, an IPv4 address or
+/// a bracketed IPv6 address.
+fn is_valid_domain(domain: &str) -> bool {
+    let (host, port) = match domain.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port)),
+        _ => (domain, None),
+    };
+    if port.is_some_and(|port| port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit())) {
+        return false;
+    }
+    if let Some(ipv6) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        return !ipv6.is_empty() && ipv6.bytes().all(|b| b.is_ascii_hexdigit() || b == b':');
+    }
+    !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// Checks a repository path component: lowercase alphanumerics joined by
+/// `.`, `_`, `__` or runs of `-`.
+fn is_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    let is_alphanumeric = |b: &u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    if !bytes.first().is_some_and(is_alphanumeric) || !bytes.last().is_some_and(is_alphanumeric) {
+        return false;
+    }
+    let mut separator = String::new();
+    for b in bytes {
+        if is_alphanumeric(b) {
+            if !matches!(separator.as_str(), "" | "." | "_" | "__")
+                && !separator.bytes().all(|b| b == b'-')
+            {
+                return false;
+            }
+            separator.clear();
+        } else if matches!(b, b'.' | b'_' | b'-') {
+            separator.push(*b as char);
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+/// Checks a tag: word characters, `.` and `-`, not starting with either of
+/// the latter.
+fn parse_tag(tag: &str) -> Result<String, ReferenceError> {
+    let valid = tag.len() <= TAG_MAX_LENGTH
+        && tag
+            .bytes()
+            .next()
+            .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'_')
+        && tag
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-'));
+    if valid {
+        Ok(tag.to_string())
+    } else {
+        Err(ReferenceError::InvalidTag(tag.to_string()))
+    }
+}
+
+/// Checks `<algorithm>:<encoded>`; `sha256` and `sha512` digests must be
+/// lowercase hex of the right length.
+fn parse_digest(digest: &str) -> Result<String, ReferenceError> {
+    let invalid = || ReferenceError::InvalidDigest(digest.to_string());
+    let (algorithm, encoded) = digest.split_once(':').ok_or_else(invalid)?;
+    let valid_algorithm = algorithm.split(['+', '.', '_', '-']).all(|component| {
+        !component.is_empty()
+            && component
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+    });
+    let valid_encoded = match algorithm {
+        "sha256" | "sha512" => {
+            let length = if algorithm == "sha256" { 64 } else { 128 };
+            encoded.len() == length
+                && encoded
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        }
+        _ => {
+            !encoded.is_empty()
+                && encoded
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'=' | b'_' | b'-'))
+        }
+    };
+    if valid_algorithm && valid_encoded {
+        Ok(digest.to_string())
+    } else {
+        Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(reference: &str) -> ImageReference {
+        reference.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_image_reference() {
+        let digest = format!("sha256:{}", "ab".repeat(32));
+
+        // full image url
+        let reference = parse("registry-1.docker.io/library/nginx:latest");
+        assert_eq!(reference.registry, DOCKER_HUB_REGISTRY);
+        assert_eq!(reference.repository, "library/nginx");
+        assert_eq!(reference.reference(), "latest");
+        assert_eq!(reference.registry_url(), "https://registry-1.docker.io");
+
+        // official image
+        let reference = parse("nginx");
+        assert_eq!(reference.registry, DOCKER_HUB_REGISTRY);
+        assert_eq!(reference.repository, "library/nginx");
+        assert_eq!(reference.tag.as_deref(), Some("latest"));
+        assert_eq!(reference.reference(), "latest");
+        assert_eq!(reference.registry_url(), "https://registry-1.docker.io");
+
+        let reference = parse("docker.io/mcp/slack:1.0");
+        assert_eq!(reference.registry, DOCKER_HUB_REGISTRY);
+        assert_eq!(reference.repository, "mcp/slack");
+        assert_eq!(reference.reference(), "1.0");
+
+        let reference = parse("ghcr.io/owner/image:tag");
+        assert_eq!(reference.registry, "ghcr.io");
+        assert_eq!(reference.repository, "owner/image");
+        assert_eq!(reference.registry_url(), "https://ghcr.io");
+
+        // the port is not mistaken for a tag
+        let reference = parse("localhost:5000/app");
+        assert_eq!(reference.registry, "localhost:5000");
+        assert_eq!(reference.repository, "app");
+        assert_eq!(reference.tag.as_deref(), Some("latest"));
+        assert_eq!(reference.registry_url(), "http://localhost:5000");
+        let reference = parse("localhost/team/app:v2");
+        assert_eq!(reference.registry, "localhost");
+        assert_eq!(reference.repository, "team/app");
+        assert_eq!(reference.reference(), "v2");
+
+        // pinned by digest, with or without a tag
+        let reference = parse(&format!("nginx@{digest}"));
+        assert_eq!(reference.tag, None);
+        assert_eq!(reference.reference(), digest);
+        let reference = parse(&format!("registry.example.com:443/a/b/c:1.2@{digest}"));
+        assert_eq!(reference.registry, "registry.example.com:443");
+        assert_eq!(reference.repository, "a/b/c");
+        assert_eq!(reference.tag.as_deref(), Some("1.2"));
+        assert_eq!(reference.reference(), digest);
+        assert_eq!(
+            reference.to_string(),
+            format!("registry.example.com:443/a/b/c:1.2@{digest}")
+        );
+
+        assert_eq!(
+            "Nginx".parse::<ImageReference>(),
+            Err(ReferenceError::InvalidRepository("Nginx".to_string()))
+        );
+        assert!(matches!(
+            "nginx:-latest".parse::<ImageReference>(),
+            Err(ReferenceError::InvalidTag(_))
+        ));
+        assert!(matches!(
+            "nginx@sha256:abc".parse::<ImageReference>(),
+            Err(ReferenceError::InvalidDigest(_))
+        ));
+        assert!(matches!(
+            "nginx@latest".parse::<ImageReference>(),
+            Err(ReferenceError::InvalidDigest(_))
+        ));
+        for invalid in [
+            "",
+            "nginx:",
+            "library//nginx",
+            "a--/b",
+            "-app",
+            "host:port/app",
+        ] {
+            assert!(invalid.parse::<ImageReference>().is_err(), "{invalid}");
+        }
+        assert!(
+            "my_org/my__app.v2/a-b---c"
+                .parse::<ImageReference>()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_registry_url() {
+        assert_eq!(registry_url("docker.io"), "https://registry-1.docker.io");
+        assert_eq!(
+            registry_url("index.docker.io"),
+            "https://registry-1.docker.io"
+        );
+        assert_eq!(registry_url("ghcr.io/"), "https://ghcr.io");
+        assert_eq!(registry_url("127.0.0.1:5000"), "http://127.0.0.1:5000");
+        assert_eq!(registry_url("http://mirror:8080/"), "http://mirror:8080");
+    }
+}
@@ -0,0 +1,274 @@
+//! Parsing of `WWW-Authenticate` challenges and caching of bearer tokens.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Lifetime assumed for tokens whose response omits `expires_in`, as
+/// specified by the Docker token authentication spec.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+/// Tokens are refreshed this long before they actually expire.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(10);
+
+/// Single authentication challenge from a `WWW-Authenticate` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    /// Authentication scheme, lower-cased (for example `bearer` or `basic`).
+    pub scheme: String,
+    /// Auth parameters keyed by lower-cased name, with quotes removed.
+    pub params: HashMap<String, String>,
+}
+
+impl Challenge {
+    /// Returns the `realm` parameter.
+    pub fn realm(&self) -> Option<&str> {
+        self.param("realm")
+    }
+
+    /// Returns the `service` parameter.
+    pub fn service(&self) -> Option<&str> {
+        self.param("service")
+    }
+
+    /// Returns the `scope` parameter.
+    pub fn scope(&self) -> Option<&str> {
+        self.param("scope")
+    }
+
+    /// Returns the `error` parameter (for example `invalid_token`).
+    pub fn error(&self) -> Option<&str> {
+        self.param("error")
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+}
+
+/// Parses a `WWW-Authenticate` header value into its challenges.
+///
+/// Follows the RFC 7235 grammar: several challenges may share one header,
+/// parameter values may be quoted strings containing commas and escaped
+/// characters, and a `token68` credential is skipped.
+pub fn parse_challenges(header: &str) -> Vec<Challenge> {
+    let mut parser = Parser {
+        input: header.as_bytes(),
+        pos: 0,
+    };
+    let mut challenges = Vec::new();
+
+    loop {
+        parser.skip_separators();
+        let Some(scheme) = parser.token() else {
+            break;
+        };
+        let mut challenge = Challenge {
+            scheme: scheme.to_ascii_lowercase(),
+            params: HashMap::new(),
+        };
+
+        loop {
+            parser.skip_whitespace_and_commas();
+            let start = parser.pos;
+            let Some(name) = parser.token() else {
+                break;
+            };
+            parser.skip_whitespace();
+            if parser.peek() != Some(b'=') {
+                // Start of the next challenge.
+                parser.pos = start;
+                break;
+            }
+            parser.pos += 1;
+            if parser.peek() == Some(b'=') {
+                // token68 credential such as `abc==`.
+                while parser.peek() == Some(b'=') {
+                    parser.pos += 1;
+                }
+                continue;
+            }
+            parser.skip_whitespace();
+            let value = if parser.peek() == Some(b'"') {
+                parser.quoted_string()
+            } else {
+                parser.token().unwrap_or_default()
+            };
+            challenge.params.insert(name.to_ascii_lowercase(), value);
+        }
+
+        challenges.push(challenge);
+    }
+
+    challenges
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_whitespace_and_commas(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b',')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips anything that cannot start a scheme token.
+    fn skip_separators(&mut self) {
+        while let Some(c) = self.peek() {
+            if is_token_char(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn token(&mut self) -> Option<String> {
+        let start = self.pos;
+        while self.peek().is_some_and(is_token_char) {
+            self.pos += 1;
+        }
+        (self.pos > start)
+            .then(|| String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+    }
+
+    fn quoted_string(&mut self) -> String {
+        // skip the opening quote
+        self.pos += 1;
+        let mut value = Vec::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    if let Some(escaped) = self.peek() {
+                        value.push(escaped);
+                        self.pos += 1;
+                    }
+                }
+                _ => value.push(c),
+            }
+        }
+        String::from_utf8_lossy(&value).into_owned()
+    }
+}
+
+fn is_token_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~/:".contains(&c)
+}
+
+struct CachedToken {
+    token: String,
+    expires_at: Instant,
+}
+
+/// Bearer tokens keyed by the scope they were issued for.
+#[derive(Default)]
+pub(crate) struct TokenCache {
+    tokens: Mutex<HashMap<String, CachedToken>>,
+    // held while a token is fetched, one per scope
+    refreshes: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl TokenCache {
+    /// Returns the token for `scope` unless it is about to expire.
+    pub fn get(&self, scope: &str) -> Option<String> {
+        let tokens = self.tokens.lock().unwrap();
+        tokens
+            .get(scope)
+            .filter(|cached| Instant::now() + EXPIRY_MARGIN < cached.expires_at)
+            .map(|cached| cached.token.clone())
+    }
+
+    /// Waits for any refresh of the token for `scope` to finish, then keeps
+    /// other refreshes of the scope waiting until the guard is dropped.
+    pub async fn lock_refresh(&self, scope: &str) -> OwnedMutexGuard<()> {
+        let lock = Arc::clone(
+            self.refreshes
+                .lock()
+                .unwrap()
+                .entry(scope.to_string())
+                .or_default(),
+        );
+        lock.lock_owned().await
+    }
+
+    /// Stores a token issued for `scope`, valid for `expires_in` seconds.
+    pub fn insert(&self, scope: &str, token: String, expires_in: Option<u64>) {
+        let lifetime = expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+        self.tokens.lock().unwrap().insert(
+            scope.to_string(),
+            CachedToken {
+                token,
+                expires_at: Instant::now() + lifetime,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bearer_challenge() {
+        let challenges = parse_challenges(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull,push""#,
+        );
+        assert_eq!(challenges.len(), 1);
+        let challenge = &challenges[0];
+        assert_eq!(challenge.scheme, "bearer");
+        assert_eq!(challenge.realm(), Some("https://auth.docker.io/token"));
+        assert_eq!(challenge.service(), Some("registry.docker.io"));
+        assert_eq!(
+            challenge.scope(),
+            Some("repository:library/nginx:pull,push")
+        );
+        assert_eq!(challenge.error(), None);
+    }
+
+    #[test]
+    fn test_parse_multiple_challenges() {
+        let challenges = parse_challenges(
+            r#"Basic realm="Registry \"main\"", Bearer realm="https://ghcr.io/token", error=invalid_token, error_description="token, expired""#,
+        );
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(challenges[0].scheme, "basic");
+        assert_eq!(challenges[0].realm(), Some(r#"Registry "main""#));
+        assert_eq!(challenges[1].scheme, "bearer");
+        assert_eq!(challenges[1].error(), Some("invalid_token"));
+        assert_eq!(
+            challenges[1].params.get("error_description").unwrap(),
+            "token, expired"
+        );
+
+        let challenges = parse_challenges("Negotiate abc123==, Basic realm=x");
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(challenges[1].realm(), Some("x"));
+    }
+
+    #[test]
+    fn test_token_cache_expiry() {
+        let cache = TokenCache::default();
+        cache.insert("repository:a:pull", "a".to_string(), Some(300));
+        cache.insert("repository:b:pull", "b".to_string(), Some(0));
+        assert_eq!(cache.get("repository:a:pull").as_deref(), Some("a"));
+        assert_eq!(cache.get("repository:b:pull"), None);
+        assert_eq!(cache.get("repository:c:pull"), None);
+    }
+}
@@ -1,15 +1,33 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use futures_util::{StreamExt, TryStreamExt, stream};
 use reqwest;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::fs::{self, File};
-use tokio::io::AsyncWriteExt;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use super::auth::{Challenge, TokenCache, parse_challenges};
 use super::progress::{NoopProgress, ProgressTracker};
-use crate::manifest::{self, Descriptor, Manifest, ManifestList, PlatformManifest};
+use super::retry::RetryPolicy;
+use crate::digest::{Digester, sha256_digest};
+use crate::fs::layout::{
+    ALL_PLATFORMS_DIR, INDEX_FILE, OCI_LAYOUT_FILE, OciLayout, REF_NAME_ANNOTATION,
+    RESOLVED_DIGEST_ANNOTATION, is_digest, oci_blob_path, platform_dir, reference_dir,
+};
+use crate::fs::{BlobStore, ImageLayout};
+use crate::manifest::{
+    Descriptor, IMAGE_MANIFEST_MEDIA_TYPES, INDEX_MEDIA_TYPES, ImageConfig, ImageManifest,
+    Manifest, ManifestList, Platform, PlatformManifest, PlatformParam, SCHEMA1_MEDIA_TYPES,
+    Schema1Manifest, schema1_payload,
+};
+use crate::reference::ImageReference;
 
 /// Failures raised while communicating with the remote registry or filesystem.
 #[derive(Error, Debug)]
@@ -23,18 +41,49 @@ pub enum RegistryError {
     #[error("Token not found")]
     TokenNotFound,
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
     #[error("Unsupported content type: {0}")]
     UnsupportedContentType(String),
 
     #[error("Manifest not found")]
     ManifestNotFound,
 
+    #[error("Repository not found: {0}")]
+    RepositoryNotFound(String),
+
+    #[error("No manifest for platform {0}")]
+    PlatformNotFound(String),
+
     #[error("Manifest parse error: {0}")]
     ManifestParseError(#[from] serde_json::Error),
 
     #[error("Download error with status code {0}")]
     DownloadError(u16),
 
+    #[error("Blob {expected} failed verification: got digest {actual}")]
+    BlobDigestMismatch { expected: String, actual: String },
+
+    #[error("Blob {digest} failed verification: expected {expected}, got {actual}")]
+    BlobMismatch {
+        digest: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Manifest failed verification: expected {expected}, got {actual}")]
+    ManifestMismatch { expected: String, actual: String },
+
+    #[error("Invalid signature in schema 1 manifest")]
+    InvalidSchema1Signature,
+
+    #[error("Invalid embedded data for blob {0}")]
+    InvalidBlobData(String),
+
+    #[error("Unsupported digest algorithm: {0}")]
+    UnsupportedDigest(String),
+
     #[error("HTTP error: {0}")]
     HttpError(#[from] reqwest::Error),
 
@@ -52,19 +101,70 @@ struct TokenResponse {
     pub expires_in: Option<u64>,
 }
 
-/// Optional filters used to pick a specific platform when downloading multi-arch images.
-pub struct PlatformParam {
-    /// Specific CPU architecture to fetch.
-    pub architecture: Option<String>,
-    /// Specific operating system to fetch.
-    pub os: Option<String>,
-    /// CPU variant (for example `arm/v7`) to fetch.
-    pub variant: Option<String>,
+/// Page of a `/v2/<name>/tags/list` response.
+#[derive(Debug, Deserialize)]
+struct TagList {
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+/// Page of a `/v2/_catalog` response.
+#[derive(Debug, Deserialize)]
+struct Catalog {
+    #[serde(default)]
+    repositories: Option<Vec<String>>,
+}
+
+/// Platform fields of an image config blob.
+#[derive(Debug, Deserialize)]
+struct ConfigPlatform {
+    #[serde(default)]
+    architecture: String,
+    #[serde(default)]
+    os: String,
+    variant: Option<String>,
+}
+
+impl From<ConfigPlatform> for Platform {
+    fn from(config: ConfigPlatform) -> Self {
+        let or_unknown = |value: String| {
+            if value.is_empty() {
+                "unknown".to_string()
+            } else {
+                value
+            }
+        };
+        Platform {
+            architecture: or_unknown(config.architecture),
+            os: or_unknown(config.os),
+            os_version: None,
+            os_features: None,
+            variant: config.variant,
+        }
+    }
 }
 
 const DEFAULT_REGISTRY: &str = "https://registry-1.docker.io";
 const DEFAULT_CONCURRENT_DOWNLOADS: usize = 3;
 
+/// Manifest and config of a remote image, fetched without its layers by
+/// [`RegistryClient::inspect_image`].
+#[derive(Debug, Clone)]
+pub struct RemoteImage {
+    /// Digest the reference resolved to, that of the index for
+    /// multi-platform images.
+    pub digest: String,
+    /// Platforms offered by a multi-platform index, without attestations;
+    /// empty for single-platform images.
+    pub platforms: Vec<Platform>,
+    /// Platform of the inspected manifest.
+    pub platform: Platform,
+    /// Manifest of the inspected platform.
+    pub manifest: ImageManifest,
+    /// Config of the inspected platform.
+    pub config: ImageConfig,
+}
+
 /// High level client for retrieving manifests and blobs from an OCI registry.
 #[derive(Clone)]
 pub struct RegistryClient {
@@ -73,9 +173,11 @@ pub struct RegistryClient {
     oci_dir: PathBuf,
     concurrent_downloads: usize,
     auth_token: Option<String>,
+    tokens: Arc<TokenCache>,
     username: Option<String>,
     password: Option<String>,
     progress: Arc<dyn ProgressTracker>,
+    retry: RetryPolicy,
 }
 
 impl Default for RegistryClient {
@@ -86,9 +188,11 @@ impl Default for RegistryClient {
             oci_dir: "./".into(),
             concurrent_downloads: DEFAULT_CONCURRENT_DOWNLOADS,
             auth_token: None,
+            tokens: Arc::new(TokenCache::default()),
             username: None,
             password: None,
             progress: Arc::new(NoopProgress),
+            retry: RetryPolicy::default(),
         }
     }
 }
@@ -113,6 +217,9 @@ impl RegistryClient {
     }
 
     /// Creates a client configured with a pre-baked bearer token.
+    ///
+    /// The token is sent until the registry rejects it, after which scoped
+    /// tokens are requested as usual.
     pub fn with_token(registry_url: &str, token: &str) -> Self {
         Self {
             registry_url: registry_url.to_string(),
@@ -126,11 +233,31 @@ impl RegistryClient {
         self.oci_dir = dir.into();
     }
 
+    /// Returns the directory images are downloaded into.
+    pub(crate) fn downloads_dir(&self) -> &Path {
+        &self.oci_dir
+    }
+
     /// Limits the number of concurrent blob downloads.
     pub fn set_concurrent_downloads(&mut self, concurrent: usize) {
         self.concurrent_downloads = concurrent;
     }
 
+    /// Returns the number of blobs fetched concurrently.
+    pub(crate) fn concurrent_downloads(&self) -> usize {
+        self.concurrent_downloads
+    }
+
+    /// Sets the policy used to retry requests that fail with transient errors.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Replaces the tracker that receives download and retry notifications.
+    pub fn set_progress_tracker(&mut self, progress: Arc<dyn ProgressTracker>) {
+        self.progress = progress;
+    }
+
     #[cfg(feature = "progress")]
     /// Enables progress reporting using the `indicatif` progress bars.
     pub fn enable_progress(mut self) -> Self {
@@ -138,260 +265,1963 @@ impl RegistryClient {
         self
     }
 
-    async fn authenticate_if_needed(&mut self, url: &str) -> Result<()> {
-        if self.auth_token.is_some() {
-            return Ok(());
-        }
-
-        let response = self.http.head(url).send().await?;
-
-        let auth_header = "www-authenticate";
-        if response.status() == 401 {
-            let auth_header = response
-                .headers()
-                .get(auth_header)
-                .ok_or_else(|| RegistryError::HeaderNotFound(auth_header.to_string()))?
-                .to_str()
-                .map_err(|_| RegistryError::HeaderNotFound(auth_header.to_string()))?;
-
-            // 解析类似：Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull"
-            let mut realm = String::new();
-            let mut service = String::new();
-            let mut scope = None;
-
-            // 简单的解析逻辑（生产环境建议使用更健壮的解析器）
-            for part in auth_header.split(',') {
-                let part = part.trim();
-                if part.starts_with("Bearer realm=") {
-                    realm = part
-                        .strip_prefix("Bearer realm=\"")
-                        .and_then(|s| s.strip_suffix('"'))
-                        .unwrap_or("")
-                        .to_string();
-                } else if part.starts_with("service=") {
-                    service = part
-                        .strip_prefix("service=\"")
-                        .and_then(|s| s.strip_suffix('"'))
-                        .unwrap_or("")
-                        .to_string();
-                } else if part.starts_with("scope=") {
-                    scope = part
-                        .strip_prefix("scope=\"")
-                        .and_then(|s| s.strip_suffix('"'))
-                        .map(|s| s.to_string());
-                }
-            }
+    /// Sends `request` with the credentials cached for `scope`.
+    ///
+    /// A `401` response is answered by parsing its `WWW-Authenticate`
+    /// challenge, fetching a fresh token for the scope and sending the request
+    /// once more, which also refreshes tokens that expired or were revoked.
+    /// Concurrent requests rejected with the same token share one refresh.
+    async fn send_authorized(
+        &self,
+        label: &str,
+        scope: &str,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let retry_request = request
+            .try_clone()
+            .expect("registry requests never stream a body");
+        let sent_token = self.tokens.get(scope);
+        let response = self.send(label, self.with_auth(request, scope)).await?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
 
-            let mut token_url = format!("{realm}?service={service}");
-            if let Some(scope) = scope {
-                token_url = format!("{token_url}&scope={scope}");
+        let challenges: Vec<Challenge> = response
+            .headers()
+            .get_all(reqwest::header::WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_challenges)
+            .collect();
+        let Some(challenge) = challenges.iter().find(|c| c.scheme == "bearer") else {
+            // Basic credentials, if any, were already sent with the first request.
+            return Err(RegistryError::Unauthorized(label.to_string()));
+        };
+        {
+            let _refresh = self.tokens.lock_refresh(scope).await;
+            // another request may have refreshed the token in the meantime
+            if self.tokens.get(scope) == sent_token {
+                self.fetch_token(challenge, scope).await?;
             }
+        }
 
-            let mut request = self.http.get(token_url);
-            if let (Some(username), Some(password)) = (&self.username, &self.password) {
-                request = request.basic_auth(username, Some(password));
-            }
+        let response = self
+            .send(label, self.with_auth(retry_request, scope))
+            .await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(RegistryError::Unauthorized(label.to_string()));
+        }
+        Ok(response)
+    }
 
-            let response = request.send().await?;
+    /// Requests a bearer token from the challenge's realm and caches it under `scope`.
+    async fn fetch_token(&self, challenge: &Challenge, scope: &str) -> Result<()> {
+        let realm = challenge
+            .realm()
+            .ok_or_else(|| RegistryError::HeaderNotFound("www-authenticate realm".to_string()))?;
 
-            if !response.status().is_success() {
-                return Err(RegistryError::TokenFetchFailed(response.status().as_u16()));
-            }
+        let mut query = Vec::new();
+        if let Some(service) = challenge.service() {
+            query.push(("service", service));
+        }
+        match challenge.scope() {
+            Some(challenge_scope) => query.push(("scope", challenge_scope)),
+            None if !scope.is_empty() => query.push(("scope", scope)),
+            None => {}
+        }
+
+        let mut request = self.http.get(realm).query(&query);
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            request = request.basic_auth(username, Some(password));
+        }
 
-            let auth_response: TokenResponse = response.json().await?;
-            let token = auth_response
-                .token
-                .or(auth_response.access_token)
-                .ok_or_else(|| RegistryError::TokenNotFound)?;
-            self.auth_token = Some(token);
+        let response = self.send(realm, request).await?;
+
+        if !response.status().is_success() {
+            return Err(RegistryError::TokenFetchFailed(response.status().as_u16()));
         }
 
+        let auth_response: TokenResponse = response.json().await?;
+        let token = auth_response
+            .token
+            .or(auth_response.access_token)
+            .ok_or_else(|| RegistryError::TokenNotFound)?;
+        self.tokens.insert(scope, token, auth_response.expires_in);
+
         Ok(())
     }
 
-    fn with_auth(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
-        if let Some(token) = &self.auth_token {
-            request = request.bearer_auth(token);
+    /// Sends `request`, retrying transient failures according to the retry policy.
+    ///
+    /// `label` identifies the request in retry notifications.
+    async fn send(
+        &self,
+        label: &str,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let mut attempt = 1;
+        loop {
+            let current = request
+                .try_clone()
+                .expect("registry requests never stream a body");
+            let (delay, reason) = match current.send().await {
+                Ok(response) => {
+                    let status = response.status();
+                    match self
+                        .retry
+                        .delay_for_response(attempt, status, response.headers())
+                    {
+                        Some(delay) => (delay, format!("HTTP {}", status.as_u16())),
+                        None => return Ok(response),
+                    }
+                }
+                Err(err) => match self.retry.delay_for_error(attempt, &err) {
+                    Some(delay) => (delay, err.to_string()),
+                    None => return Err(err.into()),
+                },
+            };
+            self.progress.retry(label, attempt, delay, &reason);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn with_auth(&self, request: reqwest::RequestBuilder, scope: &str) -> reqwest::RequestBuilder {
+        if let Some(token) = self.tokens.get(scope) {
+            request.bearer_auth(token)
+        } else if let Some(token) = &self.auth_token {
+            request.bearer_auth(token)
         } else if let (Some(username), Some(password)) = (&self.username, &self.password) {
-            request = request.basic_auth(username, Some(password));
+            request.basic_auth(username, Some(password))
+        } else {
+            request
         }
-        request
+    }
+
+    /// Checks the configured credentials against the registry.
+    ///
+    /// Requests the `/v2/` endpoint, exchanging the credentials for a token
+    /// when the registry asks for one. Fails with
+    /// [`RegistryError::TokenFetchFailed`] or [`RegistryError::Unauthorized`]
+    /// when the registry rejects them.
+    pub async fn login(&self) -> Result<()> {
+        let url = format!("{}/v2/", self.registry_url);
+        let response = self.send_authorized(&url, "", self.http.get(&url)).await?;
+        if !response.status().is_success() {
+            return Err(RegistryError::DownloadError(response.status().as_u16()));
+        }
+        Ok(())
+    }
+
+    /// Lists the tags of `repository`, following the `Link` headers of
+    /// paginated responses.
+    ///
+    /// Fails with [`RegistryError::RepositoryNotFound`] when the registry
+    /// does not know the repository.
+    pub async fn list_tags(&self, repository: &str) -> Result<Vec<String>> {
+        let url = format!("{}/v2/{}/tags/list", self.registry_url, repository);
+        let pages: Vec<TagList> = match self.fetch_pages(url, &pull_scope(repository)).await {
+            Err(RegistryError::DownloadError(404)) => {
+                return Err(RegistryError::RepositoryNotFound(repository.to_string()));
+            }
+            result => result?,
+        };
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.tags)
+            .flatten()
+            .collect())
+    }
+
+    /// Lists the repositories of the registry, following the `Link` headers
+    /// of paginated responses.
+    ///
+    /// Many public registries, Docker Hub among them, do not offer the
+    /// catalog and answer with [`RegistryError::Unauthorized`] or a
+    /// [`RegistryError::DownloadError`].
+    pub async fn catalog(&self) -> Result<Vec<String>> {
+        let url = format!("{}/v2/_catalog", self.registry_url);
+        let pages: Vec<Catalog> = self.fetch_pages(url, CATALOG_SCOPE).await?;
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.repositories)
+            .flatten()
+            .collect())
+    }
+
+    /// Fetches `url` and the pages its responses link to with
+    /// `Link: <url>; rel="next"`.
+    async fn fetch_pages<T: DeserializeOwned>(&self, url: String, scope: &str) -> Result<Vec<T>> {
+        let mut pages = Vec::new();
+        let mut visited = HashSet::new();
+        let mut next = Some(url);
+        while let Some(url) = next.take() {
+            if !visited.insert(url.clone()) {
+                break;
+            }
+            let response = self
+                .send_authorized(&url, scope, self.http.get(&url))
+                .await?;
+            if !response.status().is_success() {
+                return Err(RegistryError::DownloadError(response.status().as_u16()));
+            }
+            next = self.next_page_url(response.headers());
+            pages.push(response.json().await?);
+        }
+        Ok(pages)
+    }
+
+    /// Resolves the `rel="next"` target of a `Link` header against the
+    /// registry. Links to another host are followed on the configured
+    /// registry, so credentials are never sent elsewhere.
+    fn next_page_url(&self, headers: &reqwest::header::HeaderMap) -> Option<String> {
+        let link = headers
+            .get_all(reqwest::header::LINK)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(next_link)?;
+        let base = reqwest::Url::parse(&self.registry_url).ok()?;
+        let url = base.join(&link).ok()?;
+        if url.origin() == base.origin() {
+            return Some(url.to_string());
+        }
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        Some(base.join(&path).ok()?.to_string())
     }
 
     /// Fetches the manifest (or manifest list) for the specified image reference.
-    pub async fn get_image_manifest(
-        &mut self,
-        image: &str,
-        tag_or_digest: &str,
-    ) -> Result<Manifest> {
+    ///
+    /// Like every method taking an [`ImageReference`], only its repository,
+    /// tag and digest are used: requests go to the registry the client was
+    /// created for (see [`ImageReference::registry_url`]).
+    pub async fn get_image_manifest(&mut self, image: &ImageReference) -> Result<Manifest> {
+        let fetched = self
+            .fetch_manifest(&image.repository, image.reference())
+            .await?;
+        Ok(fetched.manifest)
+    }
+
+    /// Fetches a manifest together with the raw bytes served by the registry,
+    /// which are needed to store it under its digest.
+    ///
+    /// The bytes are verified against the requested digest and against the
+    /// `Docker-Content-Digest` header returned by the registry.
+    async fn fetch_manifest(&self, image: &str, tag_or_digest: &str) -> Result<FetchedManifest> {
         let url = format!(
             "{}/v2/{}/manifests/{}",
             self.registry_url, image, tag_or_digest
         );
 
-        self.authenticate_if_needed(&url).await?;
-
+        let request = self
+            .http
+            .get(&url)
+            .header(reqwest::header::ACCEPT, manifest_accept());
         let response = self
-            .with_auth(self.http.get(&url).header(
-                "Accept",
-                "application/vnd.docker.distribution.manifest.v2+json",
-            ))
-            .send()
+            .send_authorized(&url, &pull_scope(image), request)
             .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(RegistryError::ManifestNotFound);
+        }
+        if !response.status().is_success() {
+            return Err(RegistryError::DownloadError(response.status().as_u16()));
+        }
 
-        let content_type_header = "content-type";
+        // parameters such as `charset` are irrelevant for manifests
         let content_type = response
             .headers()
-            .get(content_type_header)
-            .ok_or_else(|| RegistryError::HeaderNotFound(content_type_header.to_string()))?
-            .to_str()
-            .map_err(|_| RegistryError::HeaderNotFound(content_type_header.to_string()))?;
-
-        match content_type {
-            "application/vnd.oci.image.manifest.v1+json"
-            | "application/vnd.docker.distribution.manifest.v2+json" => {
-                Ok(Manifest::OCIManifest(response.json().await?))
-            }
-            "application/vnd.oci.image.index.v1+json"
-            | "application/vnd.docker.distribution.manifest.list.v2+json" => {
-                Ok(Manifest::OCIIndex(response.json().await?))
-            }
-            _ => Err(RegistryError::UnsupportedContentType(
-                content_type.to_string(),
-            )),
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let header_digest = response
+            .headers()
+            .get(DOCKER_CONTENT_DIGEST)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let body = response.bytes().await?.to_vec();
+
+        // registries that do not know the manifest type serve a generic one,
+        // so the document itself has to tell
+        let generic = GENERIC_CONTENT_TYPES.contains(&content_type.as_str());
+        if SCHEMA1_MEDIA_TYPES.contains(&content_type.as_str())
+            || (generic && schema_version(&body) == Some(1))
+        {
+            // signed schema 1 manifests are digested without their signatures
+            let payload = schema1_payload(&body).ok_or(RegistryError::InvalidSchema1Signature)?;
+            let digest = verify_manifest(tag_or_digest, header_digest.as_deref(), &payload)?;
+            let (manifest, raw) = self.convert_schema1(image, &body).await?;
+            return Ok(FetchedManifest {
+                manifest,
+                raw,
+                digest,
+            });
+        }
+        if !generic
+            && !IMAGE_MANIFEST_MEDIA_TYPES.contains(&content_type.as_str())
+            && !INDEX_MEDIA_TYPES.contains(&content_type.as_str())
+        {
+            return Err(RegistryError::UnsupportedContentType(content_type));
+        }
+        let digest = verify_manifest(tag_or_digest, header_digest.as_deref(), &body)?;
+        Ok(FetchedManifest {
+            manifest: Manifest::from_json(&body)?,
+            raw: body,
+            digest,
+        })
+    }
+
+    /// Converts a schema 1 manifest into a Docker schema 2 manifest, looking
+    /// up the layer sizes schema 1 does not record.
+    ///
+    /// The converted manifest is returned as the raw bytes, since that is the
+    /// manifest stored with the image.
+    async fn convert_schema1(&self, image: &str, body: &[u8]) -> Result<(Manifest, Vec<u8>)> {
+        let schema1: Schema1Manifest = serde_json::from_slice(body)?;
+        let mut manifest = schema1.to_image_manifest()?;
+        for layer in &mut manifest.layers {
+            layer.size = self.blob_size(image, &layer.digest).await?;
+        }
+        let raw_manifest = serde_json::to_vec(&manifest)?;
+        Ok((Manifest::OCIManifest(manifest), raw_manifest))
+    }
+
+    /// Fetches a small blob, such as an image config, into memory and
+    /// verifies it.
+    async fn fetch_blob(&self, image: &str, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        if let Some(data) = &descriptor.data {
+            return decode_embedded(descriptor, data);
+        }
+        let url = format!(
+            "{}/v2/{}/blobs/{}",
+            self.registry_url, image, descriptor.digest
+        );
+        let response = self
+            .send_authorized(&descriptor.digest, &pull_scope(image), self.http.get(&url))
+            .await?;
+        if !response.status().is_success() {
+            return Err(RegistryError::DownloadError(response.status().as_u16()));
         }
+        let data = response.bytes().await?.to_vec();
+        verify_blob(descriptor, &data)?;
+        Ok(data)
     }
 
-    /// Downloads an image and all of its layers into the configured downloads directory.
+    /// Fetches the bytes of a blob within `range`, using an HTTP `Range`
+    /// request. Registries that ignore the range send the whole blob, which
+    /// is streamed up to the end of `range` without being buffered.
     ///
-    /// When the manifest resolves to a multi-platform index the `platform`
-    /// parameter filters which architecture to download.
+    /// Partial content cannot be checked against the blob's digest; readers
+    /// verify what they decode from it instead.
+    pub async fn fetch_blob_range(
+        &self,
+        image: &str,
+        digest: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let url = format!("{}/v2/{}/blobs/{}", self.registry_url, image, digest);
+        let request = self.http.get(&url).header(
+            reqwest::header::RANGE,
+            format!("bytes={}-{}", range.start, range.end - 1),
+        );
+        let response = self
+            .send_authorized(digest, &pull_scope(image), request)
+            .await?;
+        let status = response.status();
+        let partial = status == reqwest::StatusCode::PARTIAL_CONTENT;
+        if partial && content_range_start(&response) != Some(range.start) {
+            return Err(RegistryError::DownloadError(status.as_u16()));
+        }
+        if !status.is_success() {
+            return Err(RegistryError::DownloadError(status.as_u16()));
+        }
+        let data = if partial {
+            response.bytes().await?.to_vec()
+        } else {
+            read_body_range(response, &range).await?
+        };
+        if data.len() as u64 != range.end - range.start {
+            return Err(RegistryError::BlobMismatch {
+                digest: digest.to_string(),
+                expected: format!("{} bytes at {}", range.end - range.start, range.start),
+                actual: format!("{} bytes", data.len()),
+            });
+        }
+        Ok(data)
+    }
+
+    /// Starts downloading a blob, leaving the response body to be streamed
+    /// by the caller.
+    pub(crate) async fn open_blob(&self, image: &str, digest: &str) -> Result<reqwest::Response> {
+        let url = format!("{}/v2/{}/blobs/{}", self.registry_url, image, digest);
+        let response = self
+            .send_authorized(digest, &pull_scope(image), self.http.get(&url))
+            .await?;
+        if !response.status().is_success() {
+            return Err(RegistryError::DownloadError(response.status().as_u16()));
+        }
+        Ok(response)
+    }
+
+    /// Returns the size of a blob from the `Content-Length` of a `HEAD` request.
+    async fn blob_size(&self, image: &str, digest: &str) -> Result<u64> {
+        let url = format!("{}/v2/{}/blobs/{}", self.registry_url, image, digest);
+        let response = self
+            .send_authorized(digest, &pull_scope(image), self.http.head(&url))
+            .await?;
+        if !response.status().is_success() {
+            return Err(RegistryError::DownloadError(response.status().as_u16()));
+        }
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| RegistryError::HeaderNotFound("content-length".to_string()))
+    }
+
+    /// Downloads an image and all of its layers into the configured downloads
+    /// directory, returning the directory the image was stored in.
+    ///
+    /// A digest in the reference, such as `nginx@sha256:<hex>`, pins the exact
+    /// manifest to pull and takes precedence over its tag.
+    ///
+    /// The image is stored under `<image>/<tag>/<os>-<arch>[-<variant>]`, so
+    /// several platforms of one tag can coexist, as an OCI image layout: an
+    /// `oci-layout` marker, an `index.json` whose manifest carries the tag in
+    /// the `org.opencontainers.image.ref.name` annotation, and the manifest,
+    /// config and layers under `blobs/<algorithm>/<hex>`. `index.json` is
+    /// written last, so its presence marks a complete download. Images pulled
+    /// by digest are stored under `<image>/@<algorithm>-<hex>` instead (see
+    /// [`reference_dir`]). The digest the reference resolved to is recorded
+    /// in the `io.peeko.image.digest` annotation of `index.json`.
+    ///
+    /// Layers and config are kept once in the [`BlobStore`] of the downloads
+    /// directory and hard-linked into the image, so blobs shared with images
+    /// pulled earlier are not downloaded again.
+    ///
+    /// When the manifest resolves to a multi-platform index `platform` picks
+    /// the build to download, the host's one ([`PlatformParam::host`]) when
+    /// none is given. A single-platform image is downloaded whatever its
+    /// platform, unless it does not match a given `platform`. Attestation
+    /// manifests are never picked.
     pub async fn download_image(
         &mut self,
+        image: &ImageReference,
+        platform: Option<&PlatformParam>,
+    ) -> Result<PathBuf> {
+        let reference = image.reference();
+        let image = image.repository.as_str();
+        let ResolvedManifest {
+            digest: resolved_digest,
+            manifest: oci_manifest,
+            raw: raw_manifest,
+            platform: target_platform,
+            ..
+        } = self.resolve_manifest(image, reference, platform).await?;
+
+        let store = BlobStore::in_dir(&self.oci_dir);
+        let blobs = image_blobs([&oci_manifest]);
+        self.fetch_blobs(image, &blobs).await?;
+
+        // single-platform images only declare their platform in the config
+        let target_platform = match target_platform {
+            Some(target_platform) => target_platform,
+            None => {
+                let config = fs::read(store.blob_path(&oci_manifest.config.digest)).await?;
+                serde_json::from_slice::<ConfigPlatform>(&config)?.into()
+            }
+        };
+
+        let dir_name = platform_dir(&target_platform);
+        let entry = PlatformManifest {
+            digest: sha256_digest(&raw_manifest),
+            media_type: oci_manifest.media_type.clone(),
+            size: raw_manifest.len() as u64,
+            platform: Some(target_platform),
+            annotations: None,
+        };
+        let layout = LayoutTarget {
+            image,
+            reference,
+            dir_name: &dir_name,
+            resolved_digest: &resolved_digest,
+        };
+        self.write_layout(&layout, &blobs, &[raw_manifest], entry)
+            .await
+    }
+
+    /// Fetches the manifest and config of an image without downloading its
+    /// layers.
+    ///
+    /// `platform` picks the manifest to inspect or is checked against a
+    /// single-platform image, as in [`download_image`](Self::download_image),
+    /// and the platforms offered by an index are listed in
+    /// [`RemoteImage::platforms`].
+    pub async fn inspect_image(
+        &self,
+        image: &ImageReference,
+        platform: Option<&PlatformParam>,
+    ) -> Result<RemoteImage> {
+        let repository = image.repository.as_str();
+        let resolved = self
+            .resolve_manifest(repository, image.reference(), platform)
+            .await?;
+        let config = self
+            .fetch_blob(repository, &resolved.manifest.config)
+            .await?;
+        let platform = match resolved.platform {
+            Some(platform) => platform,
+            None => serde_json::from_slice::<ConfigPlatform>(&config)?.into(),
+        };
+        Ok(RemoteImage {
+            digest: resolved.digest,
+            platforms: resolved.platforms,
+            platform,
+            config: serde_json::from_slice(&config)?,
+            manifest: resolved.manifest,
+        })
+    }
+
+    /// Fetches the manifest `reference` points to, picking the manifest for
+    /// `platform`, or the host's, when it is a multi-platform index.
+    ///
+    /// The platform of a single-platform image is only looked up, from its
+    /// config, when it has to match `platform`.
+    async fn resolve_manifest(
+        &self,
         image: &str,
-        tag: &str,
-        platform: PlatformParam,
-    ) -> Result<()> {
-        let manifest = self.get_image_manifest(image, tag).await?;
-
-        let image_manifest = match manifest {
-            Manifest::OCIManifest(oci_manifest) => Some(oci_manifest),
-            Manifest::OCIIndex(manifest_list) => {
-                let target = self.match_manifest(&manifest_list, &platform);
-                match target {
-                    Some(target) => {
-                        let manifest = self.get_image_manifest(image, &target.digest).await?;
-                        if let Manifest::OCIManifest(oci_manifest) = manifest {
-                            Some(oci_manifest)
-                        } else {
-                            None
+        reference: &str,
+        platform: Option<&PlatformParam>,
+    ) -> Result<ResolvedManifest> {
+        let fetched = self.fetch_manifest(image, reference).await?;
+        let manifest_list = match fetched.manifest {
+            Manifest::OCIManifest(manifest) => {
+                let image_platform = match platform {
+                    Some(platform) => {
+                        let config = self.fetch_blob(image, &manifest.config).await?;
+                        let image_platform: Platform =
+                            serde_json::from_slice::<ConfigPlatform>(&config)?.into();
+                        if !platform.matches(&image_platform) {
+                            return Err(RegistryError::PlatformNotFound(platform.to_string()));
                         }
+                        Some(image_platform)
                     }
                     None => None,
-                }
+                };
+                return Ok(ResolvedManifest {
+                    digest: fetched.digest,
+                    manifest,
+                    raw: fetched.raw,
+                    platform: image_platform,
+                    platforms: Vec::new(),
+                });
             }
+            Manifest::OCIIndex(manifest_list) => manifest_list,
         };
+        let platform = platform.cloned().unwrap_or_else(PlatformParam::host);
 
-        let oci_manifest = image_manifest.ok_or_else(|| RegistryError::ManifestNotFound)?;
+        let target = self
+            .match_manifest(&manifest_list, &platform)
+            .ok_or_else(|| RegistryError::PlatformNotFound(platform.to_string()))?;
+        let target_fetched = self.fetch_manifest(image, &target.digest).await?;
+        let Manifest::OCIManifest(manifest) = target_fetched.manifest else {
+            return Err(RegistryError::ManifestNotFound);
+        };
+        let platforms = manifest_list
+            .manifests
+            .iter()
+            .filter_map(|entry| entry.platform.clone())
+            .filter(|platform| !platform.is_unknown())
+            .collect();
+        Ok(ResolvedManifest {
+            digest: fetched.digest,
+            manifest,
+            raw: target_fetched.raw,
+            platform: target.platform.clone(),
+            platforms,
+        })
+    }
 
-        // create folder;
-        let folder_path = self.oci_dir.join(format!("{image}/{tag}"));
-        fs::create_dir_all(&folder_path).await?;
+    /// Mirrors every manifest of a multi-platform index, including
+    /// attestations, together with their configs and layers, returning the
+    /// directory the index was stored in.
+    ///
+    /// The index is stored under `<image>/<tag>/all` as an OCI image layout
+    /// whose `index.json` references the original index, so it can be handed
+    /// to other OCI tools as is. Single-platform images are stored as by
+    /// [`download_image`](Self::download_image).
+    pub async fn download_all_platforms(&mut self, image_ref: &ImageReference) -> Result<PathBuf> {
+        let reference = image_ref.reference();
+        let image = image_ref.repository.as_str();
+        let fetched = self.fetch_manifest(image, reference).await?;
+        let Manifest::OCIIndex(manifest_list) = fetched.manifest else {
+            return self.download_image(image_ref, None).await;
+        };
+        let raw_index = fetched.raw;
+
+        let mut image_manifests = Vec::new();
+        let mut raw_manifests = Vec::new();
+        for entry in &manifest_list.manifests {
+            let fetched = self.fetch_manifest(image, &entry.digest).await?;
+            if let Manifest::OCIManifest(image_manifest) = fetched.manifest {
+                image_manifests.push(image_manifest);
+            }
+            raw_manifests.push(fetched.raw);
+        }
 
-        let manifest_path = folder_path.join("manifest.json");
-        let mut manifest_file = File::create(manifest_path).await?;
-        let json = serde_json::to_string_pretty(&oci_manifest)?;
-        manifest_file.write_all(json.as_bytes()).await?;
-        manifest_file.flush().await?;
+        let blobs = image_blobs(&image_manifests);
+        self.fetch_blobs(image, &blobs).await?;
 
-        // download layers
-        let tasks = oci_manifest
-            .layers
-            .iter()
-            .chain(std::iter::once(&oci_manifest.config)) // download config
-            .map(|layer| self.download(image, layer, &folder_path));
+        let media_type = if manifest_list.media_type.is_empty() {
+            OCI_INDEX_MEDIA_TYPE.to_string()
+        } else {
+            manifest_list.media_type.clone()
+        };
+        let entry = PlatformManifest {
+            digest: sha256_digest(&raw_index),
+            media_type,
+            size: raw_index.len() as u64,
+            platform: None,
+            annotations: None,
+        };
+        raw_manifests.push(raw_index);
+        let layout = LayoutTarget {
+            image,
+            reference,
+            dir_name: ALL_PLATFORMS_DIR,
+            resolved_digest: &fetched.digest,
+        };
+        self.write_layout(&layout, &blobs, &raw_manifests, entry)
+            .await
+    }
+
+    /// Downloads `blobs` into the shared blob store, skipping blobs that an
+    /// earlier pull already stored. Blobs embedded in their descriptor are
+    /// written without a request.
+    async fn fetch_blobs(&self, image: &str, blobs: &[&Descriptor]) -> Result<()> {
+        let store = BlobStore::in_dir(&self.oci_dir);
+        let tasks = blobs.iter().map(|blob| {
+            let store = &store;
+            async move {
+                if !store.contains(&blob.digest, blob.size).await {
+                    let store_path = store.blob_path(&blob.digest);
+                    match &blob.data {
+                        Some(data) => write_embedded(blob, data, &store_path).await?,
+                        None => self.download(image, blob, &store_path).await?,
+                    }
+                }
+                Ok::<_, RegistryError>(())
+            }
+        });
 
         stream::iter(tasks)
             .buffer_unordered(self.concurrent_downloads)
             .try_collect::<Vec<_>>()
             .await?;
-
         Ok(())
     }
 
-    async fn download(&self, image: &str, descriptor: &Descriptor, dest_path: &Path) -> Result<()> {
+    /// Writes the OCI image layout described by `layout` holding the stored
+    /// `blobs`, the `manifests` and an `index.json` that references `entry`
+    /// under the tag.
+    async fn write_layout(
+        &self,
+        layout: &LayoutTarget<'_>,
+        blobs: &[&Descriptor],
+        manifests: &[Vec<u8>],
+        mut entry: PlatformManifest,
+    ) -> Result<PathBuf> {
+        // create folder, replacing an image stored directly under the tag by
+        // earlier versions
+        let tag_path = self
+            .oci_dir
+            .join(layout.image)
+            .join(reference_dir(layout.reference));
+        if ImageLayout::detect(&tag_path).is_some() {
+            fs::remove_dir_all(&tag_path).await?;
+        }
+        let folder_path = tag_path.join(layout.dir_name);
+        fs::create_dir_all(&folder_path).await?;
+        write_atomic(
+            &folder_path.join(OCI_LAYOUT_FILE),
+            &serde_json::to_vec(&OciLayout::default())?,
+        )
+        .await?;
+
+        let store = BlobStore::in_dir(&self.oci_dir);
+        for blob in blobs {
+            store
+                .link_into(&blob.digest, &oci_blob_path(&folder_path, &blob.digest))
+                .await?;
+        }
+        for manifest in manifests {
+            let manifest_path = oci_blob_path(&folder_path, &sha256_digest(manifest));
+            if let Some(parent) = manifest_path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            write_atomic(&manifest_path, manifest).await?;
+        }
+
+        // the ref name is meant for tags, digests already name the manifest
+        if !is_digest(layout.reference) {
+            entry.annotations = Some(HashMap::from([(
+                REF_NAME_ANNOTATION.to_string(),
+                layout.reference.to_string(),
+            )]));
+        }
+        let index = ManifestList {
+            schema_version: 2,
+            media_type: OCI_INDEX_MEDIA_TYPE.to_string(),
+            manifests: vec![entry],
+            annotations: Some(HashMap::from([(
+                RESOLVED_DIGEST_ANNOTATION.to_string(),
+                layout.resolved_digest.to_string(),
+            )])),
+        };
+        write_atomic(
+            &folder_path.join(INDEX_FILE),
+            &serde_json::to_vec_pretty(&index)?,
+        )
+        .await?;
+
+        Ok(folder_path)
+    }
+
+    /// Downloads a blob to `file_path`, resuming from the `.partial` file when
+    /// the connection drops mid-transfer.
+    async fn download(&self, image: &str, descriptor: &Descriptor, file_path: &Path) -> Result<()> {
+        let mut attempt = 1;
+        loop {
+            let err = match self.try_download(image, descriptor, file_path).await {
+                Err(RegistryError::HttpError(err)) if err.is_body() => err,
+                result => return result,
+            };
+            match self.retry.delay_for_error(attempt, &err) {
+                Some(delay) => {
+                    self.progress
+                        .retry(&descriptor.digest, attempt, delay, &err.to_string());
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Downloads a blob to `file_path`, resuming from a previous `.partial`
+    /// file when one exists.
+    ///
+    /// Data is written to `<file_path>.partial` and only renamed to its final
+    /// name once the size and digest have been verified, so an interrupted
+    /// download never looks complete.
+    async fn try_download(
+        &self,
+        image: &str,
+        descriptor: &Descriptor,
+        file_path: &Path,
+    ) -> Result<()> {
         let url = format!(
             "{}/v2/{}/blobs/{}",
             self.registry_url, image, descriptor.digest
         );
-        let response = self.with_auth(self.http.get(url)).send().await?;
-        if !response.status().is_success() {
-            return Err(RegistryError::DownloadError(response.status().as_u16()));
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).await?;
         }
+        let partial_path = with_suffix(file_path, ".partial");
+
+        let mut digester = new_digester(descriptor)?;
+        let mut offset = resume_partial(&partial_path, descriptor, &mut digester).await?;
+
+        let mut request = self.http.get(url);
+        if offset > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+        }
+        let response = self
+            .send_authorized(&descriptor.digest, &pull_scope(image), request)
+            .await?;
+
+        let status = response.status();
+        let mut file = if offset > 0 && status == reqwest::StatusCode::PARTIAL_CONTENT {
+            if content_range_start(&response) != Some(offset) {
+                let _ = fs::remove_file(&partial_path).await;
+                return Err(RegistryError::DownloadError(status.as_u16()));
+            }
+            OpenOptions::new().append(true).open(&partial_path).await?
+        } else if status.is_success() {
+            // The registry ignored the range request, start over.
+            offset = 0;
+            digester = new_digester(descriptor)?;
+            File::create(&partial_path).await?
+        } else {
+            if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+                let _ = fs::remove_file(&partial_path).await;
+            }
+            return Err(RegistryError::DownloadError(status.as_u16()));
+        };
 
-        let content_length = response.content_length().unwrap_or(0);
         self.progress
-            .start_download(&descriptor.digest, content_length);
+            .start_download(&descriptor.digest, descriptor.size);
+        self.progress.update(&descriptor.digest, offset);
 
-        let file_type = manifest::get_file_type(&descriptor.media_type);
-        let mut file =
-            File::create(dest_path.join(format!("{}.{}", descriptor.digest, file_type))).await?;
+        let result = self
+            .write_verified(response, descriptor, &mut file, digester, offset)
+            .await;
+        self.progress.finish(&descriptor.digest);
+        drop(file);
+
+        match result {
+            Ok(()) => {
+                fs::rename(&partial_path, file_path).await?;
+                Ok(())
+            }
+            Err(
+                err @ (RegistryError::BlobMismatch { .. }
+                | RegistryError::BlobDigestMismatch { .. }),
+            ) => {
+                let _ = fs::remove_file(&partial_path).await;
+                Err(err)
+            }
+            // Keep the partial file around so the next attempt can resume.
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Streams the response body into `file` while hashing it, failing when the
+    /// received bytes do not match the descriptor's size or digest.
+    ///
+    /// `written` is the number of bytes already present in the file and fed
+    /// into `digester`.
+    async fn write_verified(
+        &self,
+        response: reqwest::Response,
+        descriptor: &Descriptor,
+        file: &mut File,
+        mut digester: Digester,
+        mut written: u64,
+    ) -> Result<()> {
         let mut stream = response.bytes_stream();
 
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
+            written += chunk.len() as u64;
+            if written > descriptor.size {
+                return Err(size_mismatch(descriptor, written));
+            }
+            digester.update(&chunk);
             file.write_all(&chunk).await?;
             self.progress.update(&descriptor.digest, chunk.len() as u64);
         }
-
-        self.progress.finish(&descriptor.digest);
         file.flush().await?;
 
+        if written != descriptor.size {
+            return Err(size_mismatch(descriptor, written));
+        }
+        let actual = digester.finalize();
+        if actual != descriptor.digest {
+            return Err(RegistryError::BlobDigestMismatch {
+                expected: descriptor.digest.clone(),
+                actual,
+            });
+        }
+
         Ok(())
     }
 
+    /// Picks the entry of `manifest_list` matching `platform`, skipping the
+    /// attestation manifests that registries tag with an `unknown` platform.
     fn match_manifest<'a>(
         &self,
         manifest_list: &'a ManifestList,
         platform: &PlatformParam,
     ) -> Option<&'a PlatformManifest> {
-        if let (None, None, None) = (&platform.architecture, &platform.os, &platform.variant) {
-            let first = manifest_list.manifests.first();
-            return first;
-        }
-        manifest_list.manifests.iter().find(|m| {
-            if let Some(arch) = &platform.architecture
-                && m.platform.architecture.ne(arch)
-            {
-                return false;
-            }
+        manifest_list
+            .manifests
+            .iter()
+            .find(|m| platform.matches_entry(m))
+    }
+}
 
-            if let Some(os) = &platform.os
-                && m.platform.os.ne(os)
-            {
-                return false;
-            }
+/// Manifest fetched from the registry.
+struct FetchedManifest {
+    manifest: Manifest,
+    /// Bytes stored for the manifest, which differ from the served ones for
+    /// converted schema 1 manifests.
+    raw: Vec<u8>,
+    /// Digest the registry serves the manifest under.
+    digest: String,
+}
 
-            if let (Some(variant), Some(m_variant)) = (&platform.variant, &m.platform.variant)
-                && variant.ne(m_variant)
-            {
-                return false;
-            }
+/// Image manifest a reference resolved to.
+struct ResolvedManifest {
+    /// Digest of the manifest or index the reference points to.
+    digest: String,
+    manifest: ImageManifest,
+    /// Bytes stored for `manifest`.
+    raw: Vec<u8>,
+    /// Platform of `manifest` declared by the index it was picked from.
+    platform: Option<Platform>,
+    /// Platforms offered by that index, without attestations.
+    platforms: Vec<Platform>,
+}
 
-            true
-        })
+/// Where [`RegistryClient::write_layout`] stores an image:
+/// `<image>/<reference>/<dir_name>`.
+struct LayoutTarget<'a> {
+    image: &'a str,
+    /// Tag or digest the image was pulled with.
+    reference: &'a str,
+    dir_name: &'a str,
+    /// Digest of the manifest `reference` resolved to.
+    resolved_digest: &'a str,
+}
+
+/// Returns the configs and layers of `manifests`, without duplicates.
+fn image_blobs<'a>(manifests: impl IntoIterator<Item = &'a ImageManifest>) -> Vec<&'a Descriptor> {
+    let mut seen = HashSet::new();
+    manifests
+        .into_iter()
+        .flat_map(|manifest| std::iter::once(&manifest.config).chain(&manifest.layers))
+        .filter(|blob| seen.insert(blob.digest.as_str()))
+        .collect()
+}
+
+/// Content types that do not identify a manifest type.
+const GENERIC_CONTENT_TYPES: &[&str] = &[
+    "",
+    "application/json",
+    "text/plain",
+    "application/octet-stream",
+];
+
+/// Returns the `Accept` header listing every supported manifest media type,
+/// preferring anything over schema 1.
+fn manifest_accept() -> String {
+    INDEX_MEDIA_TYPES
+        .iter()
+        .chain(IMAGE_MANIFEST_MEDIA_TYPES)
+        .map(|media_type| media_type.to_string())
+        .chain(
+            SCHEMA1_MEDIA_TYPES
+                .iter()
+                .map(|media_type| format!("{media_type};q=0.5")),
+        )
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Header carrying the digest of a manifest served by the registry.
+const DOCKER_CONTENT_DIGEST: &str = "docker-content-digest";
+
+/// Checks that `body` matches the digest it was requested by and the one the
+/// registry announced, returning its digest.
+fn verify_manifest(
+    tag_or_digest: &str,
+    header_digest: Option<&str>,
+    body: &[u8],
+) -> Result<String> {
+    let requested = Some(tag_or_digest).filter(|reference| is_digest(reference));
+    let mut digest = None;
+    for expected in requested.into_iter().chain(header_digest) {
+        let mut digester = Digester::for_digest(expected)
+            .ok_or_else(|| RegistryError::UnsupportedDigest(expected.to_string()))?;
+        digester.update(body);
+        let actual = digester.finalize();
+        if actual != expected {
+            return Err(RegistryError::ManifestMismatch {
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+        digest.get_or_insert(actual);
+    }
+    Ok(digest.unwrap_or_else(|| sha256_digest(body)))
+}
+
+/// Returns the `schemaVersion` of a manifest document.
+fn schema_version(body: &[u8]) -> Option<u64> {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()?
+        .get("schemaVersion")?
+        .as_u64()
+}
+
+/// Verifies the base64 `data` embedded in `descriptor` and writes it to `path`.
+async fn write_embedded(descriptor: &Descriptor, data: &str, path: &Path) -> Result<()> {
+    let data = decode_embedded(descriptor, data)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    write_atomic(path, &data).await
+}
+
+/// Decodes and verifies the base64 `data` embedded in `descriptor`.
+fn decode_embedded(descriptor: &Descriptor, data: &str) -> Result<Vec<u8>> {
+    let data = STANDARD
+        .decode(data)
+        .map_err(|_| RegistryError::InvalidBlobData(descriptor.digest.clone()))?;
+    verify_blob(descriptor, &data)?;
+    Ok(data)
+}
+
+/// Checks that `data` has the size and digest declared by `descriptor`.
+fn verify_blob(descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+    if data.len() as u64 != descriptor.size {
+        return Err(size_mismatch(descriptor, data.len() as u64));
+    }
+    let mut digester = new_digester(descriptor)?;
+    digester.update(data);
+    let actual = digester.finalize();
+    if actual != descriptor.digest {
+        return Err(RegistryError::BlobDigestMismatch {
+            expected: descriptor.digest.clone(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Media type of the `index.json` written into image layouts.
+const OCI_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+
+/// Returns `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Writes `data` to a temporary sibling of `path` and renames it into place.
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = with_suffix(path, ".tmp");
+    let mut file = File::create(&tmp_path).await?;
+    file.write_all(data).await?;
+    file.flush().await?;
+    drop(file);
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Token scope granting access to the registry catalog.
+const CATALOG_SCOPE: &str = "registry:catalog:*";
+
+/// Returns the target of the `rel="next"` entry of a `Link` header value.
+fn next_link(value: &str) -> Option<String> {
+    value.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        let is_next = params.split(';').any(|param| {
+            param.trim().split_once('=').is_some_and(|(name, value)| {
+                name.trim() == "rel" && value.trim().trim_matches('"') == "next"
+            })
+        });
+        let target = target.trim().strip_prefix('<')?.strip_suffix('>')?;
+        is_next.then(|| target.to_string())
+    })
+}
+
+/// Token scope granting pull access to `image`.
+fn pull_scope(image: &str) -> String {
+    format!("repository:{image}:pull")
+}
+
+fn new_digester(descriptor: &Descriptor) -> Result<Digester> {
+    Digester::for_digest(&descriptor.digest)
+        .ok_or_else(|| RegistryError::UnsupportedDigest(descriptor.digest.clone()))
+}
+
+/// Feeds an existing partial download into `digester` and returns how many
+/// bytes can be kept. Partial files that are not smaller than the blob are
+/// discarded.
+async fn resume_partial(
+    partial_path: &Path,
+    descriptor: &Descriptor,
+    digester: &mut Digester,
+) -> Result<u64> {
+    let mut file = match File::open(partial_path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    if file.metadata().await?.len() >= descriptor.size {
+        drop(file);
+        fs::remove_file(partial_path).await?;
+        return Ok(0);
+    }
+
+    let mut read = 0u64;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        digester.update(&buf[..n]);
+        read += n as u64;
+    }
+    Ok(read)
+}
+
+/// Returns the first byte position of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .parse()
+        .ok()
+}
+
+/// Reads the bytes within `range` of a whole blob sent as the body of
+/// `response`, dropping the connection once they are read.
+async fn read_body_range(mut response: reqwest::Response, range: &Range<u64>) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity((range.end - range.start) as usize);
+    let mut position = 0u64;
+    while position < range.end
+        && let Some(chunk) = response.chunk().await?
+    {
+        let chunk_end = position + chunk.len() as u64;
+        let start = range.start.clamp(position, chunk_end) - position;
+        let end = range.end.clamp(position, chunk_end) - position;
+        data.extend_from_slice(&chunk[start as usize..end as usize]);
+        position = chunk_end;
+    }
+    Ok(data)
+}
+
+fn size_mismatch(descriptor: &Descriptor, actual: u64) -> RegistryError {
+    RegistryError::BlobMismatch {
+        digest: descriptor.digest.clone(),
+        expected: format!("{} bytes", descriptor.size),
+        actual: format!("{actual} bytes"),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
     use super::*;
+    use crate::reader::fixtures::{self, Entry, TAR_LAYER, serve_images};
+    use crate::registry::mock::{MockRegistry, MockResponse};
+
+    fn image_ref(reference: &str) -> ImageReference {
+        reference.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_download_verifies_digest() {
+        let registry = MockRegistry::start(|_| {
+            MockResponse::new(200)
+                .header("Content-Type", "application/octet-stream")
+                .body("tampered content")
+        })
+        .await;
+        let client = RegistryClient::new(registry.url());
+        let dir = tempfile::tempdir().unwrap();
+
+        let good = fixtures::descriptor(TAR_LAYER, b"tampered content");
+        let good_path = oci_blob_path(dir.path(), &good.digest);
+        client
+            .download("test/app", &good, &good_path)
+            .await
+            .unwrap();
+        assert!(good_path.exists());
+
+        let bad = fixtures::descriptor(TAR_LAYER, b"original content");
+        let bad_path = oci_blob_path(dir.path(), &bad.digest);
+        let err = client.download("test/app", &bad, &bad_path).await;
+        assert!(matches!(err, Err(RegistryError::BlobDigestMismatch { .. })));
+        let message = err.unwrap_err().to_string();
+        assert_eq!(message.matches(&bad.digest).count(), 1, "{message}");
+        assert!(!bad_path.exists());
+
+        let mut short = fixtures::descriptor(TAR_LAYER, b"tampered content");
+        short.size -= 1;
+        let err = client.download("test/app", &short, &good_path).await;
+        assert!(matches!(err, Err(RegistryError::BlobMismatch { .. })));
+
+        let requests = registry.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(
+            requests[1].path,
+            format!("/v2/test/app/blobs/{}", bad.digest)
+        );
+        assert!(!requests[1].headers.contains_key("authorization"));
+    }
+
+    #[derive(Default)]
+    struct RecordingProgress {
+        retries: std::sync::Mutex<Vec<(String, u32)>>,
+    }
+
+    impl ProgressTracker for RecordingProgress {
+        fn start_download(&self, _digest: &str, _total_bytes: u64) {}
+        fn update(&self, _digest: &str, _bytes: u64) {}
+        fn finish(&self, _digest: &str) {}
+        fn retry(&self, label: &str, attempt: u32, _delay: Duration, _reason: &str) {
+            self.retries
+                .lock()
+                .unwrap()
+                .push((label.to_string(), attempt));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_failures() {
+        let failures = AtomicUsize::new(0);
+        let registry = MockRegistry::start(move |_| {
+            match failures.fetch_add(1, Ordering::SeqCst) {
+                0 => MockResponse::new(503),
+                1 => MockResponse::new(429).header("Retry-After", "0"),
+                _ => MockResponse::new(200)
+                    .header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+                    .body(
+                        r#"{"schemaVersion":2,"mediaType":"application/vnd.oci.image.manifest.v1+json",
+                        "config":{"digest":"sha256:00","mediaType":"application/vnd.oci.image.config.v1+json","size":2},
+                        "layers":[]}"#,
+                    ),
+            }
+        })
+        .await;
+
+        let progress = Arc::new(RecordingProgress::default());
+        let mut client = RegistryClient::new(registry.url());
+        client.set_progress_tracker(progress.clone());
+        client.set_retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            jitter: 0.0,
+            ..Default::default()
+        });
+
+        let manifest = client
+            .get_image_manifest(&image_ref("test/app:latest"))
+            .await;
+        assert!(matches!(manifest, Ok(Manifest::OCIManifest(_))));
+        let retries = progress.retries.lock().unwrap().clone();
+        let url = format!("{}/v2/test/app/manifests/latest", registry.url());
+        assert_eq!(retries, vec![(url.clone(), 1), (url, 2)]);
+
+        client.set_retry_policy(RetryPolicy::none());
+        let registry = MockRegistry::start(|_| MockResponse::new(503)).await;
+        client.registry_url = registry.url().to_string();
+        let err = client
+            .get_image_manifest(&image_ref("test/app:latest"))
+            .await;
+        assert!(matches!(err, Err(RegistryError::DownloadError(503))));
+        assert_eq!(registry.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_scoped_and_refreshed() {
+        let generation = Arc::new(AtomicUsize::new(0));
+        let issued = Arc::clone(&generation);
+        let registry = MockRegistry::start(move |req| {
+            let generation = issued.load(Ordering::SeqCst);
+            if req.path.starts_with("/token") {
+                let repo = if req.path.contains("repository%3Ateam%2Fapi%3Apull") {
+                    "api"
+                } else {
+                    "web"
+                };
+                return MockResponse::new(200)
+                    .body(format!(r#"{{"token":"{repo}-{generation}","expires_in":300}}"#));
+            }
+            let repo = req.path.split('/').nth(3).unwrap_or_default();
+            let expected = format!("Bearer {repo}-{generation}");
+            if req.headers.get("authorization") != Some(&expected) {
+                let challenge = format!(
+                    r#"Bearer realm="http://{}/token",service="mock",scope="repository:team/{repo}:pull",error="invalid_token""#,
+                    req.headers["host"]
+                );
+                return MockResponse::new(401).header("WWW-Authenticate", &challenge);
+            }
+            MockResponse::new(200)
+                .header("Content-Type", "application/vnd.oci.image.index.v1+json")
+                .body(r#"{"schemaVersion":2,"mediaType":"application/vnd.oci.image.index.v1+json","manifests":[]}"#)
+        })
+        .await;
+
+        let mut client = RegistryClient::new(registry.url());
+        client
+            .get_image_manifest(&image_ref("team/api:latest"))
+            .await
+            .unwrap();
+        client
+            .get_image_manifest(&image_ref("team/web:latest"))
+            .await
+            .unwrap();
+        client
+            .get_image_manifest(&image_ref("team/api:v2"))
+            .await
+            .unwrap();
+        let token_requests = |registry: &MockRegistry| {
+            registry
+                .requests()
+                .iter()
+                .filter(|r| r.path.starts_with("/token"))
+                .count()
+        };
+        assert_eq!(token_requests(&registry), 2);
+
+        // Revoke every token, the client must fetch a new one on 401.
+        generation.fetch_add(1, Ordering::SeqCst);
+        client
+            .get_image_manifest(&image_ref("team/api:latest"))
+            .await
+            .unwrap();
+        assert_eq!(token_requests(&registry), 3);
+        let last = registry.requests().pop().unwrap();
+        assert_eq!(last.headers["authorization"], "Bearer api-1");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_token_refresh() {
+        let registry = MockRegistry::start(|req| {
+            if req.path.starts_with("/token") {
+                return MockResponse::new(200).body(r#"{"token":"t","expires_in":300}"#);
+            }
+            if req.headers.get("authorization").map(String::as_str) != Some("Bearer t") {
+                let challenge = format!(
+                    r#"Bearer realm="http://{}/token",service="mock""#,
+                    req.headers["host"]
+                );
+                return MockResponse::new(401).header("WWW-Authenticate", &challenge);
+            }
+            MockResponse::new(200).body(r#"{"name":"test/app","tags":["v1"]}"#)
+        })
+        .await;
+
+        let client = RegistryClient::new(registry.url());
+        let lists = futures_util::future::join_all((0..8).map(|_| client.list_tags("test/app")));
+        for tags in lists.await {
+            assert_eq!(tags.unwrap(), ["v1"]);
+        }
+        let token_requests = registry
+            .requests()
+            .iter()
+            .filter(|r| r.path.starts_with("/token"))
+            .count();
+        assert_eq!(token_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_tags_and_catalog() {
+        let registry = MockRegistry::start(|req| match req.path.as_str() {
+            "/v2/test/app/tags/list" => MockResponse::new(200)
+                .header(
+                    "Link",
+                    r#"</v2/test/app/tags/list?n=2&last=v2>; rel="next""#,
+                )
+                .body(r#"{"name":"test/app","tags":["v1","v2"]}"#),
+            "/v2/test/app/tags/list?n=2&last=v2" => {
+                MockResponse::new(200).body(r#"{"name":"test/app","tags":["v3"]}"#)
+            }
+            "/v2/test/empty/tags/list" => {
+                MockResponse::new(200).body(r#"{"name":"test/empty","tags":null}"#)
+            }
+            "/v2/_catalog" => MockResponse::new(200)
+                .header(
+                    "Link",
+                    r#"<http://elsewhere.example/v2/_catalog?last=test%2Fapp>; rel="next""#,
+                )
+                .body(r#"{"repositories":["test/app"]}"#),
+            "/v2/_catalog?last=test%2Fapp" => {
+                MockResponse::new(200).body(r#"{"repositories":["test/empty"]}"#)
+            }
+            _ => MockResponse::new(404),
+        })
+        .await;
+        let client = RegistryClient::new(registry.url());
+
+        assert_eq!(
+            client.list_tags("test/app").await.unwrap(),
+            ["v1", "v2", "v3"]
+        );
+        assert!(client.list_tags("test/empty").await.unwrap().is_empty());
+        assert!(matches!(
+            client.list_tags("test/missing").await,
+            Err(RegistryError::RepositoryNotFound(_))
+        ));
+        // links to other hosts are followed on the configured registry
+        assert_eq!(client.catalog().await.unwrap(), ["test/app", "test/empty"]);
+    }
+
+    #[tokio::test]
+    async fn test_login() {
+        let registry = MockRegistry::start(|req| {
+            if req.path.starts_with("/token") {
+                // base64("me:pw")
+                return match req.headers.get("authorization").map(String::as_str) {
+                    Some("Basic bWU6cHc=") => MockResponse::new(200).body(r#"{"token":"t"}"#),
+                    _ => MockResponse::new(401),
+                };
+            }
+            if req.headers.get("authorization").map(String::as_str) == Some("Bearer t") {
+                return MockResponse::new(200);
+            }
+            let challenge = format!(
+                r#"Bearer realm="http://{}/token",service="mock""#,
+                req.headers["host"]
+            );
+            MockResponse::new(401).header("WWW-Authenticate", &challenge)
+        })
+        .await;
+
+        let client = RegistryClient::with_credentials(registry.url(), "me", "pw");
+        client.login().await.unwrap();
+        let client = RegistryClient::with_credentials(registry.url(), "me", "wrong");
+        let err = client.login().await;
+        assert!(matches!(err, Err(RegistryError::TokenFetchFailed(401))));
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_partial_file() {
+        const CONTENT: &[u8] = b"0123456789abcdefghij";
+        let registry = MockRegistry::start(|req| match req.headers.get("range") {
+            Some(range) => {
+                let start: usize = range
+                    .strip_prefix("bytes=")
+                    .and_then(|r| r.strip_suffix('-'))
+                    .and_then(|r| r.parse().ok())
+                    .unwrap();
+                MockResponse::new(206)
+                    .header(
+                        "Content-Range",
+                        &format!("bytes {start}-{}/{}", CONTENT.len() - 1, CONTENT.len()),
+                    )
+                    .body(&CONTENT[start..])
+            }
+            None => MockResponse::new(200).body(CONTENT),
+        })
+        .await;
+        let client = RegistryClient::new(registry.url());
+        let dir = tempfile::tempdir().unwrap();
+        let descriptor = fixtures::descriptor(TAR_LAYER, CONTENT);
+        let final_path = dir.path().join("blob");
+        let partial_path = dir.path().join("blob.partial");
+
+        std::fs::write(&partial_path, &CONTENT[..8]).unwrap();
+        client
+            .download("test/app", &descriptor, &final_path)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&final_path).unwrap(), CONTENT);
+        assert!(!partial_path.exists());
+        let requests = registry.requests();
+        assert_eq!(requests[0].headers.get("range").unwrap(), "bytes=8-");
+
+        // A corrupt partial file is detected once the download completes.
+        std::fs::remove_file(&final_path).unwrap();
+        std::fs::write(&partial_path, b"XXXXXXXX").unwrap();
+        let err = client.download("test/app", &descriptor, &final_path).await;
+        assert!(matches!(err, Err(RegistryError::BlobDigestMismatch { .. })));
+        assert!(!partial_path.exists());
+        assert!(!final_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_blob_range_ignored_by_registry() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let body = content.clone();
+        // the registry ignores `Range` and sends the whole blob
+        let registry =
+            MockRegistry::start(move |_| MockResponse::new(200).body(body.clone())).await;
+        let client = RegistryClient::new(registry.url());
+        let digest = sha256_digest(&content);
+
+        for range in [0..10, 70_000..70_123, 99_990..100_000] {
+            let data = client
+                .fetch_blob_range("test/app", &digest, range.clone())
+                .await
+                .unwrap();
+            assert_eq!(data, &content[range.start as usize..range.end as usize]);
+        }
+        let err = client
+            .fetch_blob_range("test/app", &digest, 99_990..100_010)
+            .await;
+        assert!(matches!(err, Err(RegistryError::BlobMismatch { .. })));
+    }
+
+    /// Builds an image whose single layer holds `etc/hostname`, returning its
+    /// manifest and blobs keyed by digest.
+    fn test_image(hostname: &[u8], os: &str) -> (Vec<u8>, HashMap<String, Vec<u8>>) {
+        let layer = fixtures::tar_layer(&[Entry::File("etc/hostname", hostname)]);
+        fixtures::image(os, vec![(fixtures::descriptor(TAR_LAYER, &layer), layer)])
+    }
+
+    #[tokio::test]
+    async fn test_download_image_writes_oci_layout() {
+        let (manifest, blobs) = test_image(b"peeko", "linux");
+        let manifest_digest = sha256_digest(&manifest);
+        let layer_digest = serde_json::from_slice::<ImageManifest>(&manifest)
+            .unwrap()
+            .layers[0]
+            .digest
+            .clone();
+        let registry = serve_images(
+            HashMap::from([("/v2/test/app/manifests/v1".to_string(), manifest)]),
+            blobs,
+        )
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(registry.url());
+        client.set_downloads_dir(dir.path());
+        let platform = PlatformParam {
+            architecture: None,
+            os: None,
+            variant: None,
+        };
+        let image_dir = client
+            .download_image(&image_ref("test/app:v1"), Some(&platform))
+            .await
+            .unwrap();
+
+        assert_eq!(image_dir, dir.path().join("test/app/v1/linux-amd64"));
+        assert_eq!(ImageLayout::detect(&image_dir), Some(ImageLayout::Oci));
+        let index: ManifestList =
+            serde_json::from_slice(&std::fs::read(image_dir.join(INDEX_FILE)).unwrap()).unwrap();
+        assert_eq!(index.manifests[0].digest, manifest_digest);
+        assert_eq!(
+            index.manifests[0].annotations.as_ref().unwrap()[REF_NAME_ANNOTATION],
+            "v1"
+        );
+        assert!(oci_blob_path(&image_dir, &manifest_digest).is_file());
+        assert!(oci_blob_path(&image_dir, &layer_digest).is_file());
+        assert_eq!(
+            crate::fs::collect_images(dir.path()).unwrap(),
+            vec!["test/app:v1".to_string()]
+        );
+
+        let reader = crate::reader::build_image_reader(&image_dir).await.unwrap();
+        assert_eq!(reader.read_file("etc/hostname").await.unwrap(), b"peeko");
+    }
+
+    #[tokio::test]
+    async fn test_download_image_by_digest() {
+        let (manifest, blobs) = test_image(b"pinned", "linux");
+        let digest = sha256_digest(&manifest);
+        let (tampered, _) = test_image(b"tampered", "linux");
+        let tampered_digest = sha256_digest(b"original");
+        let registry = serve_images(
+            HashMap::from([
+                ("/v2/test/app/manifests/v1".to_string(), manifest.clone()),
+                (format!("/v2/test/app/manifests/{digest}"), manifest),
+                (
+                    format!("/v2/test/app/manifests/{tampered_digest}"),
+                    tampered,
+                ),
+            ]),
+            blobs,
+        )
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(registry.url());
+        client.set_downloads_dir(dir.path());
+        let by_tag = client
+            .download_image(&image_ref("test/app:v1"), None)
+            .await
+            .unwrap();
+        let by_digest = client
+            .download_image(&image_ref(&format!("test/app@{digest}")), None)
+            .await
+            .unwrap();
+
+        let digest_dir = digest.replace(':', "-");
+        assert_eq!(
+            by_digest,
+            dir.path()
+                .join(format!("test/app/@{digest_dir}/linux-amd64"))
+        );
+        assert_eq!(
+            crate::fs::read_resolved_digest(&by_tag).as_ref(),
+            Some(&digest)
+        );
+        let mut images = crate::fs::collect_images(dir.path()).unwrap();
+        images.sort();
+        assert_eq!(
+            images,
+            ["test/app:v1".to_string(), format!("test/app@{digest}")]
+        );
+
+        // a digest finds the image pulled by it before the tag resolving to it
+        let found = crate::fs::find_image(dir.path(), "test/app", &digest, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.path, by_digest);
+        assert_eq!(found.digest.as_ref(), Some(&digest));
+
+        let err = client
+            .download_image(&image_ref(&format!("test/app@{tampered_digest}")), None)
+            .await;
+        assert!(matches!(err, Err(RegistryError::ManifestMismatch { .. })));
+
+        let registry = MockRegistry::start(|_| {
+            MockResponse::new(200)
+                .header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+                .header("Docker-Content-Digest", "sha256:00")
+                .body(r#"{"schemaVersion":2,"config":{"digest":"sha256:00","mediaType":"application/vnd.oci.image.config.v1+json","size":2},"layers":[]}"#)
+        })
+        .await;
+        client.registry_url = registry.url().to_string();
+        let err = client.get_image_manifest(&image_ref("test/app:v1")).await;
+        assert!(matches!(err, Err(RegistryError::ManifestMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_download_image_reuses_stored_blobs() {
+        let (first, mut blobs) = test_image(b"peeko", "linux");
+        let (second, second_blobs) = test_image(b"peeko", "freebsd");
+        blobs.extend(second_blobs);
+        let layer_digest = serde_json::from_slice::<ImageManifest>(&first)
+            .unwrap()
+            .layers[0]
+            .digest
+            .clone();
+        let registry = serve_images(
+            HashMap::from([
+                ("/v2/test/app/manifests/v1".to_string(), first),
+                ("/v2/test/app/manifests/v2".to_string(), second),
+            ]),
+            blobs,
+        )
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(registry.url());
+        client.set_downloads_dir(dir.path());
+        let mut image_dirs = Vec::new();
+        for tag in ["v1", "v2"] {
+            let platform = PlatformParam {
+                architecture: None,
+                os: None,
+                variant: None,
+            };
+            image_dirs.push(
+                client
+                    .download_image(&image_ref(&format!("test/app:{tag}")), Some(&platform))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let layer_path = format!("/v2/test/app/blobs/{layer_digest}");
+        let layer_requests = registry
+            .requests()
+            .iter()
+            .filter(|req| req.path == layer_path)
+            .count();
+        assert_eq!(layer_requests, 1);
+
+        let store = BlobStore::in_dir(dir.path());
+        assert!(store.blob_path(&layer_digest).is_file());
+        for image_dir in image_dirs {
+            assert_eq!(
+                std::fs::read(oci_blob_path(&image_dir, &layer_digest)).unwrap(),
+                std::fs::read(store.blob_path(&layer_digest)).unwrap()
+            );
+        }
+        assert_eq!(crate::fs::collect_images(dir.path()).unwrap().len(), 2);
+    }
+
+    /// Serves `test/app:v1` as an index of linux/amd64 and linux/arm64/v8
+    /// images, preceded by an attestation manifest.
+    async fn serve_platform_index() -> MockRegistry {
+        let (attestation, mut blobs) = test_image(b"attestation", "unknown");
+        let (amd64, amd64_blobs) = test_image(b"amd64", "linux");
+        let (arm64, arm64_blobs) = test_image(b"arm64", "linux");
+        blobs.extend(amd64_blobs);
+        blobs.extend(arm64_blobs);
+        let entry = |manifest: &[u8], platform: &str| {
+            format!(
+                r#"{{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"{}","size":{},"platform":{platform}}}"#,
+                sha256_digest(manifest),
+                manifest.len()
+            )
+        };
+        let index = format!(
+            r#"{{"schemaVersion":2,"mediaType":"application/vnd.oci.image.index.v1+json","manifests":[{},{},{}]}}"#,
+            entry(&attestation, r#"{"architecture":"unknown","os":"unknown"}"#),
+            entry(&amd64, r#"{"architecture":"amd64","os":"linux"}"#),
+            entry(
+                &arm64,
+                r#"{"architecture":"arm64","os":"linux","variant":"v8"}"#
+            ),
+        );
+        let mut manifests =
+            HashMap::from([("/v2/test/app/manifests/v1".to_string(), index.into_bytes())]);
+        for manifest in [attestation, amd64, arm64] {
+            manifests.insert(
+                format!("/v2/test/app/manifests/{}", sha256_digest(&manifest)),
+                manifest,
+            );
+        }
+        serve_images(manifests, blobs).await
+    }
+
+    #[tokio::test]
+    async fn test_download_image_selects_platform() {
+        let registry = serve_platform_index().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(registry.url());
+        client.set_downloads_dir(dir.path());
+        let any = client
+            .download_image(&image_ref("test/app:v1"), Some(&PlatformParam::default()))
+            .await
+            .unwrap();
+        assert_eq!(any, dir.path().join("test/app/v1/linux-amd64"));
+        let arm = client
+            .download_image(
+                &image_ref("test/app:v1"),
+                Some(&"linux/arm64/v8".parse().unwrap()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(arm, dir.path().join("test/app/v1/linux-arm64-v8"));
+        assert!(matches!(
+            client
+                .download_image(
+                    &image_ref("test/app:v1"),
+                    Some(&"linux/s390x".parse().unwrap()),
+                )
+                .await,
+            Err(RegistryError::PlatformNotFound(platform)) if platform == "linux/s390x"
+        ));
+        let attestation = format!("/v2/test/app/manifests/{}", sha256_digest(b"attestation"));
+        assert!(
+            registry
+                .requests()
+                .iter()
+                .all(|req| req.path != attestation)
+        );
+
+        assert_eq!(
+            crate::fs::collect_images(dir.path()).unwrap(),
+            vec!["test/app:v1".to_string()]
+        );
+        let found = crate::fs::find_image(
+            dir.path(),
+            "test/app",
+            "v1",
+            Some(&"linux/arm64".parse().unwrap()),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(found.path, arm);
+        assert_eq!(found.to_string(), "test/app:v1 (linux/arm64/v8)");
+        let reader = crate::reader::build_image_reader(&found.path)
+            .await
+            .unwrap();
+        assert_eq!(reader.read_file("etc/hostname").await.unwrap(), b"arm64");
+    }
+
+    #[tokio::test]
+    async fn test_download_all_platforms() {
+        let registry = serve_platform_index().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(registry.url());
+        client.set_downloads_dir(dir.path());
+        let image_dir = client
+            .download_all_platforms(&image_ref("test/app:v1"))
+            .await
+            .unwrap();
+        assert_eq!(image_dir, dir.path().join("test/app/v1/all"));
+
+        let index: ManifestList =
+            serde_json::from_slice(&std::fs::read(image_dir.join(INDEX_FILE)).unwrap()).unwrap();
+        assert_eq!(
+            index.manifests[0].media_type,
+            "application/vnd.oci.image.index.v1+json"
+        );
+        assert!(oci_blob_path(&image_dir, &index.manifests[0].digest).is_file());
+        let stored: Vec<String> = crate::fs::collect_stored_images(dir.path())
+            .unwrap()
+            .iter()
+            .map(|image| image.to_string())
+            .collect();
+        assert_eq!(
+            stored,
+            vec!["test/app:v1 (linux/amd64)", "test/app:v1 (linux/arm64/v8)"]
+        );
+
+        let read_hostname = |platform: Option<&str>| {
+            let image_dir = image_dir.clone();
+            let platform: PlatformParam = platform.map(|p| p.parse().unwrap()).unwrap_or_default();
+            async move {
+                crate::reader::build_platform_image_reader(&image_dir, &platform)
+                    .await?
+                    .read_file("etc/hostname")
+                    .await
+            }
+        };
+        assert_eq!(read_hostname(None).await.unwrap(), b"amd64");
+        assert_eq!(read_hostname(Some("linux/arm64")).await.unwrap(), b"arm64");
+        assert!(matches!(
+            read_hostname(Some("linux/s390x")).await,
+            Err(crate::reader::ImageReaderError::PlatformNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_platform_of_single_platform_image() {
+        let (manifest, blobs) = test_image(b"amd64", "linux");
+        let manifests = HashMap::from([("/v2/test/app/manifests/v1".to_string(), manifest)]);
+        let registry = serve_images(manifests, blobs).await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(registry.url());
+        client.set_downloads_dir(dir.path());
+        let image = image_ref("test/app:v1");
+
+        assert!(matches!(
+            client
+                .download_image(&image, Some(&"linux/arm64".parse().unwrap()))
+                .await,
+            Err(RegistryError::PlatformNotFound(platform)) if platform == "linux/arm64"
+        ));
+        assert!(dir.path().read_dir().unwrap().next().is_none());
+        let inspected = client
+            .inspect_image(&image, Some(&"linux/amd64".parse().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(inspected.platform.to_string(), "linux/amd64");
+        // without a platform the image is taken as it is
+        let image_dir = client.download_image(&image, None).await.unwrap();
+        assert_eq!(image_dir, dir.path().join("test/app/v1/linux-amd64"));
+    }
+
+    #[tokio::test]
+    async fn test_inspect_image() {
+        let registry = serve_platform_index().await;
+        let client = RegistryClient::new(registry.url());
+        let image = client
+            .inspect_image(
+                &image_ref("test/app:v1"),
+                Some(&"linux/arm64".parse().unwrap()),
+            )
+            .await
+            .unwrap();
+
+        let platforms: Vec<String> = image.platforms.iter().map(|p| p.to_string()).collect();
+        assert_eq!(platforms, vec!["linux/amd64", "linux/arm64/v8"]);
+        assert_eq!(image.platform.to_string(), "linux/arm64/v8");
+        assert_eq!(image.config.os, "linux");
+        assert_eq!(image.manifest.layers.len(), 1);
+        assert_eq!(image.manifest.layers_size(), image.manifest.layers[0].size);
+
+        let blob_requests: Vec<String> = registry
+            .requests()
+            .into_iter()
+            .filter_map(|req| req.path.split_once("/blobs/").map(|(_, d)| d.to_string()))
+            .collect();
+        assert_eq!(blob_requests, vec![image.manifest.config.digest.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_download_schema1_image() {
+        let (_, blobs) = test_image(b"legacy", "linux");
+        let (layer_digest, layer) = blobs
+            .iter()
+            .find(|(_, blob)| !blob.starts_with(b"{"))
+            .map(|(digest, blob)| (digest.clone(), blob.clone()))
+            .unwrap();
+        let layer = fixtures::gzip(&layer);
+        let layer_digest_gz = sha256_digest(&layer);
+        assert_ne!(layer_digest, layer_digest_gz);
+
+        let history = [
+            serde_json::json!({
+                "id": "top", "parent": "base", "throwaway": true,
+                "architecture": "amd64", "os": "linux", "config": {"Cmd": ["sh"]},
+                "container_config": {"Cmd": ["/bin/sh", "-c", "#(nop) CMD [\"sh\"]"]},
+            }),
+            serde_json::json!({
+                "id": "base",
+                "container_config": {"Cmd": ["/bin/sh", "-c", "#(nop) ADD file:etc"]},
+            }),
+        ];
+        let manifest = serde_json::json!({
+            "schemaVersion": 1, "name": "test/app", "tag": "v1", "architecture": "amd64",
+            "fsLayers": [{"blobSum": layer_digest_gz}, {"blobSum": layer_digest_gz}],
+            "history": history.map(|h| serde_json::json!({"v1Compatibility": h.to_string()})),
+            "signatures": [],
+        });
+        let manifest = manifest.to_string();
+        let manifest_digest = sha256_digest(manifest.as_bytes());
+        let registry = MockRegistry::start(move |req| {
+            if req.path.starts_with("/v2/test/app/manifests/") {
+                return MockResponse::new(200)
+                    .header(
+                        "Content-Type",
+                        "application/vnd.docker.distribution.manifest.v1+prettyjws",
+                    )
+                    .body(manifest.clone());
+            }
+            match req.path.strip_prefix("/v2/test/app/blobs/") {
+                Some(digest) if digest == layer_digest_gz => {
+                    MockResponse::new(200).body(layer.clone())
+                }
+                _ => MockResponse::new(404),
+            }
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(registry.url());
+        client.set_downloads_dir(dir.path());
+        let image_dir = client
+            .download_image(&image_ref("test/app:v1"), None)
+            .await
+            .unwrap();
+
+        let requests = registry.requests();
+        let accept = &requests[0].headers["accept"];
+        for media_type in INDEX_MEDIA_TYPES
+            .iter()
+            .chain(IMAGE_MANIFEST_MEDIA_TYPES)
+            .chain(SCHEMA1_MEDIA_TYPES)
+        {
+            assert!(accept.contains(media_type), "{accept}");
+        }
+        assert!(requests.iter().any(|req| req.method == "HEAD"));
+        // the config is embedded in the converted manifest
+        assert_eq!(requests.iter().filter(|req| req.method == "GET").count(), 2);
+
+        assert_eq!(image_dir, dir.path().join("test/app/v1/linux-amd64"));
+        let reader = crate::reader::build_image_reader(&image_dir).await.unwrap();
+        assert_eq!(reader.read_file("etc/hostname").await.unwrap(), b"legacy");
+
+        // pulling by digest verifies the manifest as it does for other schemas
+        client
+            .download_image(&image_ref(&format!("test/app@{manifest_digest}")), None)
+            .await
+            .unwrap();
+        let other_digest = sha256_digest(b"another manifest");
+        let err = client
+            .download_image(&image_ref(&format!("test/app@{other_digest}")), None)
+            .await;
+        assert!(matches!(err, Err(RegistryError::ManifestMismatch { .. })));
+    }
 
     #[tokio::test]
     async fn test_get_image_manifest() {
         let mut client = RegistryClient::new("https://registry-1.docker.io");
         let image_manifest = client
-            .get_image_manifest("library/hello-world", "latest")
+            .get_image_manifest(&image_ref("library/hello-world:latest"))
             .await
             .unwrap();
         println!("Image manifest: {:?}", image_manifest);
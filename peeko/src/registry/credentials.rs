@@ -0,0 +1,514 @@
+//! Lookup of registry credentials stored by Docker, Podman and credential helpers.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Key Docker uses for Docker Hub in `config.json`.
+const DOCKER_HUB_KEY: &str = "https://index.docker.io/v1/";
+/// Hostnames that all refer to Docker Hub.
+const DOCKER_HUB_ALIASES: &[&str] = &[
+    "docker.io",
+    "index.docker.io",
+    "registry-1.docker.io",
+    "registry.hub.docker.com",
+];
+
+/// Failures raised while reading credential files or running helpers.
+#[derive(Error, Debug)]
+pub enum CredentialsError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Failed to parse {path}: {source}")]
+    ParseError {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("Invalid auth entry for {0}")]
+    InvalidAuth(String),
+
+    #[error("Credential helper {helper} failed: {message}")]
+    HelperFailed { helper: String, message: String },
+}
+
+/// Convenient result alias that uses [`CredentialsError`].
+pub type Result<T> = std::result::Result<T, CredentialsError>;
+
+/// Username and password (or access token) for a registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ConfigFile {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(
+        rename = "credHelpers",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    cred_helpers: HashMap<String, String>,
+    #[serde(rename = "credsStore", skip_serializing_if = "Option::is_none")]
+    creds_store: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AuthEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identitytoken: Option<String>,
+}
+
+impl AuthEntry {
+    /// Returns whether the entry holds credentials. Docker Desktop writes
+    /// empty entries next to its `credsStore`, which has the actual ones.
+    fn has_credentials(&self) -> bool {
+        let present = |value: &Option<String>| value.as_deref().is_some_and(|v| !v.is_empty());
+        present(&self.auth)
+            || (self.username.is_some() && self.password.is_some())
+            || present(&self.identitytoken)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HelperResponse {
+    #[serde(rename = "Username")]
+    username: String,
+    #[serde(rename = "Secret")]
+    secret: String,
+}
+
+/// Resolves credentials for a registry from Docker-compatible config files.
+///
+/// Files are consulted in order and the first one that knows the registry
+/// wins. Within a file `credHelpers` takes precedence over `auths`, which
+/// takes precedence over the default `credsStore`.
+#[derive(Debug, Clone)]
+pub struct CredentialResolver {
+    config_files: Vec<PathBuf>,
+}
+
+impl CredentialResolver {
+    /// Creates a resolver reading the standard Docker and Podman locations:
+    /// `$DOCKER_CONFIG/config.json` (or `~/.docker/config.json`),
+    /// `$REGISTRY_AUTH_FILE`, `$XDG_RUNTIME_DIR/containers/auth.json` and
+    /// `~/.config/containers/auth.json`.
+    pub fn from_env() -> Self {
+        let home = home_dir();
+        let mut config_files = Vec::new();
+
+        match env::var_os("DOCKER_CONFIG") {
+            Some(dir) => config_files.push(PathBuf::from(dir).join("config.json")),
+            None => {
+                if let Some(home) = &home {
+                    config_files.push(home.join(".docker/config.json"));
+                }
+            }
+        }
+        if let Some(file) = env::var_os("REGISTRY_AUTH_FILE") {
+            config_files.push(file.into());
+        }
+        if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+            config_files.push(PathBuf::from(dir).join("containers/auth.json"));
+        }
+        if let Some(home) = &home {
+            config_files.push(home.join(".config/containers/auth.json"));
+        }
+
+        Self { config_files }
+    }
+
+    /// Creates a resolver that only reads the given files.
+    pub fn with_files<I, P>(files: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        Self {
+            config_files: files.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Adds a file that is consulted before all others, such as the file
+    /// backing a [`CredentialStore`].
+    pub fn prepend_file<P: Into<PathBuf>>(&mut self, file: P) {
+        self.config_files.insert(0, file.into());
+    }
+
+    /// Returns the credentials for `registry`, which may be a hostname
+    /// (`ghcr.io`) or a URL (`https://registry-1.docker.io`).
+    ///
+    /// Missing config files are skipped; `Ok(None)` means no file or helper
+    /// has credentials for the registry.
+    pub fn resolve(&self, registry: &str) -> Result<Option<Credentials>> {
+        let host = normalize_registry(registry);
+        for path in &self.config_files {
+            let Some(config) = read_config(path)? else {
+                continue;
+            };
+            if let Some(credentials) = resolve_in_config(&config, &host)? {
+                return Ok(Some(credentials));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Default for CredentialResolver {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+/// Credentials file owned by peeko, written in the Docker `config.json`
+/// format so that [`CredentialResolver`] can read it.
+///
+/// On Unix the file is only readable and writable by its owner.
+#[derive(Debug, Clone)]
+pub struct CredentialStore {
+    path: PathBuf,
+}
+
+impl CredentialStore {
+    /// Creates a store backed by the file at `path`, which need not exist yet.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Returns the path of the backing file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saves `credentials` for `registry`, replacing existing ones.
+    pub fn store(&self, registry: &str, credentials: &Credentials) -> Result<()> {
+        let mut config = read_config(&self.path)?.unwrap_or_default();
+        let host = normalize_registry(registry);
+        config
+            .auths
+            .retain(|key, _| normalize_registry(key) != host);
+        let auth = STANDARD.encode(format!("{}:{}", credentials.username, credentials.password));
+        config.auths.insert(
+            host,
+            AuthEntry {
+                auth: Some(auth),
+                username: None,
+                password: None,
+                identitytoken: None,
+            },
+        );
+        self.write(&config)
+    }
+
+    /// Removes the credentials for `registry`, returning whether any existed.
+    pub fn remove(&self, registry: &str) -> Result<bool> {
+        let Some(mut config) = read_config(&self.path)? else {
+            return Ok(false);
+        };
+        let host = normalize_registry(registry);
+        let before = config.auths.len();
+        config
+            .auths
+            .retain(|key, _| normalize_registry(key) != host);
+        if config.auths.len() == before {
+            return Ok(false);
+        }
+        self.write(&config)?;
+        Ok(true)
+    }
+
+    /// Writes the file through a temporary sibling so a crash never leaves
+    /// it truncated.
+    fn write(&self, config: &ConfigFile) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json =
+            serde_json::to_vec_pretty(config).map_err(|source| CredentialsError::ParseError {
+                path: self.path.clone(),
+                source,
+            })?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp_path)?;
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+fn read_config(path: &Path) -> Result<Option<ConfigFile>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|source| CredentialsError::ParseError {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+fn resolve_in_config(config: &ConfigFile, host: &str) -> Result<Option<Credentials>> {
+    if let Some(helper) = config
+        .cred_helpers
+        .iter()
+        .find(|(key, _)| normalize_registry(key) == host)
+        .map(|(_, helper)| helper)
+    {
+        return run_helper(helper, host);
+    }
+
+    if let Some((key, entry)) = config
+        .auths
+        .iter()
+        .find(|(key, entry)| normalize_registry(key) == host && entry.has_credentials())
+    {
+        return decode_auth_entry(key, entry);
+    }
+
+    match &config.creds_store {
+        Some(helper) => run_helper(helper, host),
+        None => Ok(None),
+    }
+}
+
+fn decode_auth_entry(key: &str, entry: &AuthEntry) -> Result<Option<Credentials>> {
+    if let (Some(username), Some(password)) = (&entry.username, &entry.password) {
+        return Ok(Some(Credentials {
+            username: username.clone(),
+            password: password.clone(),
+        }));
+    }
+    let Some(auth) = entry.auth.as_deref().filter(|auth| !auth.is_empty()) else {
+        // Entries holding only an `identitytoken` need an OAuth2 flow.
+        return Ok(None);
+    };
+
+    let decoded = STANDARD
+        .decode(auth.trim())
+        .map_err(|_| CredentialsError::InvalidAuth(key.to_string()))?;
+    let decoded =
+        String::from_utf8(decoded).map_err(|_| CredentialsError::InvalidAuth(key.to_string()))?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or_else(|| CredentialsError::InvalidAuth(key.to_string()))?;
+    Ok(Some(Credentials {
+        username: username.to_string(),
+        password: password.to_string(),
+    }))
+}
+
+/// Runs `docker-credential-<helper> get` for `host`.
+fn run_helper(helper: &str, host: &str) -> Result<Option<Credentials>> {
+    let program = format!("docker-credential-{helper}");
+    let server_url = if host == "docker.io" {
+        DOCKER_HUB_KEY
+    } else {
+        host
+    };
+    let helper_error = |message: String| CredentialsError::HelperFailed {
+        helper: program.clone(),
+        message,
+    };
+
+    let mut child = Command::new(&program)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| helper_error(err.to_string()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(server_url.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        // Helpers report unknown servers through a non-zero exit status.
+        if message.contains("credentials not found") {
+            return Ok(None);
+        }
+        return Err(helper_error(message));
+    }
+
+    let response: HelperResponse =
+        serde_json::from_slice(&output.stdout).map_err(|err| helper_error(err.to_string()))?;
+    if response.username == "<token>" {
+        // Identity tokens need an OAuth2 flow which is not supported.
+        return Ok(None);
+    }
+    Ok(Some(Credentials {
+        username: response.username,
+        password: response.secret,
+    }))
+}
+
+/// Reduces a registry URL or config key to a bare host (with port), mapping
+/// every Docker Hub alias to `docker.io`.
+pub fn normalize_registry(registry: &str) -> String {
+    let without_scheme = registry
+        .strip_prefix("https://")
+        .or_else(|| registry.strip_prefix("http://"))
+        .unwrap_or(registry);
+    let host = without_scheme
+        .split('/')
+        .next()
+        .unwrap_or(without_scheme)
+        .to_lowercase();
+    if DOCKER_HUB_ALIASES.contains(&host.as_str()) {
+        "docker.io".to_string()
+    } else {
+        host
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_registry() {
+        assert_eq!(
+            normalize_registry("https://index.docker.io/v1/"),
+            "docker.io"
+        );
+        assert_eq!(
+            normalize_registry("https://registry-1.docker.io"),
+            "docker.io"
+        );
+        assert_eq!(normalize_registry("ghcr.io"), "ghcr.io");
+        assert_eq!(
+            normalize_registry("http://localhost:5000"),
+            "localhost:5000"
+        );
+        assert_eq!(normalize_registry("quay.io/team"), "quay.io");
+    }
+
+    #[test]
+    fn test_resolve_from_auths() {
+        let dir = tempfile::tempdir().unwrap();
+        let docker = dir.path().join("config.json");
+        let podman = dir.path().join("auth.json");
+        let auth = STANDARD.encode("hub-user:hub:secret");
+        fs::write(
+            &docker,
+            format!(
+                r#"{{"auths":{{"https://index.docker.io/v1/":{{"auth":"{auth}"}},"ghcr.io":{{}}}}}}"#
+            ),
+        )
+        .unwrap();
+        let auth = STANDARD.encode("quay-user:quay-secret");
+        let ghcr_auth = STANDARD.encode("ghcr-user:ghcr-secret");
+        fs::write(
+            &podman,
+            format!(
+                r#"{{"auths":{{"quay.io":{{"auth":"{auth}"}},"ghcr.io":{{"auth":"{ghcr_auth}"}},"gcr.io":{{}}}}}}"#
+            ),
+        )
+        .unwrap();
+
+        let resolver =
+            CredentialResolver::with_files([dir.path().join("missing.json"), docker, podman]);
+        assert_eq!(
+            resolver.resolve("https://registry-1.docker.io").unwrap(),
+            Some(Credentials {
+                username: "hub-user".to_string(),
+                password: "hub:secret".to_string(),
+            })
+        );
+        assert_eq!(
+            resolver.resolve("quay.io").unwrap().unwrap().username,
+            "quay-user"
+        );
+        // empty entries do not hide the credentials of later files
+        assert_eq!(
+            resolver.resolve("ghcr.io").unwrap().unwrap().username,
+            "ghcr-user"
+        );
+        assert_eq!(resolver.resolve("gcr.io").unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty_entry_falls_back_to_creds_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let docker = dir.path().join("config.json");
+        fs::write(
+            &docker,
+            r#"{"auths":{"https://index.docker.io/v1/":{}},"credsStore":"peeko-test-missing"}"#,
+        )
+        .unwrap();
+
+        // the store is asked, and fails since its helper is not installed
+        let resolver = CredentialResolver::with_files([docker]);
+        match resolver.resolve("docker.io") {
+            Err(CredentialsError::HelperFailed { helper, .. }) => {
+                assert_eq!(helper, "docker-credential-peeko-test-missing")
+            }
+            other => panic!("unexpected resolution {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_credential_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = CredentialStore::new(dir.path().join("peeko/auth.json"));
+        let credentials = Credentials {
+            username: "me".to_string(),
+            password: "s3cret".to_string(),
+        };
+        store
+            .store("https://registry-1.docker.io", &credentials)
+            .unwrap();
+        store.store("ghcr.io", &credentials).unwrap();
+
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(store.path()).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        let resolver = CredentialResolver::with_files([store.path()]);
+        assert_eq!(resolver.resolve("docker.io").unwrap(), Some(credentials));
+
+        assert!(store.remove("index.docker.io").unwrap());
+        assert!(!store.remove("docker.io").unwrap());
+        assert_eq!(resolver.resolve("docker.io").unwrap(), None);
+        assert!(resolver.resolve("ghcr.io").unwrap().is_some());
+    }
+}
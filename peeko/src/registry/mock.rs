@@ -0,0 +1,164 @@
+//! Minimal HTTP/1.1 server used by tests to stand in for an OCI registry.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Request line and headers received by the mock registry.
+#[derive(Debug, Clone)]
+pub(crate) struct MockRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lower-cased.
+    pub headers: HashMap<String, String>,
+}
+
+/// Canned response returned by a mock handler.
+pub(crate) struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Serves `content`, or the part of it asked for by a `Range: bytes=a-b`
+    /// header of `req`.
+    pub fn ranged(req: &MockRequest, content: &[u8]) -> Self {
+        let range = req
+            .headers
+            .get("range")
+            .and_then(|range| range.strip_prefix("bytes="))
+            .and_then(|range| range.split_once('-'))
+            .and_then(|(start, end)| {
+                Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+            });
+        match range {
+            Some((start, end)) if start <= end && end < content.len() => Self::new(206)
+                .header(
+                    "Content-Range",
+                    &format!("bytes {start}-{end}/{}", content.len()),
+                )
+                .body(&content[start..=end]),
+            Some(_) => Self::new(416),
+            None => Self::new(200).body(content),
+        }
+    }
+}
+
+type Handler = dyn Fn(&MockRequest) -> MockResponse + Send + Sync;
+
+/// Local registry stand-in that answers every request through a handler closure.
+pub(crate) struct MockRegistry {
+    url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockRegistry {
+    /// Binds to an ephemeral port and starts serving in the background.
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+                tokio::spawn(async move {
+                    let Some(request) = read_request(&mut socket).await else {
+                        return;
+                    };
+                    recorded.lock().unwrap().push(request.clone());
+                    let response = handler(&request);
+                    let _ = write_response(&mut socket, &request, response).await;
+                });
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    /// Base URL of the server, e.g. `http://127.0.0.1:1234`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns every request received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request(socket: &mut tokio::net::TcpStream) -> Option<MockRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = socket.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let text = String::from_utf8_lossy(&buf);
+    let mut lines = text.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    Some(MockRequest {
+        method,
+        path,
+        headers,
+    })
+}
+
+async fn write_response(
+    socket: &mut tokio::net::TcpStream,
+    request: &MockRequest,
+    response: MockResponse,
+) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    socket.write_all(head.as_bytes()).await?;
+    if request.method != "HEAD" {
+        socket.write_all(&response.body).await?;
+    }
+    socket.shutdown().await
+}
@@ -1,7 +1,18 @@
 //! OCI registry client capable of fetching manifests and downloading layers.
 
+pub mod auth;
 pub mod client;
+pub mod credentials;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod progress;
+pub mod retry;
 
+/// Re-export of the platform filter accepted by [`RegistryClient`].
+pub use crate::manifest::PlatformParam;
 /// Re-export of the high level registry client.
-pub use client::{PlatformParam, RegistryClient, RegistryError};
+pub use client::{RegistryClient, RegistryError, RemoteImage};
+/// Re-export of the credential lookup helpers.
+pub use credentials::{CredentialResolver, CredentialStore, Credentials};
+/// Re-export of the retry policy used by [`RegistryClient`].
+pub use retry::RetryPolicy;
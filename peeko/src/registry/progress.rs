@@ -1,5 +1,7 @@
 //! Progress reporting abstraction used when downloading blobs from the registry.
 
+use std::time::Duration;
+
 #[cfg(feature = "progress")]
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
@@ -8,6 +10,10 @@ pub trait ProgressTracker: Send + Sync {
     fn start_download(&self, digest: &str, total_bytes: u64);
     fn update(&self, digest: &str, bytes: u64);
     fn finish(&self, digest: &str);
+
+    /// Called before a failed request is retried. `label` is the blob digest
+    /// or the request URL, `attempt` the number of attempts made so far.
+    fn retry(&self, _label: &str, _attempt: u32, _delay: Duration, _reason: &str) {}
 }
 
 /// No-op progress tracker used when no reporting is required.
@@ -54,12 +60,7 @@ impl ProgressTracker for IndicatifProgress {
         {
             pb.set_style(style.progress_chars("#>-"));
         }
-        let digest_display = if let Some(pos) = digest.find(":") {
-            &digest[pos + 1..(pos + 12).min(digest.len())]
-        } else {
-            &digest[..12.min(digest.len())]
-        };
-        pb.set_message(format!("{digest_display}.."));
+        pb.set_message(format!("{}..", short_digest(digest)));
         self.bars.lock().unwrap().insert(digest.to_string(), pb);
     }
 
@@ -74,4 +75,25 @@ impl ProgressTracker for IndicatifProgress {
             pb.finish_with_message("Done");
         }
     }
+
+    fn retry(&self, label: &str, attempt: u32, delay: Duration, reason: &str) {
+        let label = if label.starts_with("sha") {
+            format!("{}..", short_digest(label))
+        } else {
+            label.to_string()
+        };
+        let _ = self.multi.println(format!(
+            "Retrying {label} in {:.1}s after attempt {attempt} failed: {reason}",
+            delay.as_secs_f64()
+        ));
+    }
+}
+
+#[cfg(feature = "progress")]
+fn short_digest(digest: &str) -> &str {
+    if let Some(pos) = digest.find(":") {
+        &digest[pos + 1..(pos + 12).min(digest.len())]
+    } else {
+        &digest[..12.min(digest.len())]
+    }
 }
@@ -0,0 +1,173 @@
+//! Retry policy applied to registry HTTP requests.
+
+use std::time::{Duration, SystemTime};
+
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+/// Controls how requests that fail with transient errors are retried.
+///
+/// Connection failures, timeouts, `408`, `429` and `5xx` responses (other than
+/// `501`) are considered transient. The delay before attempt `n + 1` is
+/// `initial_backoff * 2^(n - 1)`, capped at `max_backoff` and randomised by
+/// `jitter`. A `Retry-After` header on the failed response takes precedence
+/// when it is not longer than `max_retry_after`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one. `1` disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the exponential backoff.
+    pub max_backoff: Duration,
+    /// Fraction (`0.0..=1.0`) of the backoff that is randomly shaved off.
+    pub jitter: f64,
+    /// Longest `Retry-After` the client is willing to wait before giving up.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.2,
+            max_retry_after: Duration::from_secs(120),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the backoff to wait after `attempt` (1-based) failed.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter > 0.0 {
+            delay.mul_f64(1.0 - jitter * fastrand::f64())
+        } else {
+            delay
+        }
+    }
+
+    /// Returns how long to wait before retrying a response with `status`, or
+    /// `None` when the response should be returned to the caller as is.
+    pub(crate) fn delay_for_response(
+        &self,
+        attempt: u32,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || !is_retryable_status(status) {
+            return None;
+        }
+        match retry_after(headers) {
+            Some(delay) if delay > self.max_retry_after => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    /// Returns how long to wait before retrying after a transport error.
+    pub(crate) fn delay_for_error(&self, attempt: u32, err: &reqwest::Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !is_transient_error(err) {
+            return None;
+        }
+        Some(self.backoff(attempt))
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || (status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED)
+}
+
+pub(crate) fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
+}
+
+/// Parses a `Retry-After` header given either as seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(40), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_delay_for_response() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            max_retry_after: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            policy.delay_for_response(1, StatusCode::SERVICE_UNAVAILABLE, &headers),
+            Some(policy.initial_backoff)
+        );
+        assert_eq!(
+            policy.delay_for_response(1, StatusCode::NOT_FOUND, &headers),
+            None
+        );
+        assert_eq!(
+            policy.delay_for_response(4, StatusCode::SERVICE_UNAVAILABLE, &headers),
+            None
+        );
+
+        headers.insert(RETRY_AFTER, "3".parse().unwrap());
+        assert_eq!(
+            policy.delay_for_response(1, StatusCode::TOO_MANY_REQUESTS, &headers),
+            Some(Duration::from_secs(3))
+        );
+        headers.insert(RETRY_AFTER, "3600".parse().unwrap());
+        assert_eq!(
+            policy.delay_for_response(1, StatusCode::TOO_MANY_REQUESTS, &headers),
+            None
+        );
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            policy.delay_for_response(1, StatusCode::TOO_MANY_REQUESTS, &headers),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -2,13 +2,21 @@
 
 use crate::reader::vfs::{FileEntry, VirtualFileSystem};
 
-/// Prints aggregate counts of files, directories, symlinks and total size.
+/// Prints aggregate counts of files, directories, links, special files and
+/// total size.
+///
+/// Hard links share the content of their target, so their size is only
+/// counted once.
 pub fn show_statistics(vfs: &VirtualFileSystem) {
     let entries = vfs.get_entries();
 
     let mut file_count = 0;
     let mut dir_count = 0;
     let mut symlink_count = 0;
+    let mut hardlink_count = 0;
+    let mut device_count = 0;
+    let mut fifo_count = 0;
+    let mut sparse_count = 0;
     let mut total_size = 0u64;
 
     for entry in entries.values() {
@@ -19,6 +27,13 @@ pub fn show_statistics(vfs: &VirtualFileSystem) {
             }
             FileEntry::Directory { .. } => dir_count += 1,
             FileEntry::Symlink { .. } => symlink_count += 1,
+            FileEntry::Hardlink { .. } => hardlink_count += 1,
+            FileEntry::CharDevice { .. } | FileEntry::BlockDevice { .. } => device_count += 1,
+            FileEntry::Fifo { .. } => fifo_count += 1,
+            FileEntry::Sparse { size, .. } => {
+                sparse_count += 1;
+                total_size += size;
+            }
         }
     }
 
@@ -26,6 +41,10 @@ pub fn show_statistics(vfs: &VirtualFileSystem) {
     println!("Total directories: {dir_count}");
     println!("Total files: {file_count}");
     println!("Total symlinks: {symlink_count}");
+    println!("Total hard links: {hardlink_count}");
+    println!("Total device nodes: {device_count}");
+    println!("Total FIFOs: {fifo_count}");
+    println!("Total sparse files: {sparse_count}");
     println!(
         "Total size: {:.2} MB",
         total_size as f64 / (1024.0 * 1024.0)
@@ -54,6 +73,17 @@ pub fn list_top_level(vfs: &VirtualFileSystem) {
                 FileEntry::Symlink { target, .. } => {
                     println!("  /{} -> {}", path.display(), target)
                 }
+                FileEntry::Hardlink { target, .. } => {
+                    println!("  /{} => /{}", path.display(), target)
+                }
+                FileEntry::Sparse { size, .. } => {
+                    println!("  /{} ({} bytes, sparse)", path.display(), size)
+                }
+                special => println!(
+                    "  /{} ({})",
+                    path.display(),
+                    special.special_kind().unwrap_or_default()
+                ),
             }
         }
     }